@@ -38,6 +38,14 @@ type Vec4 = [f32, ..4];
 type IVec4 = [u32, ..4];
 type Mat4 = [Vec4, ..4];
 
+fn to_vec4(s: &[f32]) -> Vec4 {
+    [s[0], s[1], s[2], s[3]]
+}
+
+fn to_ivec4(s: &[u32]) -> IVec4 {
+    [s[0], s[1], s[2], s[3]]
+}
+
 struct TextureStore {
     textures: HashMap<String, gfx::TextureHandle>,
 }
@@ -95,49 +103,6 @@ impl TextureStore {
     }
 }
 
-struct BoneMap {
-    /// Translates a bone name into a bone id
-    pub bone_map: HashMap<String, u32>,
-    pub offsets: Vec<ai::Matrix4x4>,
-    pub transforms: Vec<Mat4>,
-}
-
-impl BoneMap {
-    fn new(scene: &ai::Scene) -> BoneMap {
-        let mut bone_map = HashMap::new();
-        let mut offsets = Vec::new();
-        let mut num_bones = 0u32;
-
-        for mesh in scene.get_meshes().iter() {
-            for bone in mesh.get_bones().iter() {
-                let name = bone.name.to_string();
-                match bone_map.get(&name) {
-                    Some(_) => continue,
-                    None => {
-                        bone_map.insert(name, num_bones);
-                        offsets.push(bone.offset_matrix);
-                        num_bones += 1;
-                    }
-                }
-            }
-        }
-
-        BoneMap {
-            bone_map: bone_map,
-            offsets: offsets,
-            transforms: Vec::from_elem(MAX_BONES, vecmath::mat4_id()),
-        }
-    }
-
-    #[inline(always)]
-    fn get_id(&self, name: &String) -> Option<u32> {
-        match self.bone_map.get(name) {
-            None => None,
-            Some(val) => Some(*val),
-        }
-    }
-}
-
 struct ModelComponent {
     pub batch: ModelBatch,
     pub shader_data: ShaderParam,
@@ -148,16 +113,10 @@ struct Model<'a> {
     pub indices: Vec<u32>,
     pub batches: Vec<ModelComponent>,
     pub scene: ai::Scene<'a>,
-    pub bone_map: RefCell<BoneMap>,
-    pub global_inverse: ai::Matrix4x4,
+    pub skeleton: ai::animation::Skeleton,
     pub bone_transform_buffer: gfx::BufferHandle<Mat4>,
 }
 
-#[inline(always)]
-fn lerp<S, T: Add<T,T> + Sub<T,T> + Mul<S,T>>(start: T, end: T, s: S) -> T {
-    return start + (end - start) * s;
-}
-
 impl<'a> Model<'a> {
     fn from_file(ai_scene: ai::Scene<'a>,
                  graphics: &mut gfx::Graphics<gfx::GlDevice, gfx::GlCommandBuffer>,
@@ -177,13 +136,11 @@ impl<'a> Model<'a> {
         // prepare the data structures used to store the scene
         let mut vertices = Vec::with_capacity(num_vertices as uint);
         let mut indices = Vec::with_capacity(num_indices as uint);
-        // The bone weights and ids. Each vertex may be influenced by upto
-        // 4 bones
-        let mut bone_weights: Vec<Vec4> = Vec::from_elem(num_vertices as uint,
-                                                               [0.0, ..4]);
-        let mut bone_ids: Vec<IVec4> = Vec::from_elem(num_vertices as uint,
-                                                           [0, ..4]);
-        let bone_map = BoneMap::new(&ai_scene);
+        let skeleton = ai::animation::Skeleton::new(&ai_scene);
+        if skeleton.num_bones() > MAX_BONES {
+            panic!("the model has {} bones, but the shader only supports {}",
+                   skeleton.num_bones(), MAX_BONES);
+        }
 
         // stores the first index of each mesh, used for creating batches
         let mut start_indices = Vec::with_capacity(ai_scene.num_meshes as uint + 1);
@@ -221,31 +178,21 @@ impl<'a> Model<'a> {
             for mesh in ai_scene.get_meshes().iter() {
                 let vert_id_offset  = vertices.len() as u32;
 
-                // get all the bone information for this mesh
-                for bone in mesh.get_bones().iter() {
-                    let bone_id = bone_map.get_id(&bone.name.to_string());
-                    // println!("{}: Bone id and name: {} ===> {}",
-                    //          mesh_num, bone_id, bone.name);
-                    let bone_id = match bone_id {
-                        None => panic!("Invaild bone reference"),
-                        Some(id) => id,
-                    };
-                    'next_weight: for vert_weight in bone.get_weights().iter() {
-                        let vertex_id = (vert_id_offset + vert_weight.vertex_id) as uint;
-                        for i in range(0u, 4) {
-                            if bone_ids[vertex_id][i] == 0 {
-                                bone_weights[vertex_id][i] = vert_weight.weight;
-                                bone_ids[vertex_id][i] = bone_id;
-                                continue 'next_weight;
-                            }
-                        }
-                        // assimp should have limited bone weights to 4
-                        unreachable!();
-                    }
+                // get the 4 strongest bone influences of each vertex
+                let bone_index_map = match skeleton.mesh_bone_ids(*mesh) {
+                    None => panic!("Invaild bone reference"),
+                    Some(ids) => ids,
+                };
+                let influences = mesh.vertex_influences(4, bone_index_map.as_slice())
+                                     .unwrap();
+                if !influences.lossy_vertices.is_empty() {
+                    println!("warning: {} vertices are influenced by more than 4 bones",
+                             influences.lossy_vertices.len());
                 }
 
                 let verts = mesh.get_vertices();
-                let norms = mesh.get_normals();
+                // normals are generated while importing if they're missing
+                let norms = mesh.get_normals().expect("the mesh has no normals");
                 let tex_coords = mesh.get_texture_coords();
 
                 // fill up the vertex buffer
@@ -253,24 +200,24 @@ impl<'a> Model<'a> {
                     vertices.push( Vertex {
                         a_position: verts[i].to_array(),
                         a_normal: norms[i].to_array(),
-                        a_tex_coord: if tex_coords.len() == 0 {
+                        a_tex_coord: if mesh.num_uv_channels() == 0 {
                             [0.0, 0.0, 0.0]
                         } else {
                             // only support 1 texture coord
                             tex_coords[0][i].to_array()
                         },
-                        a_bone_weights: bone_weights[i + vert_id_offset as uint],
-                        a_bone_ids: bone_ids[i + vert_id_offset as uint],
+                        a_bone_weights: to_vec4(influences.get_weights(i)),
+                        a_bone_ids: to_ivec4(influences.get_bone_ids(i)),
                     });
                 }
 
-                // fill up the index buffer
-                for face in mesh.get_faces().iter() {
-                    let face_indices = face.get_indices();
-                    assert!(face_indices.len() == 3);
-                    indices.push(face_indices[0] + vert_id_offset);
-                    indices.push(face_indices[1] + vert_id_offset);
-                    indices.push(face_indices[2] + vert_id_offset);
+                // fill up the index buffer, skipping points and lines
+                let triangles = match mesh.get_indices::<u32>(ai::mesh::PrimitiveType::Triangle) {
+                    Ok(triangles) => triangles,
+                    Err(e) => panic!("couldn't get the indices of a mesh: {}", e),
+                };
+                for index in triangles.iter() {
+                    indices.push(*index + vert_id_offset);
                 }
 
                 start_indices.push(indices.len() as u32);
@@ -318,162 +265,29 @@ impl<'a> Model<'a> {
             vertices: vertices,
             indices: indices,
             batches: batches,
-            bone_map: RefCell::new(bone_map),
+            skeleton: skeleton,
             bone_transform_buffer: u_bone_transformations,
-            global_inverse: ai_scene.get_root_node().transformation.inverse(),
             scene: ai_scene,
         }
     }
 
-    fn interpolate_position(&self,
-                            time: f64,
-                            node: &ai::animation::NodeAnim
-                           ) -> ai::Vector3D {
-        let keys = node.get_position_keys();
-
-        // only one key, so no need to interpolate
-        if keys.len() == 1 {
-            return keys[0].value
-        }
-
-        // otherwise, find out which keys the given time falls between
-        // and interpolate
-        for pos_keys in keys.windows(2) {
-            // note: once we find a match, we return
-            if time < pos_keys[1].time {
-                let dt = pos_keys[1].time - pos_keys[0].time;
-                // how far inbetween the frams we are on a scale from 0 to 1
-                let s = (time - pos_keys[0].time) / dt;
-                return lerp(pos_keys[0].value,
-                            pos_keys[1].value,
-                            s as f32);
-            }
-        }
-        // get the last frame, if we didn't find a match
-        return keys[keys.len()-1].value
-    }
-
-    fn interpolate_scaling(&self,
-                           time: f64,
-                           node: &ai::animation::NodeAnim
-                           ) -> ai::Vector3D {
-        let keys = node.get_scaling_keys();
-
-        // only one key, so no need to interpolate
-        if keys.len() == 1 {
-            return keys[0].value
-        }
-
-        // otherwise, find out which keys the given time falls between
-        // and interpolate
-        for scale_keys in keys.windows(2) {
-            // note: once we find a match, we return
-            if time < scale_keys[1].time {
-                let dt = scale_keys[1].time - scale_keys[0].time;
-                // how far inbetween the frams we are on a scale from 0 to 1
-                let s = (time - scale_keys[0].time) / dt;
-                return lerp(scale_keys[0].value,
-                            scale_keys[1].value,
-                            s as f32);
-            }
-        }
-        // get the last frame, if we didn't find a match
-        return keys[keys.len()-1].value
-    }
-
-    fn interpolate_rotation(&self,
-                            time: f64,
-                            node: &ai::animation::NodeAnim
-                           ) -> ai::Quaternion {
-        let keys = node.get_rotation_keys();
-
-        // only one key, so no need to interpolate
-        if keys.len() == 1 {
-            return keys[0].value
-        }
-
-        // otherwise, find out which keys the given time falls between
-        // and interpolate
-        for rot_keys in keys.windows(2) {
-            // note: once we find a match, we return
-            if time < rot_keys[1].time {
-                let dt = rot_keys[1].time - rot_keys[0].time;
-                // how far inbetween the frames we are on a scale from 0 to 1
-                let s = (time - rot_keys[0].time) / dt;
-                // nlerp
-                return lerp(rot_keys[0].value,
-                            rot_keys[1].value,
-                            s as f32).normalize();
-            }
-        }
-        // get the last frame, if we didn't find a match
-        return keys[keys.len()-1].value
-                           }
-
-    fn update_bone_transforms(&self,
-                              time: f64,
-                              anim_num: uint,
-                              scene_node: &ai::scene::Node,
-                              parent_transform: &ai::Matrix4x4,
-                              ) {
-        // calculate the transformation matrix for this node
-        let animation = self.scene.get_animations()[anim_num];
-        let node_transform = match animation.find_node_anim(&scene_node.name) {
-            Some(node_anim) => {
-                self.interpolate_position(time, node_anim).translation_matrix() *
-                self.interpolate_rotation(time, node_anim).rotation_matrix() *
-                self.interpolate_scaling(time, node_anim).scaling_matrix()
-            },
-            None => {
-                scene_node.transformation
-            }
-        };
-
-        let node_to_global = *parent_transform * node_transform;
-
-        let opt_id = {
-            self.bone_map.borrow().get_id(&scene_node.name.to_string())
-        };
-        match opt_id {
-            None => { },
-            Some(id) => {
-                let offset = {
-                    self.bone_map.borrow().offsets[id as uint]
-                };
-                {
-                self.bone_map.borrow_mut().transforms[id as uint] =
-                    (self.global_inverse * node_to_global * offset)
-                    .transpose().to_array();
-                }
-            }
-        }
-
-        for child in scene_node.get_children().iter() {
-            self.update_bone_transforms(time,
-                                        anim_num,
-                                        *child,
-                                        &node_to_global,
-                                        );
-        }
-
-    }
-
-
     fn draw(&mut self,
             graphics: &mut gfx::Graphics<gfx::GlDevice, gfx::GlCommandBuffer>,
             frame: &gfx::Frame,
-            time: f64,
+            secs: f64,
             transform: Mat4,
             ) {
 
-        self.update_bone_transforms(time,
-                                    0,
-                                    self.scene.get_root_node(),
-                                    &ai::Matrix4x4::identity(),
-                                    );
+        // loop the first animation
+        let animation = self.scene.get_animations()[0];
+        let secs = secs % animation.get_duration_secs();
+        let bone_transforms: Vec<Mat4> = self.skeleton.evaluate(animation, secs)
+            .iter()
+            .map(|m| m.transpose().to_array())
+            .collect();
 
         graphics.device.update_buffer(self.bone_transform_buffer,
-                                      self.bone_map.borrow().transforms.as_slice(),
+                                      bone_transforms.as_slice(),
                                       0,
                                       );
 
@@ -653,11 +467,7 @@ fn main() {
     for e in Events::new(&window) {
         use event::RenderEvent;
         let now = time::get_time() - start;
-        let time = 18.0 * now.num_milliseconds() as f64 / 1e3;
-        let fmod = |x: f64, y: f64| -> f64 {
-            x - (x/y).floor() * y
-        };
-        let time = fmod(time , 140.0);
+        let secs = now.num_milliseconds() as f64 / 1e3;
 
         first_person.event(&e);
         e.render(|args| {
@@ -680,7 +490,7 @@ fn main() {
 
             model.draw(&mut graphics,
                        &frame,
-                       time,
+                       secs,
                        u_model_view_proj,
                        );
 
@@ -1,10 +1,19 @@
 //! Data structures for handling animation
 
 use libc::{c_double, c_uint};
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fmt;
+use std::io::File;
+use std::num::Float;
 
 use util::{ptr_ptr_to_slice, ptr_to_slice};
-use types::{Vector3D, Quaternion, AiString};
+use types::{Vector3D, Quaternion, Matrix4x4, AiString};
+use scene::{Node, Scene};
+use mesh::Mesh;
+
+/// Ticks per second used for animations that don't specify it.
+pub const DEFAULT_TICKS_PER_SEC : f64 = 25.0;
 
 /// A time-value pair specifying a certain 3D vector for the given time.
 #[deriving(Show, Clone)]
@@ -48,7 +57,7 @@ pub struct MeshKey {
 /// Defines how an animation channel behaves outside the defined time range.
 ///
 /// This corresponds to NodeAnim::pre_state and NodeAnim::post_state.
-#[deriving(Show)]
+#[deriving(Clone, PartialEq, Show)]
 #[repr(C)]
 pub enum AnimBehaviour {
     /// The value from the default node transformation is taken
@@ -163,6 +172,49 @@ impl NodeAnim {
     pub fn get_scaling_keys(&self) -> &[VectorKey] {
         unsafe { ptr_to_slice(self.scaling_keys, self.num_scaling_keys as uint) }
     }
+
+    /// Sample the channel at `time`, given in ticks.
+    ///
+    /// Returns the interpolated position, rotation and scaling. Positions and
+    /// scalings are linearly interpolated, rotations are interpolated with
+    /// slerp. Outside the range of the keys the channel behaves according to
+    /// `pre_state` and `post_state`; `AnimBehaviour::Default` is treated as
+    /// `AnimBehaviour::Constant`, use `sample_node` to fall back to the
+    /// transformation of the animated node instead.
+    pub fn sample(&self, time: f64) -> (Vector3D, Quaternion, Vector3D) {
+        sample_channel(self.get_position_keys(),
+                       self.get_rotation_keys(),
+                       self.get_scaling_keys(),
+                       constant_default(self.pre_state),
+                       constant_default(self.post_state),
+                       time,
+                       &(Vector3D { x: 0.0, y: 0.0, z: 0.0 },
+                         Quaternion::identity(),
+                         Vector3D { x: 1.0, y: 1.0, z: 1.0 }))
+    }
+
+    /// Sample the channel at `time`, given in ticks, falling back to the
+    /// transformation of `node` for `AnimBehaviour::Default`.
+    ///
+    /// See `sample`.
+    pub fn sample_node(&self, time: f64, node: &Node) -> (Vector3D, Quaternion, Vector3D) {
+        self.sample_default(time, &node.transformation.decompose())
+    }
+
+    /// Sample the channel at `time`, using `default` for
+    /// `AnimBehaviour::Default`
+    fn sample_default(&self,
+                      time: f64,
+                      default: &(Vector3D, Quaternion, Vector3D),
+                      ) -> (Vector3D, Quaternion, Vector3D) {
+        sample_channel(self.get_position_keys(),
+                       self.get_rotation_keys(),
+                       self.get_scaling_keys(),
+                       self.pre_state,
+                       self.post_state,
+                       time,
+                       default)
+    }
 }
 
 /// Describes vertex-based animations for a single mesh or a group of meshes.
@@ -191,6 +243,21 @@ impl MeshAnim {
     pub fn get_keys(&self) -> &[MeshKey] {
         unsafe { ptr_to_slice(self.keys, self.num_keys as uint) }
     }
+
+    /// Find the attachment meshes to interpolate between at `time`, given
+    /// in ticks.
+    ///
+    /// Returns the indices into `Mesh::get_anim_meshes` of the two frames
+    /// and the interpolation factor between them. Outside the range of the
+    /// keys the first or last frame is used. Returns `None` if there are no
+    /// keys. See `Mesh::morph`.
+    pub fn get_frames(&self, time: f64) -> Option<(u32, u32, f32)> {
+        let keys = self.get_keys();
+        match locate_key(keys, time, AnimBehaviour::Constant, AnimBehaviour::Constant) {
+            KeyPos::UseDefault => None,
+            KeyPos::Between(i, j, s) => Some((keys[i].value, keys[j].value, s as f32)),
+        }
+    }
 }
 
 /// An animation consists of keyframe data for a number of nodes.
@@ -227,6 +294,139 @@ pub struct Animation<'a> {
 }
 
 impl<'a> Animation<'a> {
+    /// Ticks per second of the animation.
+    ///
+    /// Returns `DEFAULT_TICKS_PER_SEC` if the imported file doesn't specify
+    /// it.
+    pub fn get_ticks_per_sec(&self) -> f64 {
+        if self.ticks_per_sec == 0.0 {
+            DEFAULT_TICKS_PER_SEC
+        } else {
+            self.ticks_per_sec
+        }
+    }
+
+    /// Convert a time in seconds to ticks of this animation
+    pub fn secs_to_ticks(&self, secs: f64) -> f64 {
+        secs * self.get_ticks_per_sec()
+    }
+
+    /// Duration of the animation in seconds
+    pub fn get_duration_secs(&self) -> f64 {
+        self.duration / self.get_ticks_per_sec()
+    }
+
+    /// Copy the animation, so it can outlive the scene
+    pub fn to_owned(&self) -> OwnedAnimation {
+        OwnedAnimation {
+            name: self.name.to_string(),
+            duration: self.duration,
+            ticks_per_sec: self.get_ticks_per_sec(),
+            channels: self.get_channels().iter()
+                .map(|channel| OwnedNodeAnim::from_node_anim(*channel))
+                .collect(),
+            mesh_channels: self.get_mesh_channels().iter()
+                .map(|channel| OwnedMeshAnim::from_mesh_anim(*channel))
+                .collect(),
+        }
+    }
+
+    /// Resample the node channels with `fps` uniformly spaced keys per
+    /// second.
+    ///
+    /// The resulting animation has `fps` ticks per second, so there is a key
+    /// on every tick from zero up to and including the end of the animation.
+    /// Channels without keys of a kind stay without keys of that kind, mesh
+    /// channels are copied with their key times converted.
+    ///
+    /// Returns an error if `fps` isn't a positive, finite number.
+    pub fn resample(&self, fps: f64) -> Result<OwnedAnimation, &'static str> {
+        if !(fps > 0.0 && fps.is_finite()) {
+            return Err("the frame rate must be positive")
+        }
+        let scale = fps / self.get_ticks_per_sec();
+        let duration = self.duration * scale;
+        // the source times of the new keys, in ticks of this animation
+        let times: Vec<f64> = range(0, duration.ceil() as uint + 1)
+            .map(|frame| (frame as f64).min(duration) / scale)
+            .collect();
+
+        let channels = self.get_channels().iter().map(|channel| {
+            OwnedNodeAnim {
+                position_keys: resample_keys(channel.get_position_keys(),
+                                             times.as_slice(), scale,
+                                             interpolate_vector_keys),
+                rotation_keys: resample_keys(channel.get_rotation_keys(),
+                                             times.as_slice(), scale,
+                                             interpolate_quat_keys),
+                scaling_keys: resample_keys(channel.get_scaling_keys(),
+                                            times.as_slice(), scale,
+                                            interpolate_vector_keys),
+                .. OwnedNodeAnim::from_node_anim(*channel)
+            }
+        }).collect();
+
+        let mesh_channels = self.get_mesh_channels().iter().map(|channel| {
+            let mut owned = OwnedMeshAnim::from_mesh_anim(*channel);
+            for key in owned.keys.iter_mut() {
+                key.time = key.time * scale;
+            }
+            owned
+        }).collect();
+
+        Ok(OwnedAnimation {
+            name: self.name.to_string(),
+            duration: duration,
+            ticks_per_sec: fps,
+            channels: channels,
+            mesh_channels: mesh_channels,
+        })
+    }
+
+    /// Extract the time range from `start` to `end`, given in ticks, into a
+    /// new animation called `name`.
+    ///
+    /// Keys are synthesized by interpolation at the boundaries of the range,
+    /// and the times of all keys are rebased to start at zero. Mesh channels
+    /// keep the key active at `start` and all keys within the range.
+    pub fn clip(&self, name: &str, start: f64, end: f64) -> OwnedAnimation {
+        let end = end.max(start);
+
+        let channels = self.get_channels().iter().map(|channel| {
+            OwnedNodeAnim {
+                position_keys: clip_keys(channel.get_position_keys(), start, end,
+                                         interpolate_vector_keys),
+                rotation_keys: clip_keys(channel.get_rotation_keys(), start, end,
+                                         interpolate_quat_keys),
+                scaling_keys: clip_keys(channel.get_scaling_keys(), start, end,
+                                        interpolate_vector_keys),
+                .. OwnedNodeAnim::from_node_anim(*channel)
+            }
+        }).collect();
+
+        let mesh_channels = self.get_mesh_channels().iter().map(|channel| {
+            OwnedMeshAnim {
+                name: channel.name.to_string(),
+                keys: clip_mesh_keys(channel.get_keys(), start, end),
+            }
+        }).collect();
+
+        OwnedAnimation {
+            name: name.to_string(),
+            duration: end - start,
+            ticks_per_sec: self.get_ticks_per_sec(),
+            channels: channels,
+            mesh_channels: mesh_channels,
+        }
+    }
+
+    /// Split the animation into the clips listed in `clips`, see `clip`
+    pub fn split(&self, clips: &[ClipRange]) -> Vec<OwnedAnimation> {
+        clips.iter()
+            .map(|range| self.clip(range.name.as_slice(), range.start, range.end))
+            .collect()
+    }
+
     /// The node animation channels. Each channel affects a single node.
     pub fn get_channels(&self) -> &[&NodeAnim] {
         unsafe { ptr_ptr_to_slice(self.channels, self.num_channels as uint) }
@@ -276,4 +476,1848 @@ impl<'a> fmt::Show for Animation<'a> {
     }
 }
 
+/// An animation channel of a single node, owning its keys.
+///
+/// Created by `Animation::to_owned` and `Animation::resample`.
+#[deriving(Clone, Show)]
+pub struct OwnedNodeAnim {
+    /// The name of the node affected by this channel
+    pub name: String,
+    /// The position keys of this channel
+    pub position_keys: Vec<VectorKey>,
+    /// The rotation keys of this channel
+    pub rotation_keys: Vec<QuatKey>,
+    /// The scaling keys of this channel
+    pub scaling_keys: Vec<VectorKey>,
+    /// The behaviour before the first key, see `NodeAnim::pre_state`
+    pub pre_state: AnimBehaviour,
+    /// The behaviour after the last key, see `NodeAnim::post_state`
+    pub post_state: AnimBehaviour,
+}
+
+impl OwnedNodeAnim {
+    /// Copy the keys of `channel`
+    pub fn from_node_anim(channel: &NodeAnim) -> OwnedNodeAnim {
+        OwnedNodeAnim {
+            name: channel.name.to_string(),
+            position_keys: channel.get_position_keys().to_vec(),
+            rotation_keys: channel.get_rotation_keys().to_vec(),
+            scaling_keys: channel.get_scaling_keys().to_vec(),
+            pre_state: channel.pre_state,
+            post_state: channel.post_state,
+        }
+    }
+
+    /// Sample the channel at `time`, given in ticks. See `NodeAnim::sample`.
+    pub fn sample(&self, time: f64) -> (Vector3D, Quaternion, Vector3D) {
+        sample_channel(self.position_keys.as_slice(),
+                       self.rotation_keys.as_slice(),
+                       self.scaling_keys.as_slice(),
+                       constant_default(self.pre_state),
+                       constant_default(self.post_state),
+                       time,
+                       &(Vector3D { x: 0.0, y: 0.0, z: 0.0 },
+                         Quaternion::identity(),
+                         Vector3D { x: 1.0, y: 1.0, z: 1.0 }))
+    }
+
+    /// Remove the keys which can be reproduced by interpolating their
+    /// neighbours within `tolerance`.
+    ///
+    /// The first and the last key of each track are always kept. Returns
+    /// the number of removed keys.
+    pub fn reduce_keys(&mut self, tolerance: &KeyTolerance) -> uint {
+        let before = self.position_keys.len() + self.rotation_keys.len() +
+                     self.scaling_keys.len();
+        self.position_keys = reduce_keys(self.position_keys.as_slice(),
+                                         tolerance.position);
+        self.rotation_keys = reduce_keys(self.rotation_keys.as_slice(),
+                                         tolerance.rotation);
+        self.scaling_keys = reduce_keys(self.scaling_keys.as_slice(),
+                                        tolerance.scaling);
+        before - (self.position_keys.len() + self.rotation_keys.len() +
+                  self.scaling_keys.len())
+    }
+}
+
+/// A vertex-based animation channel, owning its keys.
+#[deriving(Clone, Show)]
+pub struct OwnedMeshAnim {
+    /// The name of the mesh affected by this channel
+    pub name: String,
+    /// The key frames of this channel
+    pub keys: Vec<MeshKey>,
+}
+
+impl OwnedMeshAnim {
+    /// Copy the keys of `channel`
+    pub fn from_mesh_anim(channel: &MeshAnim) -> OwnedMeshAnim {
+        OwnedMeshAnim {
+            name: channel.name.to_string(),
+            keys: channel.get_keys().to_vec(),
+        }
+    }
+}
+
+/// An animation owning its channels, independent of the `Scene` it was
+/// created from.
+#[deriving(Clone, Show)]
+pub struct OwnedAnimation {
+    /// The name of the animation
+    pub name: String,
+    /// Duration of the animation in ticks
+    pub duration: f64,
+    /// Ticks per second of the animation
+    pub ticks_per_sec: f64,
+    /// The node animation channels
+    pub channels: Vec<OwnedNodeAnim>,
+    /// The mesh animation channels
+    pub mesh_channels: Vec<OwnedMeshAnim>,
+}
+
+impl OwnedAnimation {
+    /// Duration of the animation in seconds
+    pub fn get_duration_secs(&self) -> f64 {
+        self.duration / self.ticks_per_sec
+    }
+
+    /// Find the channel of the node named `name`
+    pub fn find_channel(&self, name: &str) -> Option<&OwnedNodeAnim> {
+        self.channels.iter().find(|channel| channel.name.as_slice() == name)
+    }
+
+    /// Remove redundant keys from all channels, see
+    /// `OwnedNodeAnim::reduce_keys`.
+    ///
+    /// Returns the number of removed keys.
+    pub fn reduce_keys(&mut self, tolerance: &KeyTolerance) -> uint {
+        self.channels.iter_mut().fold(0, |removed, channel| {
+            removed + channel.reduce_keys(tolerance)
+        })
+    }
+
+    /// Separate the horizontal translation and the yaw of the node `root`
+    /// into a root motion track, leaving the animation in place.
+    ///
+    /// `up` is the up axis of the parent space of `root`, usually Y or Z
+    /// depending on the tool the animation was exported from. Horizontal
+    /// means perpendicular to `up`, and the yaw is the rotation around it.
+    /// The root motion track holds the movement relative to the first keys
+    /// of the channel, so that applying it as the parent transformation of
+    /// the in-place animation reproduces the original movement. Returns
+    /// `None` if there is no channel for `root` or `up` is zero.
+    pub fn extract_root_motion(&mut self, root: &str, up: &Vector3D) -> Option<RootMotion> {
+        if up.dot(up) < 1e-12 {
+            return None
+        }
+        let up = up.clone().normalize();
+        let channel = match self.channels.iter_mut()
+                                .find(|channel| channel.name.as_slice() == root) {
+            Some(channel) => channel,
+            None => return None,
+        };
+
+        let yaw_at = |rotation_keys: &[QuatKey], time: f64| -> Quaternion {
+            if rotation_keys.is_empty() {
+                return Quaternion::identity()
+            }
+            let start = yaw(&rotation_keys[0].value, &up);
+            let rotation = sample_quat_keys(rotation_keys,
+                                            AnimBehaviour::Constant,
+                                            AnimBehaviour::Constant,
+                                            time,
+                                            &rotation_keys[0].value);
+            yaw(&rotation, &up) * start.conjugate()
+        };
+
+        let mut motion = RootMotion {
+            position_keys: Vec::with_capacity(channel.position_keys.len()),
+            rotation_keys: Vec::with_capacity(channel.rotation_keys.len()),
+        };
+
+        if !channel.position_keys.is_empty() {
+            let start = channel.position_keys[0].value;
+            for key in channel.position_keys.iter_mut() {
+                let moved = key.value - start;
+                let offset = moved - up * moved.dot(&up);
+                let heading = yaw_at(channel.rotation_keys.as_slice(), key.time);
+                motion.position_keys.push(VectorKey { time: key.time, value: offset });
+                key.value = heading.conjugate().rotate(&(key.value - offset));
+            }
+        }
+
+        let original = channel.rotation_keys.clone();
+        for key in channel.rotation_keys.iter_mut() {
+            let heading = yaw_at(original.as_slice(), key.time);
+            motion.rotation_keys.push(QuatKey { time: key.time, value: heading });
+            key.value = (heading.conjugate() * key.value).normalize();
+        }
+
+        Some(motion)
+    }
+}
+
+/// The movement of a character extracted by
+/// `OwnedAnimation::extract_root_motion`.
+#[deriving(Clone, Show)]
+pub struct RootMotion {
+    /// The horizontal translation relative to the start of the animation
+    pub position_keys: Vec<VectorKey>,
+    /// The rotation around the up axis relative to the start of the
+    /// animation
+    pub rotation_keys: Vec<QuatKey>,
+}
+
+impl RootMotion {
+    /// Sample the translation and rotation at `time`, given in ticks
+    pub fn sample(&self, time: f64) -> (Vector3D, Quaternion) {
+        (sample_vector_keys(self.position_keys.as_slice(),
+                            AnimBehaviour::Constant,
+                            AnimBehaviour::Constant,
+                            time,
+                            &Vector3D { x: 0.0, y: 0.0, z: 0.0 }),
+         sample_quat_keys(self.rotation_keys.as_slice(),
+                          AnimBehaviour::Constant,
+                          AnimBehaviour::Constant,
+                          time,
+                          &Quaternion::identity()))
+    }
+
+    /// The movement from `from` to `to`, given in ticks, relative to the
+    /// heading at `from`.
+    ///
+    /// This is what a character controller should add to its transformation
+    /// when advancing the animation from `from` to `to`.
+    pub fn delta(&self, from: f64, to: f64) -> (Vector3D, Quaternion) {
+        let (start_position, start_rotation) = self.sample(from);
+        let (end_position, end_rotation) = self.sample(to);
+        let inverse = start_rotation.conjugate();
+        (inverse.rotate(&(end_position - start_position)),
+         (inverse * end_rotation).normalize())
+    }
+}
+
+/// The rotation around the unit vector `up` contained in `rotation`, using
+/// a swing twist decomposition
+fn yaw(rotation: &Quaternion, up: &Vector3D) -> Quaternion {
+    let axis = Vector3D { x: rotation.x, y: rotation.y, z: rotation.z };
+    let projected = *up * axis.dot(up);
+    let twist = Quaternion { w: rotation.w, x: projected.x, y: projected.y, z: projected.z };
+    if twist.dot(&twist) < 1e-12 {
+        // a half turn around a horizontal axis, the yaw is undefined
+        Quaternion::identity()
+    } else {
+        twist * twist.rnorm()
+    }
+}
+
+/// A named time range of an animation, see `Animation::split`
+#[deriving(Clone, PartialEq, Show)]
+pub struct ClipRange {
+    /// The name of the clip
+    pub name: String,
+    /// The start of the clip in ticks
+    pub start: f64,
+    /// The end of the clip in ticks
+    pub end: f64,
+}
+
+/// Parse a table of clips.
+///
+/// Each line holds the name, start and end of a clip, separated by commas
+/// or whitespace, e.g. `walk, 0, 30`. Empty lines and everything following
+/// a `#` are ignored. The name may not contain commas or whitespace.
+pub fn parse_clip_table(table: &str) -> Result<Vec<ClipRange>, String> {
+    let mut clips = Vec::new();
+
+    for (line_num, line) in table.lines().enumerate() {
+        let line = match line.find('#') {
+            Some(comment) => line.slice_to(comment),
+            None => line,
+        };
+        let fields: Vec<&str> = line.split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|field| !field.is_empty())
+            .collect();
+        if fields.is_empty() {
+            continue
+        }
+        if fields.len() != 3 {
+            return Err(format!("line {}: expected name, start and end of a clip",
+                               line_num + 1))
+        }
+
+        let parse_time = |field: &str| -> Result<f64, String> {
+            match from_str::<f64>(field) {
+                Some(time) => Ok(time),
+                None => Err(format!("line {}: invalid time `{}`", line_num + 1, field)),
+            }
+        };
+        let start = try!(parse_time(fields[1]));
+        let end = try!(parse_time(fields[2]));
+        if end < start {
+            return Err(format!("line {}: clip `{}` ends before it starts",
+                               line_num + 1, fields[0]))
+        }
+
+        clips.push(ClipRange {
+            name: fields[0].to_string(),
+            start: start,
+            end: end,
+        });
+    }
+
+    Ok(clips)
+}
+
+/// Load a table of clips from a file, see `parse_clip_table`
+pub fn load_clip_table(path: &Path) -> Result<Vec<ClipRange>, String> {
+    match File::open(path).read_to_string() {
+        Ok(table) => parse_clip_table(table.as_slice()),
+        Err(e) => Err(format!("couldn't read {}: {}", path.display(), e)),
+    }
+}
+
+/// The maximum error allowed when removing keys, see
+/// `OwnedNodeAnim::reduce_keys`.
+#[deriving(Clone, PartialEq, Show)]
+pub struct KeyTolerance {
+    /// The maximum distance between positions
+    pub position: f32,
+    /// The maximum angle between rotations in radians
+    pub rotation: f32,
+    /// The maximum difference between scalings
+    pub scaling: f32,
+}
+
+/// The local transformation of a single joint, relative to its parent.
+#[deriving(Clone, PartialEq, Show)]
+pub struct JointPose {
+    /// The translation of the joint
+    pub position: Vector3D,
+    /// The rotation of the joint
+    pub rotation: Quaternion,
+    /// The scaling of the joint
+    pub scaling: Vector3D,
+}
+
+impl JointPose {
+    /// A joint pose that doesn't transform anything
+    pub fn identity() -> JointPose {
+        JointPose {
+            position: Vector3D { x: 0.0, y: 0.0, z: 0.0 },
+            rotation: Quaternion::identity(),
+            scaling: Vector3D { x: 1.0, y: 1.0, z: 1.0 },
+        }
+    }
+
+    /// Decompose a transformation matrix into a joint pose
+    pub fn from_matrix(transformation: &Matrix4x4) -> JointPose {
+        let (position, rotation, scaling) = transformation.decompose();
+        JointPose { position: position, rotation: rotation, scaling: scaling }
+    }
+
+    /// Compose the transformation matrix of the joint
+    pub fn to_matrix(&self) -> Matrix4x4 {
+        Matrix4x4::compose(&self.position, &self.rotation, &self.scaling)
+    }
+
+    /// Interpolate between this pose (`weight = 0`) and `other`
+    /// (`weight = 1`)
+    pub fn blend(&self, other: &JointPose, weight: f32) -> JointPose {
+        JointPose {
+            position: self.position.lerp(&other.position, weight),
+            rotation: self.rotation.slerp(&other.rotation, weight),
+            scaling: self.scaling.lerp(&other.scaling, weight),
+        }
+    }
+
+    /// Add the difference between `additive` and `reference` to this pose,
+    /// scaled by `weight`.
+    ///
+    /// Translations are offset, rotations and scalings are applied on top
+    /// of the ones of this pose.
+    pub fn add(&self, additive: &JointPose, reference: &JointPose, weight: f32) -> JointPose {
+        let delta_rotation = Quaternion::identity()
+            .slerp(&(reference.rotation.conjugate() * additive.rotation), weight);
+        let ratio = |a: f32, r: f32| if r == 0.0 { 1.0 } else { a / r };
+        let delta_scaling = Vector3D {
+            x: ratio(additive.scaling.x, reference.scaling.x),
+            y: ratio(additive.scaling.y, reference.scaling.y),
+            z: ratio(additive.scaling.z, reference.scaling.z),
+        };
+        let delta_scaling = Vector3D { x: 1.0, y: 1.0, z: 1.0 }
+            .lerp(&delta_scaling, weight);
+
+        JointPose {
+            position: self.position + (additive.position - reference.position) * weight,
+            rotation: (self.rotation * delta_rotation).normalize(),
+            scaling: Vector3D {
+                x: self.scaling.x * delta_scaling.x,
+                y: self.scaling.y * delta_scaling.y,
+                z: self.scaling.z * delta_scaling.z,
+            },
+        }
+    }
+}
+
+/// A per-joint weight, used to restrict blending to parts of a skeleton.
+///
+/// Joints are identified by the name of their node.
+#[deriving(Clone, Show)]
+pub struct JointMask {
+    /// The weights of the joints set explicitly
+    weights: HashMap<String, f32>,
+    /// The weight of all other joints
+    default: f32,
+}
+
+impl JointMask {
+    /// Create a mask giving every joint the weight `default`
+    pub fn new(default: f32) -> JointMask {
+        JointMask { weights: HashMap::new(), default: default }
+    }
+
+    /// Create a mask with weight `weight` for the joint `root` and all
+    /// joints below it, and zero for all others.
+    ///
+    /// This is useful to e.g. only affect the upper body of a character.
+    pub fn from_subtree(skeleton: &Skeleton, root: &str, weight: f32) -> JointMask {
+        let mut mask = JointMask::new(0.0);
+        let mut inside = Vec::with_capacity(skeleton.joints.len());
+        for joint in skeleton.joints.iter() {
+            let is_inside = joint.name.as_slice() == root || match joint.parent {
+                Some(parent) => inside[parent],
+                None => false,
+            };
+            if is_inside {
+                mask.set_weight(joint.name.as_slice(), weight);
+            }
+            inside.push(is_inside);
+        }
+        mask
+    }
+
+    /// Set the weight of the joint `name`
+    pub fn set_weight(&mut self, name: &str, weight: f32) {
+        let _ = self.weights.insert(name.to_string(), weight);
+    }
+
+    /// Get the weight of the joint `name`
+    pub fn get_weight(&self, name: &str) -> f32 {
+        match self.weights.get(name) {
+            Some(weight) => *weight,
+            None => self.default,
+        }
+    }
+}
+
+/// The local transformations of a set of joints, keyed by node name.
+///
+/// Poses are sampled from animations with `Pose::sample` or
+/// `Skeleton::sample_pose`, combined with `blend` and `add_layer` and turned
+/// into bone matrices with `Skeleton::evaluate_pose`.
+#[deriving(Clone, Show)]
+pub struct Pose {
+    /// The transformation of each joint
+    pub joints: HashMap<String, JointPose>,
+}
+
+impl Pose {
+    /// Create an empty pose
+    pub fn new() -> Pose {
+        Pose { joints: HashMap::new() }
+    }
+
+    /// Sample the channels of `animation` at `secs` seconds.
+    ///
+    /// The pose only contains the joints animated by `animation`, see
+    /// `Skeleton::sample_pose` for a pose of a whole skeleton.
+    pub fn sample(animation: &Animation, secs: f64) -> Pose {
+        let time = animation.secs_to_ticks(secs);
+        let mut pose = Pose::new();
+        for channel in animation.get_channels().iter() {
+            let (position, rotation, scaling) = channel.sample(time);
+            pose.set(channel.name.to_string().as_slice(), JointPose {
+                position: position,
+                rotation: rotation,
+                scaling: scaling,
+            });
+        }
+        pose
+    }
+
+    /// Get the transformation of the joint `name`
+    pub fn get(&self, name: &str) -> Option<&JointPose> {
+        self.joints.get(name)
+    }
+
+    /// Set the transformation of the joint `name`
+    pub fn set(&mut self, name: &str, joint: JointPose) {
+        let _ = self.joints.insert(name.to_string(), joint);
+    }
+
+    /// Blend this pose (`weight = 0`) with `other` (`weight = 1`).
+    ///
+    /// The weight of each joint is multiplied by its weight in `mask`.
+    /// Joints only present in one of the poses are taken from that pose.
+    pub fn blend(&self, other: &Pose, weight: f32, mask: Option<&JointMask>) -> Pose {
+        let mut result = self.clone();
+        for (name, joint) in other.joints.iter() {
+            let blended = match self.joints.get(name) {
+                Some(base) => base.blend(joint, weight * mask_weight(mask, name)),
+                None => joint.clone(),
+            };
+            let _ = result.joints.insert(name.clone(), blended);
+        }
+        result
+    }
+
+    /// Layer the difference between `additive` and `reference` on top of
+    /// this pose.
+    ///
+    /// The weight of each joint is multiplied by its weight in `mask`.
+    /// Joints missing from this pose, `additive` or `reference` are left
+    /// unchanged.
+    pub fn add_layer(&self,
+                     additive: &Pose,
+                     reference: &Pose,
+                     weight: f32,
+                     mask: Option<&JointMask>) -> Pose {
+        let mut result = self.clone();
+        for (name, base) in self.joints.iter() {
+            match (additive.joints.get(name), reference.joints.get(name)) {
+                (Some(add), Some(reference)) => {
+                    let joint = base.add(add, reference, weight * mask_weight(mask, name));
+                    let _ = result.joints.insert(name.clone(), joint);
+                }
+                _ => { }
+            }
+        }
+        result
+    }
+}
+
+/// The weight of the joint `name` in an optional mask
+fn mask_weight(mask: Option<&JointMask>, name: &String) -> f32 {
+    match mask {
+        Some(mask) => mask.get_weight(name.as_slice()),
+        None => 1.0,
+    }
+}
+
+/// Transfers animations from one skeleton to another.
+///
+/// Joints are matched by a table of names. The rotations of the source
+/// joints are applied relative to the bind poses of both skeletons, so the
+/// skeletons don't need to share the orientation of their joints. The
+/// target joints keep their own bone lengths; only the translation of the
+/// topmost mapped joints, usually the hips, is transferred, scaled by the
+/// ratio of the heights of the skeletons in their bind poses.
+pub struct Retargeter {
+    /// The mapped joints, parents before their children
+    joints: Vec<RetargetJoint>,
+}
+
+/// A pair of joints mapped by a `Retargeter`
+struct RetargetJoint {
+    /// The name of the source joint
+    source: String,
+    /// The name of the target joint
+    target: String,
+    /// The local bind pose of the source joint
+    source_bind: JointPose,
+    /// The global bind rotation of the parent of the source joint
+    source_parent: Quaternion,
+    /// The local bind pose of the target joint
+    target_bind: JointPose,
+    /// The global bind rotation of the parent of the target joint
+    target_parent: Quaternion,
+    /// The scale applied to the translation, `None` if the target joint
+    /// keeps its bind translation
+    translation_scale: Option<f32>,
+}
+
+impl Retargeter {
+    /// Create a retargeter from `source` to `target`.
+    ///
+    /// `names` maps the names of source joints to the names of the target
+    /// joints. The height of a skeleton is the extent of its mapped joints
+    /// along `up` in the bind pose, given in the space of the scene's root
+    /// node. Returns an error naming the joint if a joint doesn't exist.
+    pub fn new(source: &Skeleton,
+               target: &Skeleton,
+               names: &HashMap<String, String>,
+               up: &Vector3D) -> Result<Retargeter, String> {
+        if up.dot(up) < 1e-12 {
+            return Err("the up axis must not be zero".to_string())
+        }
+        for name in names.keys() {
+            if source.find_joint(name.as_slice()).is_none() {
+                return Err(format!("source joint `{}` not found", name))
+            }
+        }
+
+        // the indices of the mapped joints in both skeletons
+        let mut pairs = Vec::with_capacity(names.len());
+        for (index, joint) in source.joints.iter().enumerate() {
+            let target_name = match names.get(&joint.name) {
+                Some(name) => name,
+                None => continue,
+            };
+            match target.find_joint(target_name.as_slice()) {
+                Some(target_index) => pairs.push((index, target_index)),
+                None => return Err(format!("target joint `{}` not found", target_name)),
+            }
+        }
+
+        let source_indices: Vec<uint> = pairs.iter().map(|&(s, _)| s).collect();
+        let target_indices: Vec<uint> = pairs.iter().map(|&(_, t)| t).collect();
+        let source_height = source.bind_extent(source_indices.as_slice(), up);
+        let target_height = target.bind_extent(target_indices.as_slice(), up);
+        let scale = if source_height < 1e-6 { 1.0 } else { target_height / source_height };
+
+        let mut joints = Vec::with_capacity(pairs.len());
+        for &(index, target_index) in pairs.iter() {
+            let joint = &source.joints[index];
+
+            // is a parent of this joint mapped as well?
+            let mut parent = joint.parent;
+            let mut has_mapped_parent = false;
+            loop {
+                match parent {
+                    Some(p) if names.contains_key(&source.joints[p].name) => {
+                        has_mapped_parent = true;
+                        break
+                    }
+                    Some(p) => parent = source.joints[p].parent,
+                    None => break,
+                }
+            }
+
+            let (source_bind, source_parent) = source.bind_pose_of(index);
+            let (target_bind, target_parent) = target.bind_pose_of(target_index);
+            joints.push(RetargetJoint {
+                source: joint.name.clone(),
+                target: target.joints[target_index].name.clone(),
+                source_bind: source_bind,
+                source_parent: source_parent,
+                target_bind: target_bind,
+                target_parent: target_parent,
+                translation_scale: if has_mapped_parent { None } else { Some(scale) },
+            });
+        }
+
+        Ok(Retargeter { joints: joints })
+    }
+
+    /// Retarget the channels of `animation`, which must animate the source
+    /// skeleton.
+    ///
+    /// Each retargeted channel has keys at the times of all keys of its
+    /// source channel. Channels of unmapped joints are dropped.
+    pub fn retarget(&self, animation: &Animation) -> OwnedAnimation {
+        let mut channels = Vec::new();
+        for joint in self.joints.iter() {
+            let channel = match animation.get_channels().iter()
+                    .find(|c| c.name.to_string() == joint.source) {
+                Some(channel) => *channel,
+                None => continue,
+            };
+
+            let mut times: Vec<f64> = channel.get_position_keys().iter().map(|k| k.time)
+                .chain(channel.get_rotation_keys().iter().map(|k| k.time))
+                .chain(channel.get_scaling_keys().iter().map(|k| k.time))
+                .collect();
+            times.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+            times.dedup();
+
+            let bind = (joint.source_bind.position,
+                        joint.source_bind.rotation,
+                        joint.source_bind.scaling);
+            let mut owned = OwnedNodeAnim {
+                name: joint.target.clone(),
+                position_keys: Vec::with_capacity(times.len()),
+                rotation_keys: Vec::with_capacity(times.len()),
+                scaling_keys: Vec::with_capacity(times.len()),
+                pre_state: channel.pre_state,
+                post_state: channel.post_state,
+            };
+            for &time in times.iter() {
+                let (position, rotation, scaling) = channel.sample_default(time, &bind);
+                let pose = joint.retarget(&JointPose {
+                    position: position,
+                    rotation: rotation,
+                    scaling: scaling,
+                });
+                owned.position_keys.push(VectorKey { time: time, value: pose.position });
+                owned.rotation_keys.push(QuatKey { time: time, value: pose.rotation });
+                owned.scaling_keys.push(VectorKey { time: time, value: pose.scaling });
+            }
+            channels.push(owned);
+        }
+
+        OwnedAnimation {
+            name: animation.name.to_string(),
+            duration: animation.duration,
+            ticks_per_sec: animation.get_ticks_per_sec(),
+            channels: channels,
+            mesh_channels: Vec::new(),
+        }
+    }
+
+    /// Retarget a pose of the source skeleton. Unmapped joints are dropped.
+    pub fn retarget_pose(&self, pose: &Pose) -> Pose {
+        let mut result = Pose::new();
+        for joint in self.joints.iter() {
+            match pose.get(joint.source.as_slice()) {
+                Some(source) => result.set(joint.target.as_slice(), joint.retarget(source)),
+                None => { }
+            }
+        }
+        result
+    }
+}
+
+impl RetargetJoint {
+    /// Retarget a local pose of the source joint
+    fn retarget(&self, source: &JointPose) -> JointPose {
+        // the change of the rotation relative to the bind pose, in global
+        // space
+        let delta = self.source_parent * source.rotation *
+                    self.source_bind.rotation.conjugate() *
+                    self.source_parent.conjugate();
+        let rotation = self.target_parent.conjugate() * delta *
+                       self.target_parent * self.target_bind.rotation;
+
+        let position = match self.translation_scale {
+            Some(scale) => {
+                let offset = self.source_parent.rotate(
+                    &(source.position - self.source_bind.position));
+                self.target_bind.position +
+                    self.target_parent.conjugate().rotate(&(offset * scale))
+            }
+            None => self.target_bind.position,
+        };
+
+        JointPose {
+            position: position,
+            rotation: rotation.normalize(),
+            scaling: self.target_bind.scaling,
+        }
+    }
+}
+
+/// A node of a `Skeleton`
+struct Joint {
+    /// The name of the scene node
+    name: String,
+    /// Index of the parent joint, the parent always precedes its children
+    parent: Option<uint>,
+    /// The transformation of the node relative to its parent
+    transformation: Matrix4x4,
+    /// `transformation` split into position, rotation and scaling
+    bind_pose: (Vector3D, Quaternion, Vector3D),
+    /// The id of the bone attached to this node, if any
+    bone: Option<uint>,
+}
+
+/// The node hierarchy of a scene together with the bones of its meshes,
+/// used to compute the bone matrices for skinning.
+///
+/// Every uniquely named bone of the scene's meshes gets an id, which is the
+/// index of its matrix in the palettes returned by `evaluate`.
+pub struct Skeleton {
+    /// The nodes of the scene, parents before their children
+    joints: Vec<Joint>,
+    /// Translates a bone name into a bone id
+    bone_ids: HashMap<String, uint>,
+    /// The offset matrix of each bone, indexed by bone id
+    offsets: Vec<Matrix4x4>,
+    /// The inverse transformation of the scene's root node
+    global_inverse: Matrix4x4,
+    /// The node to global transformation of each joint in the bind pose.
+    ///
+    /// Taken from the bone offsets where available, since the node
+    /// transformations don't need to match the pose the meshes were bound
+    /// in.
+    bind_globals: Vec<Matrix4x4>,
+}
+
+impl Skeleton {
+    /// Build the skeleton of `scene`, collecting the bones of all its
+    /// meshes.
+    pub fn new(scene: &Scene) -> Skeleton {
+        let mut bone_ids = HashMap::new();
+        let mut offsets = Vec::new();
+
+        for mesh in scene.get_meshes().iter() {
+            for bone in mesh.get_bones().iter() {
+                let name = bone.name.to_string();
+                if !bone_ids.contains_key(&name) {
+                    let _ = bone_ids.insert(name, offsets.len());
+                    offsets.push(bone.offset_matrix);
+                }
+            }
+        }
+
+        let mut joints = Vec::new();
+        let root = scene.get_root_node();
+        let mut stack = vec![(root, None)];
+        loop {
+            let (node, parent) = match stack.pop() {
+                Some(entry) => entry,
+                None => break,
+            };
+            let name = node.name.to_string();
+            let index = joints.len();
+            joints.push(Joint {
+                bone: bone_ids.get(&name).map(|id| *id),
+                name: name,
+                parent: parent,
+                transformation: node.transformation,
+                bind_pose: node.transformation.decompose(),
+            });
+            for child in node.get_children().iter().rev() {
+                stack.push((*child, Some(index)));
+            }
+        }
+
+        let mut bind_globals: Vec<Matrix4x4> = Vec::with_capacity(joints.len());
+        for joint in joints.iter() {
+            let global = match (joint.bone, joint.parent) {
+                (Some(id), _) => root.transformation * offsets[id].inverse(),
+                (None, Some(parent)) => bind_globals[parent] * joint.transformation,
+                (None, None) => joint.transformation,
+            };
+            bind_globals.push(global);
+        }
+
+        Skeleton {
+            joints: joints,
+            bone_ids: bone_ids,
+            offsets: offsets,
+            global_inverse: root.transformation.inverse(),
+            bind_globals: bind_globals,
+        }
+    }
+
+    /// The extent of the joints at `indices` along the unit vector in the
+    /// direction of `up`, in the bind pose
+    fn bind_extent(&self, indices: &[uint], up: &Vector3D) -> f32 {
+        let origin = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+        let mut low: f32 = Float::infinity();
+        let mut high: f32 = Float::neg_infinity();
+        for &index in indices.iter() {
+            let height = self.bind_globals[index].transform_point(&origin).dot(up);
+            low = low.min(height);
+            high = high.max(height);
+        }
+        if high < low { 0.0 } else { (high - low) * up.rnorm() }
+    }
+
+    /// Find the index of the joint named `name`
+    fn find_joint(&self, name: &str) -> Option<uint> {
+        self.joints.iter().position(|joint| joint.name.as_slice() == name)
+    }
+
+    /// The bind pose of the joint at `index` relative to its parent, and the
+    /// global bind rotation of its parent
+    fn bind_pose_of(&self, index: uint) -> (JointPose, Quaternion) {
+        match self.joints[index].parent {
+            Some(parent) => {
+                let parent_global = self.bind_globals[parent];
+                let local = parent_global.inverse() * self.bind_globals[index];
+                let (_, parent_rotation, _) = parent_global.decompose();
+                (JointPose::from_matrix(&local), parent_rotation)
+            }
+            None => (JointPose::from_matrix(&self.bind_globals[index]),
+                     Quaternion::identity()),
+        }
+    }
+
+    /// The number of bones, which is the size of the palettes returned by
+    /// `evaluate`
+    pub fn num_bones(&self) -> uint {
+        self.offsets.len()
+    }
+
+    /// Get the id of the bone named `name`
+    pub fn get_bone_id(&self, name: &str) -> Option<uint> {
+        self.bone_ids.get(name).map(|id| *id)
+    }
+
+    /// The offset matrices of the bones, indexed by bone id
+    pub fn get_offsets(&self) -> &[Matrix4x4] {
+        self.offsets.as_slice()
+    }
+
+    /// Compute the bone matrices of `animation` at `secs` seconds.
+    ///
+    /// Each matrix transforms a vertex from mesh space to its animated
+    /// position, i.e. it is the global inverse transformation times the
+    /// animated node to global transformation times the bone offset. The
+    /// result is indexed by bone id; bones whose node is not found keep the
+    /// identity matrix.
+    ///
+    /// Nodes without a channel in `animation` keep their transformation.
+    /// The time is not wrapped, see `AnimBehaviour` for what happens outside
+    /// the range of the keys.
+    pub fn evaluate(&self, animation: &Animation, secs: f64) -> Vec<Matrix4x4> {
+        let time = animation.secs_to_ticks(secs);
+
+        let mut channels = HashMap::new();
+        for channel in animation.get_channels().iter() {
+            let _ = channels.insert(channel.name.to_string(), *channel);
+        }
+
+        let locals: Vec<Matrix4x4> = self.joints.iter().map(|joint| {
+            match channels.get(&joint.name) {
+                Some(channel) => {
+                    let (position, rotation, scaling) =
+                        channel.sample_default(time, &joint.bind_pose);
+                    Matrix4x4::compose(&position, &rotation, &scaling)
+                }
+                None => joint.transformation,
+            }
+        }).collect();
+
+        self.palette(locals.as_slice())
+    }
+
+    /// The bone ids of the bones of `mesh`, in the order of `get_bones`, as
+    /// expected by `Mesh::vertex_influences`.
+    ///
+    /// Returns `None` if a bone of `mesh` isn't part of the skeleton.
+    pub fn mesh_bone_ids(&self, mesh: &Mesh) -> Option<Vec<u32>> {
+        let mut ids = Vec::with_capacity(mesh.get_bones().len());
+        for bone in mesh.get_bones().iter() {
+            match self.get_bone_id(bone.name.to_string().as_slice()) {
+                Some(id) => ids.push(id as u32),
+                None => return None,
+            }
+        }
+        Some(ids)
+    }
+
+    /// Sample `animation` at `secs` seconds for every joint of the skeleton.
+    ///
+    /// Joints without a channel in `animation` keep their transformation.
+    pub fn sample_pose(&self, animation: &Animation, secs: f64) -> Pose {
+        let time = animation.secs_to_ticks(secs);
+
+        let mut channels = HashMap::new();
+        for channel in animation.get_channels().iter() {
+            let _ = channels.insert(channel.name.to_string(), *channel);
+        }
+
+        let mut pose = self.rest_pose();
+        for joint in self.joints.iter() {
+            match channels.get(&joint.name) {
+                Some(channel) => {
+                    let (position, rotation, scaling) =
+                        channel.sample_default(time, &joint.bind_pose);
+                    pose.set(joint.name.as_slice(), JointPose {
+                        position: position,
+                        rotation: rotation,
+                        scaling: scaling,
+                    });
+                }
+                None => { }
+            }
+        }
+        pose
+    }
+
+    /// The pose of the skeleton given by the transformations of the nodes
+    pub fn rest_pose(&self) -> Pose {
+        let mut pose = Pose::new();
+        for joint in self.joints.iter() {
+            let (ref position, ref rotation, ref scaling) = joint.bind_pose;
+            pose.set(joint.name.as_slice(), JointPose {
+                position: *position,
+                rotation: *rotation,
+                scaling: *scaling,
+            });
+        }
+        pose
+    }
+
+    /// Compute the bone matrices for `pose`, see `evaluate`.
+    ///
+    /// Joints missing from `pose` keep the transformation of their node.
+    pub fn evaluate_pose(&self, pose: &Pose) -> Vec<Matrix4x4> {
+        let locals: Vec<Matrix4x4> = self.joints.iter().map(|joint| {
+            match pose.get(joint.name.as_slice()) {
+                Some(joint_pose) => joint_pose.to_matrix(),
+                None => joint.transformation,
+            }
+        }).collect();
+        self.palette(locals.as_slice())
+    }
+
+    /// Select the matrices of the bones of `mesh` from `palette`, as
+    /// expected by `Mesh::skin`.
+    ///
+    /// `palette` is indexed by bone id, as returned by `evaluate`.
+    pub fn mesh_palette(&self, palette: &[Matrix4x4], mesh: &Mesh) -> Vec<Matrix4x4> {
+        mesh.get_bones().iter().map(|bone| {
+            match self.get_bone_id(bone.name.to_string().as_slice()) {
+                Some(id) if id < palette.len() => palette[id],
+                _ => Matrix4x4::identity(),
+            }
+        }).collect()
+    }
+
+    /// Compute the bone matrices of the skeleton in its bind pose
+    pub fn bind_pose(&self) -> Vec<Matrix4x4> {
+        let locals: Vec<Matrix4x4> = self.joints.iter()
+            .map(|joint| joint.transformation)
+            .collect();
+        self.palette(locals.as_slice())
+    }
+
+    /// Compute the node to global transformation of every joint from the
+    /// local transformations in `locals`
+    fn global_transforms(&self, locals: &[Matrix4x4]) -> Vec<Matrix4x4> {
+        let mut globals: Vec<Matrix4x4> = Vec::with_capacity(locals.len());
+        for (joint, local) in self.joints.iter().zip(locals.iter()) {
+            let global = match joint.parent {
+                Some(parent) => globals[parent] * *local,
+                None => *local,
+            };
+            globals.push(global);
+        }
+        globals
+    }
+
+    /// Compute the bone matrices from the local transformation of every
+    /// joint
+    fn palette(&self, locals: &[Matrix4x4]) -> Vec<Matrix4x4> {
+        let globals = self.global_transforms(locals);
+        let mut palette = Vec::from_elem(self.num_bones(), Matrix4x4::identity());
+        for (joint, global) in self.joints.iter().zip(globals.iter()) {
+            match joint.bone {
+                Some(id) => {
+                    palette[id] = self.global_inverse * *global * self.offsets[id];
+                }
+                None => { }
+            }
+        }
+        palette
+    }
+}
+
+/// Keys of any kind of animation channel
+trait AnimKey {
+    /// The time of the key in ticks
+    fn time(&self) -> f64;
+
+    /// A copy of the key moved to `time`
+    fn with_time(&self, time: f64) -> Self;
+}
+
+impl AnimKey for VectorKey {
+    fn time(&self) -> f64 { self.time }
+    fn with_time(&self, time: f64) -> VectorKey {
+        VectorKey { time: time, value: self.value }
+    }
+}
+
+impl AnimKey for QuatKey {
+    fn time(&self) -> f64 { self.time }
+    fn with_time(&self, time: f64) -> QuatKey {
+        QuatKey { time: time, value: self.value }
+    }
+}
+
+impl AnimKey for MeshKey {
+    fn time(&self) -> f64 { self.time }
+    fn with_time(&self, time: f64) -> MeshKey {
+        MeshKey { time: time, value: self.value }
+    }
+}
+
+/// Sample vector keys at `time`, clamping to the first and last key
+fn interpolate_vector_keys(keys: &[VectorKey], time: f64) -> VectorKey {
+    VectorKey {
+        time: time,
+        value: sample_vector_keys(keys,
+                                  AnimBehaviour::Constant,
+                                  AnimBehaviour::Constant,
+                                  time,
+                                  &keys[0].value),
+    }
+}
+
+/// Sample rotation keys at `time`, clamping to the first and last key
+fn interpolate_quat_keys(keys: &[QuatKey], time: f64) -> QuatKey {
+    QuatKey {
+        time: time,
+        value: sample_quat_keys(keys,
+                                AnimBehaviour::Constant,
+                                AnimBehaviour::Constant,
+                                time,
+                                &keys[0].value),
+    }
+}
+
+/// Sample `keys` at `times` with `interpolate` and scale the times of the
+/// new keys by `scale`
+fn resample_keys<K: AnimKey>(keys: &[K],
+                             times: &[f64],
+                             scale: f64,
+                             interpolate: |&[K], f64| -> K) -> Vec<K> {
+    let mut resampled = Vec::new();
+    if keys.is_empty() {
+        return resampled
+    }
+    for &time in times.iter() {
+        resampled.push(interpolate(keys, time).with_time(time * scale));
+    }
+    resampled
+}
+
+/// Extract the keys between `start` and `end`, adding keys sampled with
+/// `interpolate` at the boundaries and rebasing the times to `start`
+fn clip_keys<K: AnimKey>(keys: &[K],
+                         start: f64,
+                         end: f64,
+                         interpolate: |&[K], f64| -> K) -> Vec<K> {
+    let mut clipped = Vec::new();
+    if keys.is_empty() {
+        return clipped
+    }
+    clipped.push(interpolate(keys, start).with_time(0.0));
+    for key in keys.iter() {
+        if key.time() > start && key.time() < end {
+            clipped.push(key.with_time(key.time() - start));
+        }
+    }
+    if end > start {
+        clipped.push(interpolate(keys, end).with_time(end - start));
+    }
+    clipped
+}
+
+/// Extract the mesh keys between `start` and `end`, starting with the key
+/// active at `start`, and rebase the times to `start`
+fn clip_mesh_keys(keys: &[MeshKey], start: f64, end: f64) -> Vec<MeshKey> {
+    let mut clipped = Vec::new();
+    match keys.iter().filter(|key| key.time <= start).last() {
+        Some(key) => clipped.push(MeshKey { time: 0.0, value: key.value }),
+        None => { }
+    }
+    for key in keys.iter().filter(|key| key.time > start && key.time <= end) {
+        clipped.push(MeshKey { time: key.time - start, value: key.value });
+    }
+    clipped
+}
+
+/// Keys that can be interpolated, used for key reduction
+trait InterpolatedKey: AnimKey + Clone {
+    /// The difference between the value of this key and the value
+    /// interpolated from `start` to `end` at the time of this key
+    fn error(&self, start: &Self, end: &Self) -> f32;
+}
+
+/// The interpolation factor of `time` between the keys `start` and `end`
+fn key_factor<K: AnimKey>(start: &K, end: &K, time: f64) -> f32 {
+    let length = end.time() - start.time();
+    if length <= 0.0 { 0.0 } else { ((time - start.time()) / length) as f32 }
+}
+
+impl InterpolatedKey for VectorKey {
+    fn error(&self, start: &VectorKey, end: &VectorKey) -> f32 {
+        let s = key_factor(start, end, self.time);
+        (start.value.lerp(&end.value, s) - self.value).norm()
+    }
+}
+
+impl InterpolatedKey for QuatKey {
+    fn error(&self, start: &QuatKey, end: &QuatKey) -> f32 {
+        let s = key_factor(start, end, self.time);
+        let cos = start.value.slerp(&end.value, s).dot(&self.value).abs();
+        2.0 * cos.min(1.0).acos()
+    }
+}
+
+/// Remove the keys which can be interpolated from the remaining keys with
+/// an error of at most `tolerance`.
+///
+/// Greedily extends the segment starting at the last kept key for as long
+/// as all skipped keys are within the tolerance.
+fn reduce_keys<K: InterpolatedKey>(keys: &[K], tolerance: f32) -> Vec<K> {
+    if keys.len() <= 2 {
+        return keys.to_vec()
+    }
+
+    let mut kept = vec![keys[0].clone()];
+    let mut anchor = 0u;
+    let mut end = 2u;
+    while end < keys.len() {
+        let representable = range(anchor + 1, end).all(|i| {
+            keys[i].error(&keys[anchor], &keys[end]) <= tolerance
+        });
+        if !representable {
+            anchor = end - 1;
+            kept.push(keys[anchor].clone());
+        }
+        end += 1;
+    }
+    kept.push(keys[keys.len() - 1].clone());
+    kept
+}
+
+/// Where a point in time falls within a list of keys
+enum KeyPos {
+    /// The default value of the channel should be used
+    UseDefault,
+    /// Interpolate from the first key to the second by the given factor.
+    ///
+    /// The factor is outside of `[0, 1]` when extrapolating.
+    Between(uint, uint, f64),
+}
+
+/// Find the keys surrounding `time`, honouring the pre and post states of
+/// the channel.
+fn locate_key<K: AnimKey>(keys: &[K],
+                          time: f64,
+                          pre_state: AnimBehaviour,
+                          post_state: AnimBehaviour) -> KeyPos {
+    let n = keys.len();
+    if n == 0 {
+        return KeyPos::UseDefault
+    }
+    let (first, last) = (keys[0].time(), keys[n - 1].time());
+
+    if time < first {
+        match pre_state {
+            AnimBehaviour::Default => KeyPos::UseDefault,
+            AnimBehaviour::Constant => KeyPos::Between(0, 0, 0.0),
+            // duplicated times give no slope to extrapolate along
+            AnimBehaviour::Linear if n > 1 && keys[1].time() > first =>
+                KeyPos::Between(0, 1, (time - first) / (keys[1].time() - first)),
+            AnimBehaviour::Linear => KeyPos::Between(0, 0, 0.0),
+            AnimBehaviour::Repeat => locate_in_range(keys, wrap_time(time, first, last)),
+        }
+    } else if time > last {
+        match post_state {
+            AnimBehaviour::Default => KeyPos::UseDefault,
+            AnimBehaviour::Constant => KeyPos::Between(n - 1, n - 1, 0.0),
+            AnimBehaviour::Linear if n > 1 && keys[n - 2].time() < last => {
+                let prev = keys[n - 2].time();
+                KeyPos::Between(n - 2, n - 1, (time - prev) / (last - prev))
+            }
+            AnimBehaviour::Linear => KeyPos::Between(n - 1, n - 1, 0.0),
+            AnimBehaviour::Repeat => locate_in_range(keys, wrap_time(time, first, last)),
+        }
+    } else {
+        locate_in_range(keys, time)
+    }
+}
+
+/// Find the keys surrounding `time` with a binary search. `time` must be
+/// within the range of the keys.
+fn locate_in_range<K: AnimKey>(keys: &[K], time: f64) -> KeyPos {
+    // find the last key at or before `time`
+    let (mut lo, mut hi) = (0u, keys.len());
+    while hi - lo > 1 {
+        let mid = (lo + hi) / 2;
+        if keys[mid].time() <= time {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+    }
+
+    if lo + 1 >= keys.len() {
+        return KeyPos::Between(lo, lo, 0.0)
+    }
+    let (t0, t1) = (keys[lo].time(), keys[lo + 1].time());
+    let s = if t1 > t0 { (time - t0) / (t1 - t0) } else { 0.0 };
+    KeyPos::Between(lo, lo + 1, s)
+}
+
+/// Wrap `time` into the range `[first, last]`
+fn wrap_time(time: f64, first: f64, last: f64) -> f64 {
+    let length = last - first;
+    if length <= 0.0 {
+        return first
+    }
+    let offset = (time - first) % length;
+    if offset < 0.0 { last + offset } else { first + offset }
+}
+
+fn sample_vector_keys(keys: &[VectorKey],
+                      pre_state: AnimBehaviour,
+                      post_state: AnimBehaviour,
+                      time: f64,
+                      default: &Vector3D) -> Vector3D {
+    match locate_key(keys, time, pre_state, post_state) {
+        KeyPos::UseDefault => default.clone(),
+        KeyPos::Between(i, j, s) => keys[i].value.lerp(&keys[j].value, s as f32),
+    }
+}
+
+fn sample_quat_keys(keys: &[QuatKey],
+                    pre_state: AnimBehaviour,
+                    post_state: AnimBehaviour,
+                    time: f64,
+                    default: &Quaternion) -> Quaternion {
+    match locate_key(keys, time, pre_state, post_state) {
+        KeyPos::UseDefault => default.clone(),
+        KeyPos::Between(i, j, s) => keys[i].value.slerp(&keys[j].value, s as f32),
+    }
+}
+
+/// Treat `AnimBehaviour::Default` as `AnimBehaviour::Constant`, for
+/// channels sampled without a node to fall back to
+fn constant_default(state: AnimBehaviour) -> AnimBehaviour {
+    match state {
+        AnimBehaviour::Default => AnimBehaviour::Constant,
+        other => other,
+    }
+}
+
+/// Sample the position, rotation and scaling keys of a channel at `time`
+fn sample_channel(position_keys: &[VectorKey],
+                  rotation_keys: &[QuatKey],
+                  scaling_keys: &[VectorKey],
+                  pre_state: AnimBehaviour,
+                  post_state: AnimBehaviour,
+                  time: f64,
+                  default: &(Vector3D, Quaternion, Vector3D),
+                  ) -> (Vector3D, Quaternion, Vector3D) {
+    let (ref position, ref rotation, ref scaling) = *default;
+    (sample_vector_keys(position_keys, pre_state, post_state, time, position),
+     sample_quat_keys(rotation_keys, pre_state, post_state, time, rotation),
+     sample_vector_keys(scaling_keys, pre_state, post_state, time, scaling))
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::Float;
+    use std::ptr;
+
+    use std::collections::HashMap;
+
+    use test_util::vec3;
+    use types::{Vector3D, Quaternion, Matrix4x4, AiString};
+    use super::{Animation, NodeAnim, MeshAnim, VectorKey, QuatKey, MeshKey};
+    use super::AnimBehaviour;
+    use super::{Skeleton, Joint, Retargeter};
+    use super::{JointPose, JointMask, Pose};
+    use super::{OwnedNodeAnim, KeyTolerance, ClipRange};
+    use super::{parse_clip_table, clip_keys, interpolate_vector_keys};
+    use super::interpolate_quat_keys;
+    use super::OwnedAnimation;
+
+    fn node_anim(positions: &mut Vec<VectorKey>,
+                 rotations: &mut Vec<QuatKey>,
+                 behaviour: AnimBehaviour) -> NodeAnim {
+        NodeAnim {
+            name: AiString::new(),
+            num_position_keys: positions.len() as u32,
+            position_keys: positions.as_mut_ptr(),
+            num_rotation_keys: rotations.len() as u32,
+            rotation_keys: rotations.as_mut_ptr(),
+            num_scaling_keys: 0,
+            scaling_keys: ptr::null_mut(),
+            pre_state: behaviour,
+            post_state: behaviour,
+        }
+    }
+
+    impl MeshAnim {
+        /// A mesh channel over `keys`, for the tests of other modules
+        pub fn from_keys(keys: &mut Vec<MeshKey>) -> MeshAnim {
+            MeshAnim {
+                name: AiString::new(),
+                num_keys: keys.len() as u32,
+                keys: keys.as_mut_ptr(),
+            }
+        }
+    }
+
+    fn position(anim: &NodeAnim, time: f64) -> Vector3D {
+        let (pos, _, _) = anim.sample(time);
+        pos
+    }
+
+    #[test]
+    fn test_sample_interpolates() {
+        let mut positions = vec![
+            VectorKey { time: 0.0, value: vec3(0.0, 0.0, 0.0) },
+            VectorKey { time: 1.0, value: vec3(2.0, 0.0, 0.0) },
+            VectorKey { time: 3.0, value: vec3(2.0, 4.0, 0.0) },
+        ];
+        // a rotation of 2 * acos(0.9) around z, given with a negative w so
+        // the shortest path goes through the negated quaternion
+        let sin = (1.0f32 - 0.81).sqrt();
+        let mut rotations = vec![
+            QuatKey { time: 0.0, value: Quaternion::identity() },
+            QuatKey { time: 2.0, value: Quaternion { w: -0.9, x: 0.0, y: 0.0, z: -sin } },
+        ];
+        let anim = node_anim(&mut positions, &mut rotations, AnimBehaviour::Constant);
+
+        let (pos, rot, scale) = anim.sample(2.0);
+        assert_eq!(pos, vec3(2.0, 2.0, 0.0));
+        assert_eq!(scale, vec3(1.0, 1.0, 1.0));
+        assert!((rot.dot(&rotations[1].value).abs() - 1.0).abs() < 1e-5);
+
+        // halfway along the short arc to (0.9, 0, 0, sin), not the long way
+        // around to (-0.9, 0, 0, -sin)
+        let half_angle = (0.9f32).acos() * 0.5;
+        let (_, rot, _) = anim.sample(1.0);
+        assert!((rot.w - half_angle.cos()).abs() < 1e-5);
+        assert!((rot.z - half_angle.sin()).abs() < 1e-5);
+        assert!(rot.x.abs() < 1e-5 && rot.y.abs() < 1e-5);
+
+        // constant behaviour clamps to the first and last keys
+        assert_eq!(position(&anim, -1.0), vec3(0.0, 0.0, 0.0));
+        assert_eq!(position(&anim, 10.0), vec3(2.0, 4.0, 0.0));
+    }
+
+    #[test]
+    fn test_sample_behaviours() {
+        let mut positions = vec![
+            VectorKey { time: 1.0, value: vec3(0.0, 0.0, 0.0) },
+            VectorKey { time: 3.0, value: vec3(4.0, 0.0, 0.0) },
+        ];
+        let mut rotations = vec![];
+
+        let anim = node_anim(&mut positions, &mut rotations, AnimBehaviour::Repeat);
+        assert_eq!(position(&anim, 4.0), vec3(2.0, 0.0, 0.0));
+        assert_eq!(position(&anim, 0.0), vec3(2.0, 0.0, 0.0));
+
+        let anim = node_anim(&mut positions, &mut rotations, AnimBehaviour::Linear);
+        assert_eq!(position(&anim, 4.0), vec3(6.0, 0.0, 0.0));
+        assert_eq!(position(&anim, 0.0), vec3(-2.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_sample_linear_duplicate_times() {
+        // exporters sometimes repeat the time of the first or last key
+        let mut positions = vec![
+            VectorKey { time: 1.0, value: vec3(0.0, 0.0, 0.0) },
+            VectorKey { time: 1.0, value: vec3(1.0, 0.0, 0.0) },
+            VectorKey { time: 3.0, value: vec3(4.0, 0.0, 0.0) },
+            VectorKey { time: 3.0, value: vec3(5.0, 0.0, 0.0) },
+        ];
+        let mut rotations = vec![];
+        let anim = node_anim(&mut positions, &mut rotations, AnimBehaviour::Linear);
+
+        // without a slope the first and last keys are held
+        assert_eq!(position(&anim, 0.0), vec3(0.0, 0.0, 0.0));
+        assert_eq!(position(&anim, 4.0), vec3(5.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_resample() {
+        let mut positions = vec![
+            VectorKey { time: 0.0, value: vec3(0.0, 0.0, 0.0) },
+            VectorKey { time: 0.5, value: vec3(4.0, 0.0, 0.0) },
+            VectorKey { time: 3.0, value: vec3(4.0, 5.0, 0.0) },
+        ];
+        let mut rotations = vec![];
+        let mut channel = node_anim(&mut positions, &mut rotations, AnimBehaviour::Default);
+        let mut channels = vec![&mut channel as *mut NodeAnim];
+        let animation = Animation {
+            name: AiString::new(),
+            duration: 3.0,
+            ticks_per_sec: 2.0,
+            num_channels: 1,
+            channels: channels.as_mut_ptr(),
+            num_mesh_channels: 0,
+            mesh_channels: ptr::null_mut(),
+        };
+
+        let resampled = animation.resample(4.0).unwrap();
+        assert_eq!(resampled.duration, 6.0);
+        let keys = resampled.channels[0].position_keys.as_slice();
+        let times: Vec<f64> = keys.iter().map(|k| k.time).collect();
+        assert_eq!(times, vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0]);
+        assert_eq!(keys[1].value, vec3(4.0, 0.0, 0.0));
+        assert_eq!(keys[6].value, vec3(4.0, 5.0, 0.0));
+        assert!(resampled.channels[0].rotation_keys.is_empty());
+
+        assert!(animation.resample(0.0).is_err());
+        assert!(animation.resample(-30.0).is_err());
+        assert!(animation.resample(Float::nan()).is_err());
+        assert!(animation.resample(Float::infinity()).is_err());
+    }
+
+    #[test]
+    fn test_reduce_keys() {
+        let times = [0.0, 1.0, 2.0, 3.0, 4.0, 5.0];
+        let heights = [0.0, 1.0, 2.0, 3.0, 3.0, 3.0005];
+        let mut channel = OwnedNodeAnim {
+            name: "root".to_string(),
+            position_keys: times.iter().zip(heights.iter()).map(|(&t, &y)| {
+                VectorKey { time: t, value: vec3(0.0, y, 0.0) }
+            }).collect(),
+            rotation_keys: times.iter().map(|&t| {
+                QuatKey { time: t, value: Quaternion::identity() }
+            }).collect(),
+            scaling_keys: vec![],
+            pre_state: AnimBehaviour::Default,
+            post_state: AnimBehaviour::Default,
+        };
+        let tolerance = KeyTolerance { position: 0.001, rotation: 0.001, scaling: 0.001 };
+
+        assert_eq!(channel.reduce_keys(&tolerance), 7);
+        let times: Vec<f64> = channel.position_keys.iter().map(|k| k.time).collect();
+        assert_eq!(times, vec![0.0, 3.0, 5.0]);
+        assert_eq!(channel.rotation_keys.len(), 2);
+    }
+
+    #[test]
+    fn test_clip_keys() {
+        let keys = vec![
+            VectorKey { time: 0.0, value: vec3(0.0, 0.0, 0.0) },
+            VectorKey { time: 10.0, value: vec3(10.0, 0.0, 0.0) },
+            VectorKey { time: 20.0, value: vec3(10.0, 10.0, 0.0) },
+        ];
+        let clipped = clip_keys(keys.as_slice(), 5.0, 15.0, interpolate_vector_keys);
+        let times: Vec<f64> = clipped.iter().map(|k| k.time).collect();
+        let values: Vec<Vector3D> = clipped.iter().map(|k| k.value).collect();
+        assert_eq!(times, vec![0.0, 5.0, 10.0]);
+        assert_eq!(values, vec![vec3(5.0, 0.0, 0.0),
+                                vec3(10.0, 0.0, 0.0),
+                                vec3(10.0, 5.0, 0.0)]);
+
+        let half = (0.5f32).sqrt();
+        let keys = vec![
+            QuatKey { time: 0.0, value: Quaternion::identity() },
+            QuatKey { time: 4.0, value: Quaternion { w: 0.0, x: 0.0, y: 1.0, z: 0.0 } },
+        ];
+        let clipped = clip_keys(keys.as_slice(), 2.0, 6.0, interpolate_quat_keys);
+        let times: Vec<f64> = clipped.iter().map(|k| k.time).collect();
+        assert_eq!(times, vec![0.0, 2.0, 4.0]);
+        // halfway through a half turn
+        assert!((clipped[0].value.w - half).abs() < 1e-5);
+        assert!((clipped[0].value.y - half).abs() < 1e-5);
+        assert!((clipped[2].value.dot(&keys[1].value) - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_parse_clip_table() {
+        let table = "# name, start, end\n\
+                     idle, 0, 30\n\
+                     \n\
+                     walk 31 60.5 # loops\n";
+        assert_eq!(parse_clip_table(table), Ok(vec![
+            ClipRange { name: "idle".to_string(), start: 0.0, end: 30.0 },
+            ClipRange { name: "walk".to_string(), start: 31.0, end: 60.5 },
+        ]));
+        assert!(parse_clip_table("run, 10").is_err());
+        assert!(parse_clip_table("run, 10, 5").is_err());
+        assert!(parse_clip_table("run, ten, 20").is_err());
+    }
+
+    #[test]
+    fn test_extract_root_motion() {
+        let half = (0.5f32).sqrt();
+        let quarter_yaw = Quaternion { w: half, x: 0.0, y: half, z: 0.0 };
+        let mut animation = OwnedAnimation {
+            name: "walk".to_string(),
+            duration: 1.0,
+            ticks_per_sec: 1.0,
+            channels: vec![OwnedNodeAnim {
+                name: "root".to_string(),
+                position_keys: vec![
+                    VectorKey { time: 0.0, value: vec3(0.0, 1.0, 0.0) },
+                    VectorKey { time: 1.0, value: vec3(2.0, 1.5, 0.0) },
+                ],
+                rotation_keys: vec![
+                    QuatKey { time: 0.0, value: Quaternion::identity() },
+                    QuatKey { time: 1.0, value: quarter_yaw },
+                ],
+                scaling_keys: vec![],
+                pre_state: AnimBehaviour::Default,
+                post_state: AnimBehaviour::Default,
+            }],
+            mesh_channels: vec![],
+        };
+
+        let y_up = vec3(0.0, 1.0, 0.0);
+        let original = animation.clone();
+        assert!(animation.extract_root_motion("hips", &y_up).is_none());
+        let motion = animation.extract_root_motion("root", &y_up).unwrap();
+
+        let (position, rotation) = motion.sample(1.0);
+        assert_eq!(position, vec3(2.0, 0.0, 0.0));
+        assert!((rotation.dot(&quarter_yaw).abs() - 1.0).abs() < 1e-5);
+
+        let root = animation.find_channel("root").unwrap();
+        let in_place = root.position_keys[1].value;
+        assert!((in_place - vec3(0.0, 1.5, 0.0)).norm() < 1e-5);
+        assert!((root.rotation_keys[1].value.w.abs() - 1.0).abs() < 1e-5);
+
+        // with Z up, the rise along Y is horizontal movement and the turn
+        // around Y is a tilt, which stays in the animation
+        let mut animation = original;
+        let motion = animation.extract_root_motion("root", &vec3(0.0, 0.0, 2.0)).unwrap();
+        let (position, rotation) = motion.sample(1.0);
+        assert!((position - vec3(2.0, 0.5, 0.0)).norm() < 1e-5);
+        assert!((rotation.w.abs() - 1.0).abs() < 1e-5);
+        let root = animation.find_channel("root").unwrap();
+        assert!((root.rotation_keys[1].value.dot(&quarter_yaw).abs() - 1.0).abs() < 1e-5);
+    }
+
+    /// A skeleton with an unskinned root node followed by a chain of
+    /// bones, given as their local transformations. The bones are bound in
+    /// the pose given by the transformations.
+    fn skeleton(root: Matrix4x4, bones: &[(&str, Matrix4x4)]) -> Skeleton {
+        let mut joints = vec![Joint {
+            name: "root".to_string(),
+            parent: None,
+            transformation: root,
+            bind_pose: root.decompose(),
+            bone: None,
+        }];
+        let mut bind_globals = vec![root];
+        let mut bone_ids = HashMap::new();
+        let mut offsets = Vec::new();
+
+        for (i, &(name, transformation)) in bones.iter().enumerate() {
+            let global = bind_globals[i] * transformation;
+            joints.push(Joint {
+                name: name.to_string(),
+                parent: Some(i),
+                transformation: transformation,
+                bind_pose: transformation.decompose(),
+                bone: Some(i),
+            });
+            bind_globals.push(global);
+            let _ = bone_ids.insert(name.to_string(), i);
+            offsets.push(global.inverse() * root);
+        }
+
+        Skeleton {
+            joints: joints,
+            bone_ids: bone_ids,
+            offsets: offsets,
+            global_inverse: root.inverse(),
+            bind_globals: bind_globals,
+        }
+    }
+
+    fn biped() -> Skeleton {
+        let half = (0.5f32).sqrt();
+        let one = vec3(1.0, 1.0, 1.0);
+        let root = Matrix4x4::compose(&vec3(0.0, 0.0, 5.0),
+                                      &Quaternion { w: half, x: half, y: 0.0, z: 0.0 },
+                                      &vec3(2.0, 2.0, 2.0));
+        skeleton(root, &[
+            ("hips", Matrix4x4::compose(&vec3(0.0, 1.0, 0.0), &Quaternion::identity(), &one)),
+            ("spine", Matrix4x4::compose(&vec3(0.0, 0.5, 0.0),
+                                         &Quaternion { w: half, x: 0.0, y: 0.0, z: half },
+                                         &one)),
+            ("arm", Matrix4x4::compose(&vec3(0.3, 0.2, 0.0),
+                                       &Quaternion { w: half, x: 0.0, y: half, z: 0.0 },
+                                       &one)),
+        ])
+    }
+
+    /// The `biped` with every bone offset scaled by `length`, for
+    /// retargeting between skeletons of different sizes
+    fn scaled_biped(length: f32) -> Skeleton {
+        let half = (0.5f32).sqrt();
+        let one = vec3(1.0, 1.0, 1.0);
+        let root = Matrix4x4::compose(&vec3(0.0, 0.0, 5.0),
+                                      &Quaternion { w: half, x: half, y: 0.0, z: 0.0 },
+                                      &vec3(2.0, 2.0, 2.0));
+        skeleton(root, &[
+            ("hips", Matrix4x4::compose(&(vec3(0.0, 1.0, 0.0) * length),
+                                        &Quaternion::identity(), &one)),
+            ("spine", Matrix4x4::compose(&(vec3(0.0, 0.5, 0.0) * length),
+                                         &Quaternion { w: half, x: 0.0, y: 0.0, z: half },
+                                         &one)),
+            ("arm", Matrix4x4::compose(&(vec3(0.3, 0.2, 0.0) * length),
+                                       &Quaternion { w: half, x: 0.0, y: half, z: 0.0 },
+                                       &one)),
+        ])
+    }
+
+    fn assert_matrix(matrix: &Matrix4x4, expected: &Matrix4x4) {
+        let expected = expected.to_array();
+        for (row, expected) in matrix.to_array().iter().zip(expected.iter()) {
+            for (x, y) in row.iter().zip(expected.iter()) {
+                assert!((*x - *y).abs() < 1e-4);
+            }
+        }
+    }
+
+    fn assert_identity(matrix: &Matrix4x4) {
+        assert_matrix(matrix, &Matrix4x4::identity());
+    }
+
+    #[test]
+    fn test_bind_pose_palette() {
+        let skeleton = biped();
+        assert_eq!(skeleton.num_bones(), 3);
+        assert_eq!(skeleton.get_bone_id("spine"), Some(1));
+
+        // the bones are bound in the pose of their nodes, so no vertex moves
+        for matrix in skeleton.bind_pose().iter() {
+            assert_identity(matrix);
+        }
+        for matrix in skeleton.evaluate_pose(&skeleton.rest_pose()).iter() {
+            assert_identity(matrix);
+        }
+
+        // moving the hips moves every bone below them, in mesh space
+        let mut pose = skeleton.rest_pose();
+        let mut hips = *pose.get("hips").unwrap();
+        hips.position = hips.position + vec3(1.0, 0.0, 0.0);
+        pose.set("hips", hips);
+        for matrix in skeleton.evaluate_pose(&pose).iter() {
+            let moved = matrix.transform_point(&vec3(0.5, 0.0, 0.0));
+            assert!((moved - vec3(1.5, 0.0, 0.0)).norm() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_retarget_pose() {
+        let half = (0.5f32).sqrt();
+        let source = biped();
+        let joints = ["hips", "spine", "arm"];
+        let mut names = HashMap::new();
+        for name in joints.iter() {
+            let _ = names.insert(name.to_string(), name.to_string());
+        }
+        let z_up = vec3(0.0, 0.0, 1.0);
+
+        let mut pose = source.rest_pose();
+        let mut hips = *pose.get("hips").unwrap();
+        hips.position = hips.position + vec3(0.5, 0.0, 0.0);
+        pose.set("hips", hips);
+        let mut spine = *pose.get("spine").unwrap();
+        spine.rotation = Quaternion { w: half, x: half, y: 0.0, z: 0.0 } * spine.rotation;
+        pose.set("spine", spine);
+
+        // retargeting onto the same skeleton keeps the pose
+        let retargeter = Retargeter::new(&source, &source, &names, &z_up).unwrap();
+        let same = retargeter.retarget_pose(&pose);
+        for name in joints.iter() {
+            let (a, b) = (pose.get(*name).unwrap(), same.get(*name).unwrap());
+            assert!((a.position - b.position).norm() < 1e-4);
+            assert!((a.rotation.dot(&b.rotation).abs() - 1.0).abs() < 1e-4);
+        }
+
+        // a skeleton twice as tall moves its hips twice as far
+        let target = scaled_biped(2.0);
+        let retargeter = Retargeter::new(&source, &target, &names, &z_up).unwrap();
+        let tall = retargeter.retarget_pose(&pose);
+        let tall_hips = tall.get("hips").unwrap();
+        assert!((tall_hips.position - vec3(1.0, 2.0, 0.0)).norm() < 1e-4);
+        let tall_spine = tall.get("spine").unwrap();
+        assert!((tall_spine.rotation.dot(&spine.rotation).abs() - 1.0).abs() < 1e-4);
+
+        assert!(Retargeter::new(&source, &target, &names, &vec3(0.0, 0.0, 0.0)).is_err());
+        let _ = names.insert("tail".to_string(), "tail".to_string());
+        assert!(Retargeter::new(&source, &target, &names, &z_up).is_err());
+    }
+
+    #[test]
+    fn test_evaluate_channel() {
+        let skeleton = biped();
+        let half = (0.5f32).sqrt();
+
+        // the spine starts in its bind pose, moves up by 1 and turns back
+        // from 90 degrees around z to no rotation
+        let mut positions = vec![
+            VectorKey { time: 0.0, value: vec3(0.0, 0.5, 0.0) },
+            VectorKey { time: 2.0, value: vec3(0.0, 1.5, 0.0) },
+        ];
+        let mut rotations = vec![
+            QuatKey { time: 0.0, value: Quaternion { w: half, x: 0.0, y: 0.0, z: half } },
+            QuatKey { time: 2.0, value: Quaternion::identity() },
+        ];
+        let mut spine = node_anim(&mut positions, &mut rotations, AnimBehaviour::Default);
+        spine.name = AiString::from_str("spine");
+        let mut channels = vec![&mut spine as *mut NodeAnim];
+        let animation = Animation {
+            name: AiString::new(),
+            duration: 2.0,
+            ticks_per_sec: 2.0,
+            num_channels: 1,
+            channels: channels.as_mut_ptr(),
+            num_mesh_channels: 0,
+            mesh_channels: ptr::null_mut(),
+        };
+
+        // half a second is tick 1, where the spine is at (0, 1, 0) turned
+        // by 45 degrees. The spine and the arm following it move by
+        // hips * spine(1) * spine(0)^-1 * hips^-1: a rotation by -45 degrees
+        // around z about (0, 1.5, 0), followed by a move up by 0.5.
+        let expected = Matrix4x4 {
+            a1: half, a2: half, a3: 0.0, a4: -1.5 * half,
+            b1: -half, b2: half, b3: 0.0, b4: 2.0 - 1.5 * half,
+            c1: 0.0, c2: 0.0, c3: 1.0, c4: 0.0,
+            d1: 0.0, d2: 0.0, d3: 0.0, d4: 1.0,
+        };
+        let palette = skeleton.evaluate(&animation, 0.5);
+        assert_identity(&palette[0]);
+        assert_matrix(&palette[1], &expected);
+        assert_matrix(&palette[2], &expected);
+
+        // at the first key everything is in the bind pose
+        for matrix in skeleton.evaluate(&animation, 0.0).iter() {
+            assert_identity(matrix);
+        }
+    }
+
+    fn joint(x: f32, rotation: Quaternion) -> JointPose {
+        JointPose {
+            position: vec3(x, 0.0, 0.0),
+            rotation: rotation,
+            scaling: vec3(1.0, 1.0, 1.0),
+        }
+    }
+
+    #[test]
+    fn test_pose_blend() {
+        let half = (0.5f32).sqrt();
+        let quarter_turn = Quaternion { w: half, x: 0.0, y: half, z: 0.0 };
+
+        let mut a = Pose::new();
+        a.set("hips", joint(0.0, Quaternion::identity()));
+        a.set("arm", joint(0.0, Quaternion::identity()));
+        let mut b = Pose::new();
+        b.set("hips", joint(2.0, quarter_turn));
+        b.set("arm", joint(2.0, quarter_turn));
+        b.set("hand", joint(4.0, quarter_turn));
+
+        let mut mask = JointMask::new(1.0);
+        mask.set_weight("arm", 0.0);
+        let blended = a.blend(&b, 0.5, Some(&mask));
+
+        assert_eq!(blended.get("hips").unwrap().position, vec3(1.0, 0.0, 0.0));
+        assert_eq!(blended.get("arm").unwrap().position, vec3(0.0, 0.0, 0.0));
+        assert_eq!(blended.get("hand").unwrap().position, vec3(4.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_pose_add_layer() {
+        let half = (0.5f32).sqrt();
+        let quarter_turn = Quaternion { w: half, x: 0.0, y: half, z: 0.0 };
+
+        let mut base = Pose::new();
+        base.set("spine", joint(1.0, quarter_turn));
+        let mut reference = Pose::new();
+        reference.set("spine", joint(2.0, Quaternion::identity()));
+        let mut additive = Pose::new();
+        additive.set("spine", joint(3.0, quarter_turn));
+
+        let layered = base.add_layer(&additive, &reference, 1.0, None);
+        let spine = layered.get("spine").unwrap();
+        assert_eq!(spine.position, vec3(2.0, 0.0, 0.0));
+        // two quarter turns around y
+        assert!(spine.rotation.w.abs() < 1e-5);
+        assert!((spine.rotation.y.abs() - 1.0).abs() < 1e-5);
+    }
+}
+
 // vim: et tw=78 sw=4:
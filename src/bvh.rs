@@ -0,0 +1,574 @@
+//! Ray casts and closest point queries against the triangles of a scene.
+//!
+//! A `Bvh` collects the triangles of all mesh instances in the node
+//! hierarchy of a scene in global coordinates, and sorts them into a bounding
+//! volume hierarchy of axis-aligned boxes so queries only need to test the
+//! triangles near the ray or point.
+
+use std::cmp::Ordering;
+use std::num::Float;
+
+use scene::{Node, Scene};
+use types::{Aabb, Matrix4x4, Ray, Vector3D};
+
+/// The largest number of triangles in a leaf of the hierarchy
+const MAX_LEAF_TRIANGLES: uint = 4;
+
+/// The result of a query against a `Bvh`
+#[deriving(Clone)]
+pub struct Hit<'a> {
+    /// The distance from the origin of the ray or the query point
+    pub distance: f32,
+    /// The point on the triangle, in global coordinates
+    pub point: Vector3D,
+    /// The index of the mesh in the scene
+    pub mesh_index: uint,
+    /// The index of the face in the mesh
+    pub face_index: uint,
+    /// The weights of the three vertices of the triangle at `point`.
+    ///
+    /// For polygons these refer to the triangle `0, i + 1, i + 2` of the
+    /// face indices, where `i` is `triangle_index`.
+    pub barycentrics: [f32, ..3],
+    /// The triangle of a polygon face which was hit, 0 for triangles
+    pub triangle_index: uint,
+    /// The node instancing the mesh
+    pub node: &'a Node,
+}
+
+/// A triangle of a mesh instance in global coordinates
+struct Triangle<'a> {
+    vertices: [Vector3D, ..3],
+    mesh_index: uint,
+    face_index: uint,
+    triangle_index: uint,
+    node: &'a Node,
+}
+
+/// A node of the hierarchy: a leaf holding `count` triangles starting at
+/// `first`, or an inner node with children at `first` and `first + 1` if
+/// `count` is 0
+struct BvhNode {
+    bounds: Aabb,
+    first: uint,
+    count: uint,
+}
+
+/// A bounding volume hierarchy over the triangles of a scene
+pub struct Bvh<'a> {
+    triangles: Vec<Triangle<'a>>,
+    nodes: Vec<BvhNode>,
+}
+
+impl<'a> Bvh<'a> {
+    /// Build the hierarchy over the triangles and polygons of all mesh
+    /// instances in `scene`.
+    ///
+    /// Points and lines are ignored, as are faces with indices beyond the
+    /// vertices of their mesh.
+    pub fn new(scene: &'a Scene) -> Bvh<'a> {
+        let meshes = scene.get_meshes();
+        let mut triangles = Vec::new();
+        let mut stack = vec![(scene.get_root_node(), Matrix4x4::identity())];
+        loop {
+            let (node, parent) = match stack.pop() {
+                Some(entry) => entry,
+                None => break,
+            };
+            let global = parent * node.transformation;
+            for &index in node.get_meshes().iter() {
+                let mesh_index = index as uint;
+                if mesh_index >= meshes.len() {
+                    continue
+                }
+                let mesh = meshes[mesh_index];
+                add_instance(&mut triangles, node, &global, mesh_index, mesh.get_vertices(),
+                             mesh.get_faces().iter().map(|face| face.get_indices()));
+            }
+            for child in node.get_children().iter() {
+                stack.push((*child, global));
+            }
+        }
+        Bvh::from_triangles(triangles)
+    }
+
+    /// Build the hierarchy over `triangles`
+    fn from_triangles(triangles: Vec<Triangle<'a>>) -> Bvh<'a> {
+        let mut bvh = Bvh { triangles: triangles, nodes: Vec::new() };
+        if !bvh.triangles.is_empty() {
+            bvh.nodes.push(BvhNode { bounds: Aabb::empty(), first: 0, count: 0 });
+            let num_triangles = bvh.triangles.len();
+            bvh.build(0, 0, num_triangles);
+        }
+        bvh
+    }
+
+    /// The number of triangles in the hierarchy
+    pub fn num_triangles(&self) -> uint {
+        self.triangles.len()
+    }
+
+    /// The bounding box of all triangles
+    pub fn bounds(&self) -> Aabb {
+        if self.nodes.is_empty() { Aabb::empty() } else { self.nodes[0].bounds.clone() }
+    }
+
+    /// Find the closest triangle hit by `ray`, from either side.
+    ///
+    /// The direction of the ray doesn't have to be normalized, but the
+    /// distance of the hit is always in scene units.
+    pub fn raycast(&self, ray: &Ray) -> Option<Hit<'a>> {
+        let length = ray.dir.norm();
+        if self.nodes.is_empty() || !(length > 0.0) {
+            return None
+        }
+        let origin = ray.pos;
+        let dir = ray.dir * (1.0 / length);
+        let inv_dir = Vector3D { x: 1.0 / dir.x, y: 1.0 / dir.y, z: 1.0 / dir.z };
+
+        let mut best: Option<(f32, uint, f32, f32)> = None;
+        let mut stack = vec![0u];
+        loop {
+            let node = match stack.pop() {
+                Some(index) => &self.nodes[index],
+                None => break,
+            };
+            let max_t = match best {
+                Some((t, _, _, _)) => t,
+                None => Float::infinity(),
+            };
+            if !ray_hits_box(&origin, &inv_dir, &node.bounds, max_t) {
+                continue
+            }
+            if node.count == 0 {
+                stack.push(node.first);
+                stack.push(node.first + 1);
+                continue
+            }
+            for i in range(node.first, node.first + node.count) {
+                let (t, u, v) = match ray_triangle(&origin, &dir, &self.triangles[i].vertices) {
+                    Some(intersection) => intersection,
+                    None => continue,
+                };
+                let is_better = match best {
+                    Some((best_t, _, _, _)) => t < best_t,
+                    None => true,
+                };
+                if is_better {
+                    best = Some((t, i, u, v));
+                }
+            }
+        }
+
+        best.map(|(t, i, u, v)| self.hit(i, t, origin + dir * t, [1.0 - u - v, u, v]))
+    }
+
+    /// Find the point on the triangles closest to `point`.
+    ///
+    /// Returns `None` if the scene has no triangles.
+    pub fn closest_point(&self, point: &Vector3D) -> Option<Hit<'a>> {
+        if self.nodes.is_empty() {
+            return None
+        }
+
+        let mut best: Option<(f32, uint, Vector3D, [f32, ..3])> = None;
+        let mut stack = vec![0u];
+        loop {
+            let node = match stack.pop() {
+                Some(index) => &self.nodes[index],
+                None => break,
+            };
+            let max_sq = match best {
+                Some((d, _, _, _)) => d,
+                None => Float::infinity(),
+            };
+            if box_distance_sq(point, &node.bounds) > max_sq {
+                continue
+            }
+            if node.count == 0 {
+                // visit the nearer child first
+                let (a, b) = (node.first, node.first + 1);
+                if box_distance_sq(point, &self.nodes[a].bounds) <
+                   box_distance_sq(point, &self.nodes[b].bounds) {
+                    stack.push(b);
+                    stack.push(a);
+                } else {
+                    stack.push(a);
+                    stack.push(b);
+                }
+                continue
+            }
+            for i in range(node.first, node.first + node.count) {
+                let v = &self.triangles[i].vertices;
+                let weights = closest_on_triangle(point, v);
+                let closest = v[0] * weights[0] + v[1] * weights[1] + v[2] * weights[2];
+                let d = closest - *point;
+                let distance_sq = d.dot(&d);
+                let is_better = match best {
+                    Some((best_sq, _, _, _)) => distance_sq < best_sq,
+                    None => true,
+                };
+                if is_better {
+                    best = Some((distance_sq, i, closest, weights));
+                }
+            }
+        }
+
+        best.map(|(d, i, closest, weights)| self.hit(i, d.sqrt(), closest, weights))
+    }
+
+    fn hit(&self, triangle: uint, distance: f32, point: Vector3D,
+           barycentrics: [f32, ..3]) -> Hit<'a> {
+        let t = &self.triangles[triangle];
+        Hit {
+            distance: distance,
+            point: point,
+            mesh_index: t.mesh_index,
+            face_index: t.face_index,
+            barycentrics: barycentrics,
+            triangle_index: t.triangle_index,
+            node: t.node,
+        }
+    }
+
+    /// Fill the node `index` with the triangles `start..end`, splitting at
+    /// the median of the longest axis of their centroids
+    fn build(&mut self, index: uint, start: uint, end: uint) {
+        let mut bounds = Aabb::empty();
+        let mut centroids = Aabb::empty();
+        for t in self.triangles.slice(start, end).iter() {
+            for v in t.vertices.iter() {
+                bounds.add_point(v);
+            }
+            centroids.add_point(&centroid(t));
+        }
+        self.nodes[index].bounds = bounds;
+
+        let size = centroids.size();
+        if end - start <= MAX_LEAF_TRIANGLES || size.x.max(size.y).max(size.z) <= 0.0 {
+            self.nodes[index].first = start;
+            self.nodes[index].count = end - start;
+            return
+        }
+
+        let axis = if size.x >= size.y && size.x >= size.z {
+            0u
+        } else if size.y >= size.z {
+            1
+        } else {
+            2
+        };
+        self.triangles.slice_mut(start, end).sort_by(|a, b| {
+            let (ca, cb) = (component(&centroid(a), axis), component(&centroid(b), axis));
+            ca.partial_cmp(&cb).unwrap_or(Ordering::Equal)
+        });
+
+        let first = self.nodes.len();
+        self.nodes.push(BvhNode { bounds: Aabb::empty(), first: 0, count: 0 });
+        self.nodes.push(BvhNode { bounds: Aabb::empty(), first: 0, count: 0 });
+        self.nodes[index].first = first;
+        self.nodes[index].count = 0;
+
+        let middle = start + (end - start) / 2;
+        self.build(first, start, middle);
+        self.build(first + 1, middle, end);
+    }
+}
+
+/// Add the triangles of the faces of a mesh instanced by `node` with the
+/// global transformation `global`, splitting polygons into fans
+fn add_instance<'a, 'b, I: Iterator<&'b [u32]>>(triangles: &mut Vec<Triangle<'a>>,
+                                               node: &'a Node, global: &Matrix4x4,
+                                               mesh_index: uint, vertices: &[Vector3D],
+                                               faces: I) {
+    for (face_index, indices) in faces.enumerate() {
+        if indices.len() < 3 || indices.iter().any(|&i| i as uint >= vertices.len()) {
+            continue
+        }
+        for i in range(0, indices.len() - 2) {
+            let corners = [indices[0], indices[i + 1], indices[i + 2]];
+            triangles.push(Triangle {
+                vertices: [global.transform_point(&vertices[corners[0] as uint]),
+                           global.transform_point(&vertices[corners[1] as uint]),
+                           global.transform_point(&vertices[corners[2] as uint])],
+                mesh_index: mesh_index,
+                face_index: face_index,
+                triangle_index: i,
+                node: node,
+            });
+        }
+    }
+}
+
+fn centroid(t: &Triangle) -> Vector3D {
+    (t.vertices[0] + t.vertices[1] + t.vertices[2]) * (1.0 / 3.0)
+}
+
+fn component(v: &Vector3D, axis: uint) -> f32 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+/// Whether the ray enters `bounds` before `max_t`, with the slab test
+fn ray_hits_box(origin: &Vector3D, inv_dir: &Vector3D, bounds: &Aabb, max_t: f32) -> bool {
+    let mut near = 0.0f32;
+    let mut far = max_t;
+    for axis in range(0u, 3) {
+        let o = component(origin, axis);
+        let inv = component(inv_dir, axis);
+        let t0 = (component(&bounds.min, axis) - o) * inv;
+        let t1 = (component(&bounds.max, axis) - o) * inv;
+        near = near.max(t0.min(t1));
+        far = far.min(t0.max(t1));
+    }
+    near <= far
+}
+
+/// The Möller-Trumbore intersection of a ray with a unit direction and a
+/// triangle, returns the distance and the weights of the second and third
+/// vertex
+fn ray_triangle(origin: &Vector3D, dir: &Vector3D, v: &[Vector3D, ..3])
+    -> Option<(f32, f32, f32)> {
+    let e1 = v[1] - v[0];
+    let e2 = v[2] - v[0];
+    let p = dir.cross(&e2);
+    let det = e1.dot(&p);
+    if det.abs() < 1e-12 {
+        return None
+    }
+    let inv_det = 1.0 / det;
+
+    let s = *origin - v[0];
+    let u = s.dot(&p) * inv_det;
+    if u < 0.0 || u > 1.0 {
+        return None
+    }
+    let q = s.cross(&e1);
+    let w = dir.dot(&q) * inv_det;
+    if w < 0.0 || u + w > 1.0 {
+        return None
+    }
+    let t = e2.dot(&q) * inv_det;
+    if t < 0.0 {
+        return None
+    }
+    Some((t, u, w))
+}
+
+/// The squared distance of `p` to the box, 0 inside of it
+fn box_distance_sq(p: &Vector3D, bounds: &Aabb) -> f32 {
+    let mut sum = 0.0;
+    for axis in range(0u, 3) {
+        let c = component(p, axis);
+        let d = (component(&bounds.min, axis) - c).max(c - component(&bounds.max, axis));
+        if d > 0.0 {
+            sum += d * d;
+        }
+    }
+    sum
+}
+
+/// The weights of the vertices of the point on the triangle closest to `p`,
+/// following Ericson's Real-Time Collision Detection
+fn closest_on_triangle(p: &Vector3D, v: &[Vector3D, ..3]) -> [f32, ..3] {
+    let (a, b, c) = (v[0], v[1], v[2]);
+    let ab = b - a;
+    let ac = c - a;
+
+    let ap = *p - a;
+    let d1 = ab.dot(&ap);
+    let d2 = ac.dot(&ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return [1.0, 0.0, 0.0]
+    }
+
+    let bp = *p - b;
+    let d3 = ab.dot(&bp);
+    let d4 = ac.dot(&bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return [0.0, 1.0, 0.0]
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        let w = d1 / (d1 - d3);
+        return [1.0 - w, w, 0.0]
+    }
+
+    let cp = *p - c;
+    let d5 = ab.dot(&cp);
+    let d6 = ac.dot(&cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return [0.0, 0.0, 1.0]
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        let w = d2 / (d2 - d6);
+        return [1.0 - w, 0.0, w]
+    }
+
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+        return [0.0, 1.0 - w, w]
+    }
+
+    let sum = va + vb + vc;
+    if !(sum > 0.0) {
+        // a degenerate triangle
+        return [1.0, 0.0, 0.0]
+    }
+    let (w1, w2) = (vb / sum, vc / sum);
+    [1.0 - w1 - w2, w1, w2]
+}
+
+#[cfg(test)]
+mod test {
+    use std::mem;
+    use std::num::Float;
+
+    use importer::Importer;
+    use scene::Node;
+    use test_util::{vec3, grid};
+    use types::{Matrix4x4, Quaternion, Ray, Vector3D};
+    use super::{Bvh, add_instance, ray_triangle, closest_on_triangle};
+
+    /// A pseudo random point in the cube from -5 to 5
+    fn random_point(seed: &mut u32) -> Vector3D {
+        let mut coords = [0.0f32, ..3];
+        for c in coords.iter_mut() {
+            *seed = *seed * 1103515245 + 12345;
+            *c = ((*seed >> 16) & 0x7fff) as f32 / 32767.0 * 10.0 - 5.0;
+        }
+        vec3(coords[0], coords[1], coords[2])
+    }
+
+    #[test]
+    fn test_queries_match_brute_force() {
+        // the triangles only keep the address of the node
+        let node: Node = unsafe { mem::zeroed() };
+        let (vertices, indices) = grid(3);
+        let mut faces: Vec<&[u32]> = indices.chunks(3).collect();
+        let out_of_range = [0u32, 1, 100];
+        faces.push(&out_of_range);
+
+        // rotated, scaled and translated instances of the grid
+        let mut triangles = Vec::new();
+        for i in range(0u, 4) {
+            let f = i as f32;
+            let (sin, cos) = ((f * 0.4).sin(), (f * 0.4).cos());
+            let rotation = Quaternion { w: cos, x: sin * 0.6, y: 0.0, z: sin * 0.8 };
+            let global = Matrix4x4::compose(&vec3(f - 3.0, f * 0.5 - 1.0, 1.0 - f),
+                                            &rotation,
+                                            &vec3(1.0 + f * 0.5, 1.0, 1.0));
+            add_instance(&mut triangles, &node, &global, i, vertices.as_slice(),
+                         faces.iter().map(|face| *face));
+        }
+        let bvh = Bvh::from_triangles(triangles);
+        assert_eq!(bvh.num_triangles(), 4 * 18);
+        assert!(bvh.nodes.len() >= 15);
+
+        let mut seed = 12345u32;
+        for _ in range(0u, 200) {
+            let point = random_point(&mut seed);
+            let closest = bvh.triangles.iter().fold(Float::infinity(), |best: f32, t| {
+                let w = closest_on_triangle(&point, &t.vertices);
+                let p = t.vertices[0] * w[0] + t.vertices[1] * w[1] + t.vertices[2] * w[2];
+                best.min((p - point).norm())
+            });
+            let hit = bvh.closest_point(&point).unwrap();
+            assert!((hit.distance - closest).abs() < 1e-4);
+
+            let dir = (random_point(&mut seed) - point).normalize();
+            let first = bvh.triangles.iter().fold(None, |best: Option<f32>, t| {
+                match (best, ray_triangle(&point, &dir, &t.vertices)) {
+                    (Some(b), Some((d, _, _))) => Some(b.min(d)),
+                    (None, Some((d, _, _))) => Some(d),
+                    (best, None) => best,
+                }
+            });
+            match (bvh.raycast(&Ray { pos: point, dir: dir }), first) {
+                (Some(hit), Some(t)) => assert!((hit.distance - t).abs() < 1e-4),
+                (None, None) => { }
+                _ => panic!("the raycast doesn't match the brute force result"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_imported_scene() {
+        // a cube instanced by a node and, scaled down, by its child
+        let imp = Importer::new();
+        let scene = imp.import_from_file("examples/assets/nested.dae").unwrap();
+        let bvh = Bvh::new(&scene);
+
+        let mut instances = 0u;
+        let mut stack = vec![scene.get_root_node()];
+        loop {
+            let node = match stack.pop() {
+                Some(node) => node,
+                None => break,
+            };
+            stack.push_all(node.get_children());
+            let global = node.get_global_transformation();
+            for &mesh_index in node.get_meshes().iter() {
+                instances += 1;
+                let mesh = scene.get_meshes()[mesh_index as uint];
+
+                // straight down onto the center of the instance
+                let center = global.transform_point(&mesh.aabb().center());
+                let ray = Ray { pos: center + vec3(0.0, 0.0, 10.0), dir: vec3(0.0, 0.0, -1.0) };
+                let hit = bvh.raycast(&ray).unwrap();
+                assert!(hit.node as *const Node == node as *const Node);
+                assert_eq!(hit.mesh_index, mesh_index as uint);
+
+                let face = mesh.get_faces()[hit.face_index].get_indices();
+                let vertices = mesh.get_vertices();
+                let mut point = vec3(0.0, 0.0, 0.0);
+                for i in range(0u, 3) {
+                    let vertex = global.transform_point(&vertices[face[i] as uint]);
+                    point = point + vertex * hit.barycentrics[i];
+                }
+                assert!((point - hit.point).norm() < 1e-4);
+                assert!((hit.point.x - center.x).abs() < 1e-4);
+                assert!((hit.point.y - center.y).abs() < 1e-4);
+            }
+        }
+        assert_eq!(instances, 2);
+        assert_eq!(bvh.num_triangles(), 24);
+    }
+
+    #[test]
+    fn test_ray_triangle() {
+        let triangle = [vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0)];
+        let down = vec3(0.0, 0.0, -1.0);
+
+        let (t, u, v) = ray_triangle(&vec3(0.25, 0.5, 2.0), &down, &triangle).unwrap();
+        assert!((t - 2.0).abs() < 1e-6);
+        assert!((u - 0.25).abs() < 1e-6 && (v - 0.5).abs() < 1e-6);
+
+        // misses beside the triangle and behind the origin
+        assert!(ray_triangle(&vec3(1.0, 1.0, 2.0), &down, &triangle).is_none());
+        assert!(ray_triangle(&vec3(0.25, 0.25, -1.0), &down, &triangle).is_none());
+    }
+
+    #[test]
+    fn test_closest_on_triangle() {
+        let triangle = [vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0)];
+
+        // above the interior
+        let w = closest_on_triangle(&vec3(0.25, 0.25, 1.0), &triangle);
+        assert!((w[1] - 0.25).abs() < 1e-6 && (w[2] - 0.25).abs() < 1e-6);
+
+        // beyond a vertex and beyond the hypotenuse
+        assert_eq!(closest_on_triangle(&vec3(2.0, -1.0, 0.0), &triangle), [0.0, 1.0, 0.0]);
+        let w = closest_on_triangle(&vec3(1.0, 1.0, 0.0), &triangle);
+        assert!((w[1] - 0.5).abs() < 1e-6 && (w[2] - 0.5).abs() < 1e-6);
+    }
+}
+
+// vim: et tw=78 sw=4:
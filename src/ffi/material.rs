@@ -57,3 +57,10 @@ extern {
                              mapmode: *mut material::TextureMapMode /*= NULL*/,
                              flags: *mut c_uint              /*= NULL*/) -> types::Return;
 }
+
+// ASSIMP_API unsigned int aiGetMaterialTextureCount(const C_STRUCT aiMaterial* pMat,
+//                                                   C_ENUM aiTextureType type);
+extern {
+    pub fn aiGetMaterialTextureCount(aiMaterial: *const material::Material,
+                                     aiTextureType: material::TextureType) -> c_uint;
+}
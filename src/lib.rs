@@ -19,6 +19,10 @@
 
 extern crate libc;
 extern crate vecmath;
+#[cfg(feature = "image")]
+extern crate image;
+#[cfg(feature = "serialize")]
+extern crate serialize;
 
 pub use types::{Vector2D, Vector3D, Color3D, Color4D, Matrix3x3, Matrix4x4,
                 Quaternion, Plane, Ray, AiString};
@@ -31,13 +35,19 @@ pub use postprocess::Process;
 pub use importer::Importer;
 
 pub mod animation;
+pub mod bvh;
 pub mod camera;
 pub mod info;
 pub mod light;
 pub mod material;
 pub mod mesh;
+pub mod meshlet;
+pub mod optimize;
 pub mod scene;
+pub mod simplify;
+pub mod tangents;
 pub mod texture;
+pub mod topology;
 pub mod types;
 pub mod importer;
 pub mod log;
@@ -47,5 +57,7 @@ mod postprocess;
 mod util;
 mod ffi;
 mod fileio;
+#[cfg(test)]
+mod test_util;
 
 // vim: et tw=78 sw=4:
@@ -1,10 +1,16 @@
 //! Defines the material system of the library
 
 use libc::{c_uchar, c_uint, c_float};
-use std::{ptr, mem};
+use std::collections::HashMap;
+use std::hash::{Hash, Writer};
+use std::cmp::{min, Ordering};
+use std::{hash, ptr, mem};
 
-use types::{Vector2D, AiString, Return};
-use util::{ptr_ptr_to_slice, ptr_to_slice};
+#[cfg(feature = "serialize")]
+use serialize::{json, Decodable, Decoder};
+
+use types::{Vector2D, Color4D, AiString, Return};
+use util::{ptr_ptr_to_slice, ptr_to_slice, float_bits};
 use ffi;
 
 // /// Name for default materials (2nd is used if meshes have UV coords)
@@ -29,7 +35,8 @@ use ffi;
 /// ```
 ///
 /// where `diffContrib` is the intensity of the incoming light for that pixel.
-#[deriving(Show)]
+#[deriving(Clone, PartialEq, Show)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
 #[repr(C)]
 pub enum TextureOp {
     /// T = T1 * T2
@@ -54,7 +61,8 @@ pub enum TextureOp {
 /// Defines how UV coordinates outside the [0...1] range are handled.
 ///
 /// Commonly refered to as 'wrapping mode'.
-#[deriving(Show)]
+#[deriving(Clone, PartialEq, Show)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
 #[repr(C)]
 pub enum TextureMapMode {
     /// A texture coordinate `(u, v)` is translated to `(u % 1, v % 1)`
@@ -82,7 +90,8 @@ pub enum TextureMapMode {
 /// UV channels for non-UV mapped objects, as long as an accurate description
 /// how the mapping should look like (e.g spherical) is given.
 /// See the #AI_MATKEY_MAPPING property for more details.
-#[deriving(Show)]
+#[deriving(Clone, PartialEq, Show)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
 #[repr(C)]
 pub enum TextureMapping {
     /// The mapping coordinates are taken from an UV channel.
@@ -121,7 +130,8 @@ pub enum TextureMapping {
 /// In content pipelines you'll usually define how textures have to be handled,
 /// and the artists working on models have to conform to this specification,
 /// regardless which 3D tool they're using.
-#[deriving(Show)]
+#[deriving(Clone, PartialEq, Show)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
 #[repr(C)]
 pub enum TextureType {
     /// Dummy value.
@@ -201,6 +211,29 @@ pub enum TextureType {
 
 pub const AI_TEXTURE_TYPE_MAX : u32 = TextureType::Unknown as u32;
 
+impl TextureType {
+    /// Get the texture type with the given value, such as the `semantic` of
+    /// a `MaterialProperty`.
+    pub fn from_u32(n: u32) -> Option<TextureType> {
+        match n {
+            0x0 => Some(TextureType::None),
+            0x1 => Some(TextureType::Diffuse),
+            0x2 => Some(TextureType::Specular),
+            0x3 => Some(TextureType::Ambient),
+            0x4 => Some(TextureType::Emissive),
+            0x5 => Some(TextureType::Height),
+            0x6 => Some(TextureType::Normals),
+            0x7 => Some(TextureType::Shininess),
+            0x8 => Some(TextureType::Opacity),
+            0x9 => Some(TextureType::Displacement),
+            0xA => Some(TextureType::Lightmap),
+            0xB => Some(TextureType::Reflection),
+            0xC => Some(TextureType::Unknown),
+            _ => None,
+        }
+    }
+}
+
 /// Defines all shading models supported by the library
 ///
 /// The list of shading modes has been taken from Blender.
@@ -212,7 +245,8 @@ pub const AI_TEXTURE_TYPE_MAX : u32 = TextureType::Unknown as u32;
 /// Again, this value is just a hint. Assimp tries to select the shader whose
 /// most common implementation matches the original rendering results of the
 /// 3D modeller which wrote a particular model as closely as possible.
-#[deriving(Show)]
+#[deriving(Clone, PartialEq, Show)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
 #[repr(C)]
 pub enum ShadingMode {
     /// Flat shading. Shading is done on per-face base, diffuse only.
@@ -254,6 +288,25 @@ pub enum ShadingMode {
     Fresnel = 0xa,
 }
 
+impl ShadingMode {
+    /// Get the shading mode with the given value
+    pub fn from_u32(n: u32) -> Option<ShadingMode> {
+        match n {
+            0x1 => Some(ShadingMode::Flat),
+            0x2 => Some(ShadingMode::Gouraud),
+            0x3 => Some(ShadingMode::Phong),
+            0x4 => Some(ShadingMode::Blinn),
+            0x5 => Some(ShadingMode::Toon),
+            0x6 => Some(ShadingMode::OrenNayar),
+            0x7 => Some(ShadingMode::Minnaert),
+            0x8 => Some(ShadingMode::CookTorrance),
+            0x9 => Some(ShadingMode::NoShading),
+            0xa => Some(ShadingMode::Fresnel),
+            _ => None,
+        }
+    }
+}
+
 /// Defines some mixed flags for a particular texture.
 ///
 /// Usually you'll instruct your cg artists how textures have to look like ...
@@ -339,6 +392,40 @@ pub struct UVTransform {
     pub rotation: c_float,
 }
 
+/// All the information about a single texture slot of a material.
+///
+/// See `Material::get_texture_info`.
+#[deriving(Clone, PartialEq, Show)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
+pub struct TextureInfo {
+    /// The path of the texture.
+    ///
+    /// Embedded textures are referenced as `*N` where `N` is the index of
+    /// the texture in `Scene::get_textures`.
+    pub path: String,
+
+    /// How the mapping coordinates of the texture are generated.
+    pub mapping: TextureMapping,
+
+    /// The index of the UV channel used by the texture.
+    pub uv_index: u32,
+
+    /// The blend factor of the texture.
+    pub blend: f32,
+
+    /// How the texture is combined with the previous layers.
+    pub op: TextureOp,
+
+    /// The wrapping mode along the u axis.
+    pub map_mode_u: TextureMapMode,
+
+    /// The wrapping mode along the v axis.
+    pub map_mode_v: TextureMapMode,
+
+    /// A combination of the `TextureFlags` of the texture.
+    pub flags: u32,
+}
+
 /// A very primitive RTTI system for the contents of material properties.
 #[deriving(Show)]
 #[repr(C)]
@@ -424,7 +511,20 @@ pub struct MaterialProperty {
     data: *mut c_uchar,
 }
 
-//TODO handle this in a rusty way
+/// The decoded value of a `MaterialProperty`
+#[deriving(Clone, PartialEq, Show)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
+pub enum PropertyValue {
+    /// An array of floats
+    Float(Vec<f32>),
+    /// An array of integers
+    Integer(Vec<i32>),
+    /// A string
+    String(String),
+    /// A binary buffer, content undefined
+    Buffer(Vec<u8>),
+}
+
 impl MaterialProperty {
     /// Get a binary buffer that holds the property's value.
     /// The size of the buffer is always data_length.
@@ -432,6 +532,144 @@ impl MaterialProperty {
         unsafe { ptr_to_slice(self.data, self.data_length as uint) }
     }
 
+    /// Replace the data buffer of the property, returning the previous
+    /// buffer and its length.
+    ///
+    /// Assimp frees the buffer along with the property, so the buffer it
+    /// allocated has to be put back before the material is released.
+    #[doc(hidden)]
+    pub unsafe fn swap_data(&mut self, data: *mut c_uchar, data_length: c_uint)
+                            -> (*mut c_uchar, c_uint) {
+        let previous = (self.data, self.data_length);
+        self.data = data;
+        self.data_length = data_length;
+        previous
+    }
+
+    /// Get the key of the property as a `str`
+    pub fn get_key(&self) -> &str {
+        self.key.as_str().unwrap_or("")
+    }
+
+    /// Decode the value of the property according to its `type_info`
+    pub fn get_value(&self) -> PropertyValue {
+        let data = self.get_data();
+        match self.type_info {
+            PropertyTypeInfo::PtiFloat => PropertyValue::Float(
+                data.chunks(4).filter(|c| c.len() == 4)
+                    .map(|c| unsafe { mem::transmute(read_u32(c)) })
+                    .collect()),
+            PropertyTypeInfo::PtiInteger => PropertyValue::Integer(
+                data.chunks(4).filter(|c| c.len() == 4)
+                    .map(|c| read_u32(c) as i32)
+                    .collect()),
+            PropertyTypeInfo::PtiString => {
+                // strings are stored as a 32 bit length followed by the
+                // zero terminated characters
+                if data.len() < 4 {
+                    return PropertyValue::String(String::new())
+                }
+                let len = read_u32(data) as uint;
+                let end = min(4 + len, data.len());
+                PropertyValue::String(String::from_utf8_lossy(
+                        data.slice(4, end)).into_string())
+            }
+            PropertyTypeInfo::PtiBuffer => PropertyValue::Buffer(data.to_vec()),
+        }
+    }
+
+    /// Check if the property takes part in the content comparison of
+    /// materials.
+    ///
+    /// Properties with keys starting with `?` (such as the material name) are
+    /// ignored, like assimp's `Process::RemoveRedundantMaterials` step does.
+    /// Unlike that step, temporary properties starting with `~` are ignored
+    /// as well: loaders use them for their own bookkeeping, and materials
+    /// merged across files shouldn't be told apart by it.
+    fn is_content(&self) -> bool {
+        !(self.get_key().starts_with("?") || self.get_key().starts_with("~"))
+    }
+
+    /// Compare two properties by their key, semantic and index
+    fn cmp_slot(&self, other: &MaterialProperty) -> Ordering {
+        match self.get_key().cmp(other.get_key()) {
+            Ordering::Equal => (self.semantic, self.index)
+                               .cmp(&(other.semantic, other.index)),
+            ord => ord,
+        }
+    }
+
+    /// Compare the decoded contents of two properties.
+    ///
+    /// Floats are compared by their bit patterns, with `0.0` and `-0.0`
+    /// treated as the same value, so that this is an equivalence relation
+    /// that agrees with `hash_content`.
+    fn same_content(&self, other: &MaterialProperty) -> bool {
+        if self.cmp_slot(other) != Ordering::Equal {
+            return false
+        }
+        match (self.get_value(), other.get_value()) {
+            (PropertyValue::Float(a), PropertyValue::Float(b)) =>
+                a.len() == b.len() &&
+                a.iter().zip(b.iter()).all(|(x, y)| float_bits(*x) == float_bits(*y)),
+            (a, b) => a == b,
+        }
+    }
+
+    fn hash_content<S: Writer>(&self, state: &mut S) {
+        self.get_key().hash(state);
+        self.semantic.hash(state);
+        self.index.hash(state);
+        match self.get_value() {
+            PropertyValue::Float(v) => {
+                0u8.hash(state);
+                for x in v.iter() { float_bits(*x).hash(state) }
+            }
+            PropertyValue::Integer(v) => { 1u8.hash(state); v.hash(state) }
+            PropertyValue::String(v) => { 2u8.hash(state); v.hash(state) }
+            PropertyValue::Buffer(v) => { 3u8.hash(state); v.hash(state) }
+        }
+    }
+}
+
+/// Encode a property value the way assimp stores it
+fn encode_value(value: &PropertyValue) -> (PropertyTypeInfo, Vec<u8>) {
+    let mut data = Vec::new();
+    match *value {
+        PropertyValue::Float(ref v) => {
+            for x in v.iter() { data.push_all(&write_u32(unsafe { mem::transmute(*x) })) }
+            (PropertyTypeInfo::PtiFloat, data)
+        }
+        PropertyValue::Integer(ref v) => {
+            for x in v.iter() { data.push_all(&write_u32(*x as u32)) }
+            (PropertyTypeInfo::PtiInteger, data)
+        }
+        PropertyValue::String(ref v) => {
+            data.push_all(&write_u32(v.len() as u32));
+            data.push_all(v.as_bytes());
+            data.push(0);
+            (PropertyTypeInfo::PtiString, data)
+        }
+        PropertyValue::Buffer(ref v) => {
+            data.push_all(v.as_slice());
+            (PropertyTypeInfo::PtiBuffer, data)
+        }
+    }
+}
+
+#[inline]
+fn write_u32(n: u32) -> [u8, ..4] {
+    unsafe { mem::transmute(n) }
+}
+
+#[inline]
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut buf = [0u8, ..4];
+    for (dst, src) in buf.iter_mut().zip(bytes.iter()) {
+        *dst = *src;
+    }
+    // assimp stores property data in the native byte order
+    unsafe { mem::transmute(buf) }
 }
 
 /// Data structure for a material
@@ -459,6 +697,136 @@ impl Material {
         unsafe { ptr_ptr_to_slice(self.properties, self.num_properties as uint) }
     }
 
+    /// Get pointers to the material properties, for modifying them in place
+    #[doc(hidden)]
+    pub fn get_raw_properties(&self) -> &[*mut MaterialProperty] {
+        unsafe { ptr_to_slice(self.properties, self.num_properties as uint) }
+    }
+
+    /// Get the properties that define the content of the material, sorted by
+    /// key, semantic and index.
+    fn get_content_properties(&self) -> Vec<&MaterialProperty> {
+        let mut props: Vec<&MaterialProperty> = self.get_properties().iter()
+                                                    .map(|p| *p)
+                                                    .filter(|p| p.is_content())
+                                                    .collect();
+        props.sort_by(|a, b| a.cmp_slot(*b));
+        props
+    }
+
+    /// Find the property with the given key, semantic and index
+    pub fn get_property(&self,
+                        key: &str,
+                        semantic: u32,
+                        index: u32,
+                        ) -> Option<&MaterialProperty> {
+        self.get_properties().iter()
+            .find(|p| p.get_key() == key &&
+                      p.semantic == semantic &&
+                      p.index == index)
+            .map(|p| *p)
+    }
+
+    /// Get the name of the material
+    pub fn get_name(&self) -> Option<String> {
+        match self.get_property(MATKEY_NAME, 0, 0).map(|p| p.get_value()) {
+            Some(PropertyValue::String(name)) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Get the shading mode of the material
+    pub fn get_shading_mode(&self) -> Option<ShadingMode> {
+        match self.get_property(MATKEY_SHADING_MODEL, 0, 0).map(|p| p.get_value()) {
+            Some(PropertyValue::Integer(v)) =>
+                v.iter().next().and_then(|&n| ShadingMode::from_u32(n as u32)),
+            _ => None,
+        }
+    }
+
+    /// Get the number of textures of the given type
+    pub fn get_texture_count(&self, tex_type: TextureType) -> uint {
+        unsafe { ffi::aiGetMaterialTextureCount(self, tex_type) as uint }
+    }
+
+    /// Get all the information about the texture of the given type at
+    /// `index`.
+    ///
+    /// Returns `None` if the material has no such texture.
+    pub fn get_texture_info(&self,
+                            tex_type: TextureType,
+                            index: uint,
+                            ) -> Option<TextureInfo> {
+        unsafe {
+            let mut path = AiString::new();
+            let mut mapping = TextureMapping::Uv;
+            let mut uv_index = 0;
+            let mut blend = 1.0;
+            let mut op = TextureOp::Multiply;
+            let mut map_mode = [TextureMapMode::Wrap, TextureMapMode::Wrap];
+            let mut flags = 0;
+            let res = ffi::aiGetMaterialTexture(self,
+                                                tex_type,
+                                                index as c_uint,
+                                                &mut path,
+                                                &mut mapping,
+                                                &mut uv_index,
+                                                &mut blend,
+                                                &mut op,
+                                                map_mode.as_mut_ptr(),
+                                                &mut flags,
+                                               );
+            match res {
+                Return::Success => { },
+                _ => return None,
+            }
+            Some(TextureInfo {
+                path: path.into_string().unwrap_or(String::new()),
+                mapping: mapping,
+                uv_index: uv_index,
+                blend: blend,
+                op: op,
+                map_mode_u: map_mode[0],
+                map_mode_v: map_mode[1],
+                flags: flags,
+            })
+        }
+    }
+
+    /// Get a description of the material, see `MaterialDescription`
+    pub fn describe(&self) -> MaterialDescription {
+        let mut properties: Vec<PropertyDescription> =
+            self.get_content_properties().iter()
+                .filter(|p| !p.get_key().starts_with("$tex.") &&
+                            p.get_key() != MATKEY_SHADING_MODEL)
+                .map(|p| PropertyDescription {
+                    key: p.get_key().to_string(),
+                    semantic: p.semantic,
+                    index: p.index,
+                    value: p.get_value(),
+                })
+                .collect();
+        properties.sort_by(|a, b| a.slot().cmp(&b.slot()));
+
+        let mut textures = Vec::new();
+        for n in range(1, AI_TEXTURE_TYPE_MAX + 1) {
+            let tex_type = TextureType::from_u32(n).unwrap();
+            for i in range(0, self.get_texture_count(tex_type)) {
+                match self.get_texture_info(tex_type, i) {
+                    Some(info) => textures.push(TextureSlot::new(tex_type, i as u32, &info)),
+                    None => { },
+                }
+            }
+        }
+
+        MaterialDescription {
+            name: self.get_name(),
+            shading_mode: self.get_shading_mode(),
+            properties: properties,
+            textures: textures,
+        }
+    }
+
     /// Get the path of the texture
     // TODO make a nicer interface to this information
     pub fn get_texture(&self,
@@ -497,6 +865,524 @@ impl Material {
     }
 }
 
+/// A material whose property storage is owned by Rust.
+///
+/// The properties of imported materials are allocated by assimp with C++
+/// `new`, so they can't be resized from Rust. An `EditableMaterial` holds a
+/// copy of the properties in Rust memory instead, and can be read like any
+/// other material through `Deref`. Use `Scene::edit_material` to edit the
+/// materials of a scene, or `EditableMaterial::new` to build a material from
+/// scratch.
+pub struct EditableMaterial {
+    /// The material as seen by assimp, pointing into `pointers`
+    material: Material,
+    /// The property pointers of `material`
+    pointers: Vec<*mut MaterialProperty>,
+    /// The properties, boxed so their addresses don't change
+    properties: Vec<Box<MaterialProperty>>,
+    /// The data buffer of each property
+    data: Vec<Vec<u8>>,
+}
+
+impl EditableMaterial {
+    /// Create a material without any properties
+    pub fn new() -> EditableMaterial {
+        let mut material = EditableMaterial {
+            material: Material {
+                properties: ptr::null_mut(),
+                num_properties: 0,
+                num_allocated: 0,
+            },
+            pointers: Vec::new(),
+            properties: Vec::new(),
+            data: Vec::new(),
+        };
+        material.update_pointers();
+        material
+    }
+
+    /// Create a copy of all the properties of `material`
+    pub fn from_material(material: &Material) -> EditableMaterial {
+        let mut copy = EditableMaterial::new();
+        for prop in material.get_properties().iter() {
+            let mut data = prop.get_data().to_vec();
+            copy.properties.push(box MaterialProperty {
+                key: AiString::from_str(prop.get_key()),
+                semantic: prop.semantic,
+                index: prop.index,
+                data_length: data.len() as c_uint,
+                type_info: prop.type_info,
+                data: data.as_mut_ptr(),
+            });
+            copy.data.push(data);
+        }
+        copy.update_pointers();
+        copy
+    }
+
+    /// Set a property of the material, replacing the property with the same
+    /// key, semantic and index if there is one.
+    ///
+    /// Use `TextureType::None as u32` and `0` as semantic and index for
+    /// properties that don't belong to a texture.
+    pub fn set_property(&mut self,
+                        key: &str,
+                        semantic: u32,
+                        index: u32,
+                        value: &PropertyValue) {
+        let (type_info, mut data) = encode_value(value);
+        let prop = MaterialProperty {
+            key: AiString::from_str(key),
+            semantic: semantic,
+            index: index,
+            data_length: data.len() as c_uint,
+            type_info: type_info,
+            data: data.as_mut_ptr(),
+        };
+        match self.find_property_index(key, semantic, index) {
+            Some(i) => {
+                *self.properties[i] = prop;
+                self.data[i] = data;
+            }
+            None => {
+                self.properties.push(box prop);
+                self.data.push(data);
+                self.update_pointers();
+            }
+        }
+    }
+
+    /// Set a float property which doesn't belong to a texture
+    pub fn set_float(&mut self, key: &str, value: f32) {
+        self.set_property(key, 0, 0, &PropertyValue::Float(vec![value]));
+    }
+
+    /// Set an integer property which doesn't belong to a texture
+    pub fn set_integer(&mut self, key: &str, value: i32) {
+        self.set_property(key, 0, 0, &PropertyValue::Integer(vec![value]));
+    }
+
+    /// Set a color property which doesn't belong to a texture, such as
+    /// `$clr.diffuse`
+    pub fn set_color(&mut self, key: &str, value: &Color4D) {
+        let rgba = vec![value.r, value.g, value.b, value.a];
+        self.set_property(key, 0, 0, &PropertyValue::Float(rgba));
+    }
+
+    /// Set a string property which doesn't belong to a texture
+    pub fn set_string(&mut self, key: &str, value: &str) {
+        self.set_property(key, 0, 0, &PropertyValue::String(value.to_string()));
+    }
+
+    /// Set all the properties of the texture of the given type at `index`
+    pub fn set_texture(&mut self,
+                       tex_type: TextureType,
+                       index: uint,
+                       info: &TextureInfo) {
+        let (s, i) = (tex_type as u32, index as u32);
+        self.set_property(MATKEY_TEXTURE, s, i,
+                          &PropertyValue::String(info.path.clone()));
+        self.set_property(MATKEY_MAPPING, s, i,
+                          &PropertyValue::Integer(vec![info.mapping as i32]));
+        self.set_property(MATKEY_UVWSRC, s, i,
+                          &PropertyValue::Integer(vec![info.uv_index as i32]));
+        self.set_property(MATKEY_TEXBLEND, s, i,
+                          &PropertyValue::Float(vec![info.blend]));
+        self.set_property(MATKEY_TEXOP, s, i,
+                          &PropertyValue::Integer(vec![info.op as i32]));
+        self.set_property(MATKEY_MAPPINGMODE_U, s, i,
+                          &PropertyValue::Integer(vec![info.map_mode_u as i32]));
+        self.set_property(MATKEY_MAPPINGMODE_V, s, i,
+                          &PropertyValue::Integer(vec![info.map_mode_v as i32]));
+        self.set_property(MATKEY_TEXFLAGS, s, i,
+                          &PropertyValue::Integer(vec![info.flags as i32]));
+    }
+
+    /// Remove the property with the given key, semantic and index.
+    ///
+    /// Returns `false` if the material has no such property.
+    pub fn remove_property(&mut self, key: &str, semantic: u32, index: u32) -> bool {
+        match self.find_property_index(key, semantic, index) {
+            None => false,
+            Some(i) => {
+                // keep the remaining properties in order
+                let _ = self.properties.remove(i);
+                let _ = self.data.remove(i);
+                self.update_pointers();
+                true
+            }
+        }
+    }
+
+    /// Apply a description (usually an override layer) to the material.
+    ///
+    /// Only the shading mode, properties and textures present in the
+    /// description are changed, see `MaterialDescription::apply`.
+    pub fn apply_description(&mut self, desc: &MaterialDescription) {
+        match desc.shading_mode {
+            Some(mode) => self.set_property(MATKEY_SHADING_MODEL, 0, 0,
+                                            &PropertyValue::Integer(vec![mode as i32])),
+            None => { },
+        }
+        for prop in desc.properties.iter() {
+            self.set_property(prop.key.as_slice(), prop.semantic, prop.index,
+                              &prop.value);
+        }
+        for tex in desc.textures.iter() {
+            self.apply_texture_slot(tex);
+        }
+    }
+
+    /// Set the fields of a texture slot which are present in `tex`
+    fn apply_texture_slot(&mut self, tex: &TextureSlot) {
+        let (s, i) = tex.slot();
+        match tex.path {
+            Some(ref path) => self.set_property(MATKEY_TEXTURE, s, i,
+                                                &PropertyValue::String(path.clone())),
+            None => { },
+        }
+        match tex.blend {
+            Some(blend) => self.set_property(MATKEY_TEXBLEND, s, i,
+                                             &PropertyValue::Float(vec![blend])),
+            None => { },
+        }
+        let ints = [
+            (MATKEY_MAPPING, tex.mapping.map(|m| m as i32)),
+            (MATKEY_UVWSRC, tex.uv_index.map(|n| n as i32)),
+            (MATKEY_TEXOP, tex.op.map(|o| o as i32)),
+            (MATKEY_MAPPINGMODE_U, tex.map_mode_u.map(|m| m as i32)),
+            (MATKEY_MAPPINGMODE_V, tex.map_mode_v.map(|m| m as i32)),
+            (MATKEY_TEXFLAGS, tex.flags.map(|f| f as i32)),
+        ];
+        for &(key, value) in ints.iter() {
+            match value {
+                Some(v) => self.set_property(key, s, i, &PropertyValue::Integer(vec![v])),
+                None => { },
+            }
+        }
+    }
+
+    /// A pointer to the material as seen by assimp.
+    ///
+    /// The pointer stays valid as long as the `EditableMaterial` isn't moved
+    /// or dropped.
+    #[doc(hidden)]
+    pub fn as_mut_ptr(&mut self) -> *mut Material {
+        &mut self.material
+    }
+
+    fn find_property_index(&self, key: &str, semantic: u32, index: u32) -> Option<uint> {
+        self.properties.iter().position(|p| {
+            p.get_key() == key && p.semantic == semantic && p.index == index
+        })
+    }
+
+    /// Point the material at the current list of properties
+    fn update_pointers(&mut self) {
+        self.pointers = self.properties.iter_mut()
+            .map(|p| &mut **p as *mut MaterialProperty)
+            .collect();
+        self.material.properties = self.pointers.as_mut_ptr();
+        self.material.num_properties = self.pointers.len() as c_uint;
+        self.material.num_allocated = self.pointers.capacity() as c_uint;
+    }
+}
+
+impl Deref<Material> for EditableMaterial {
+    fn deref(&self) -> &Material {
+        &self.material
+    }
+}
+
+/// Materials are compared by their decoded properties.
+///
+/// The material name, other `?` properties and temporary `~` properties
+/// don't take part in the comparison, so two materials which only differ by
+/// name are equal.
+impl PartialEq for Material {
+    fn eq(&self, other: &Material) -> bool {
+        let (a, b) = (self.get_content_properties(),
+                      other.get_content_properties());
+        a.len() == b.len() &&
+        a.iter().zip(b.iter()).all(|(x, y)| x.same_content(*y))
+    }
+}
+
+impl Eq for Material {}
+
+impl<S: Writer> Hash<S> for Material {
+    fn hash(&self, state: &mut S) {
+        for prop in self.get_content_properties().iter() {
+            prop.hash_content(state);
+        }
+    }
+}
+
+/// Groups of identical materials, see `group_identical`.
+#[deriving(Clone, Show)]
+pub struct MaterialGroups {
+    /// The indices of identical materials.
+    ///
+    /// Each group is sorted and the groups are ordered by their first
+    /// material, so the first material of each group can be kept as the
+    /// representative of the group.
+    pub groups: Vec<Vec<uint>>,
+
+    /// Maps the index of each material to the index of its group.
+    ///
+    /// Use this to remap `Mesh::material_index` when merging materials.
+    pub remap: Vec<uint>,
+}
+
+/// Find the groups of identical materials in a list of materials.
+///
+/// Materials are compared by content (see the `PartialEq` implementation of
+/// `Material`). The list may contain the materials of several scenes, which
+/// allows building a shared material library across many imported files.
+pub fn group_identical(materials: &[&Material]) -> MaterialGroups {
+    let mut groups: Vec<Vec<uint>> = Vec::new();
+    let mut remap = Vec::with_capacity(materials.len());
+    // maps the hash of a material to the groups with that hash
+    let mut buckets: HashMap<u64, Vec<uint>> = HashMap::new();
+
+    for (i, mat) in materials.iter().enumerate() {
+        let key = hash::hash(*mat);
+        let candidates = match buckets.get(&key) {
+            Some(c) => c.clone(),
+            None => Vec::new(),
+        };
+        let found = candidates.iter().map(|g| *g).find(|&g| {
+            *materials[groups[g][0]] == **mat
+        });
+        match found {
+            Some(g) => {
+                groups[g].push(i);
+                remap.push(g);
+            }
+            None => {
+                let g = groups.len();
+                groups.push(vec![i]);
+                remap.push(g);
+                let mut candidates = candidates;
+                candidates.push(g);
+                let _ = buckets.insert(key, candidates);
+            }
+        }
+    }
+
+    MaterialGroups {
+        groups: groups,
+        remap: remap,
+    }
+}
+
+/// A single material property in a `MaterialDescription`
+#[deriving(Clone, PartialEq, Show)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
+pub struct PropertyDescription {
+    /// The key of the property, e.g. `$clr.diffuse`
+    pub key: String,
+    /// The semantic of the property, 0 for non-texture properties
+    pub semantic: u32,
+    /// The index of the property, 0 for non-texture properties
+    pub index: u32,
+    /// The value of the property
+    pub value: PropertyValue,
+}
+
+impl PropertyDescription {
+    fn slot(&self) -> (&str, u32, u32) {
+        (self.key.as_slice(), self.semantic, self.index)
+    }
+}
+
+/// A single texture slot in a `MaterialDescription`.
+///
+/// Every field of the texture is optional, so an override layer only has to
+/// name the fields it changes. Descriptions created by `Material::describe`
+/// always set all of them.
+#[deriving(Clone, PartialEq, Show)]
+#[cfg_attr(feature = "serialize", deriving(Encodable, Decodable))]
+pub struct TextureSlot {
+    /// The type of the texture
+    pub texture_type: TextureType,
+    /// The index of the texture within its type
+    pub index: u32,
+    /// The path of the texture, see `TextureInfo::path`
+    pub path: Option<String>,
+    /// How the mapping coordinates of the texture are generated
+    pub mapping: Option<TextureMapping>,
+    /// The index of the UV channel used by the texture
+    pub uv_index: Option<u32>,
+    /// The blend factor of the texture
+    pub blend: Option<f32>,
+    /// How the texture is combined with the previous layers
+    pub op: Option<TextureOp>,
+    /// The wrapping mode along the u axis
+    pub map_mode_u: Option<TextureMapMode>,
+    /// The wrapping mode along the v axis
+    pub map_mode_v: Option<TextureMapMode>,
+    /// A combination of the `TextureFlags` of the texture
+    pub flags: Option<u32>,
+}
+
+impl TextureSlot {
+    /// A slot which sets every field of `info`
+    pub fn new(texture_type: TextureType, index: u32, info: &TextureInfo) -> TextureSlot {
+        TextureSlot {
+            texture_type: texture_type,
+            index: index,
+            path: Some(info.path.clone()),
+            mapping: Some(info.mapping),
+            uv_index: Some(info.uv_index),
+            blend: Some(info.blend),
+            op: Some(info.op),
+            map_mode_u: Some(info.map_mode_u),
+            map_mode_v: Some(info.map_mode_v),
+            flags: Some(info.flags),
+        }
+    }
+
+    /// Replace the fields which are set in `other`
+    pub fn apply(&mut self, other: &TextureSlot) {
+        if other.path.is_some() { self.path = other.path.clone(); }
+        if other.mapping.is_some() { self.mapping = other.mapping; }
+        if other.uv_index.is_some() { self.uv_index = other.uv_index; }
+        if other.blend.is_some() { self.blend = other.blend; }
+        if other.op.is_some() { self.op = other.op; }
+        if other.map_mode_u.is_some() { self.map_mode_u = other.map_mode_u; }
+        if other.map_mode_v.is_some() { self.map_mode_v = other.map_mode_v; }
+        if other.flags.is_some() { self.flags = other.flags; }
+    }
+
+    fn slot(&self) -> (u32, u32) {
+        (self.texture_type as u32, self.index)
+    }
+}
+
+/// A human readable description of a material.
+///
+/// The description lists the decoded properties of the material, its texture
+/// slots and its shading mode in a stable order, so it can be kept under
+/// version control. With the `serialize` feature the description can be
+/// written and read as JSON (or any other format supported by
+/// `serialize::Encodable`, such as TOML).
+///
+/// A description can also act as an override layer: only the entries
+/// present in it replace the entries of the material it is applied to, see
+/// `MaterialDescription::apply`. When decoding, missing lists are treated
+/// as empty.
+#[deriving(Clone, PartialEq, Show)]
+#[cfg_attr(feature = "serialize", deriving(Encodable))]
+pub struct MaterialDescription {
+    /// The name of the material
+    pub name: Option<String>,
+    /// The shading mode of the material
+    pub shading_mode: Option<ShadingMode>,
+    /// All properties which don't describe a texture, sorted by key
+    pub properties: Vec<PropertyDescription>,
+    /// The texture slots of the material, sorted by type and index
+    pub textures: Vec<TextureSlot>,
+}
+
+impl MaterialDescription {
+    /// Apply an override layer on top of this description.
+    ///
+    /// The shading mode is replaced if the override sets one, properties are
+    /// replaced or added by key, semantic and index. Texture slots are
+    /// matched by type and index and only the fields set in the override are
+    /// replaced. The name is only used to match overrides to materials and
+    /// left untouched.
+    pub fn apply(&mut self, overrides: &MaterialDescription) {
+        if overrides.shading_mode.is_some() {
+            self.shading_mode = overrides.shading_mode.clone();
+        }
+
+        for prop in overrides.properties.iter() {
+            let pos = self.properties.iter().position(|p| p.slot() == prop.slot());
+            match pos {
+                Some(i) => self.properties[i] = prop.clone(),
+                None => self.properties.push(prop.clone()),
+            }
+        }
+        self.properties.sort_by(|a, b| a.slot().cmp(&b.slot()));
+
+        for tex in overrides.textures.iter() {
+            let pos = self.textures.iter().position(|t| t.slot() == tex.slot());
+            match pos {
+                Some(i) => self.textures[i].apply(tex),
+                None => self.textures.push(tex.clone()),
+            }
+        }
+        self.textures.sort_by(|a, b| a.slot().cmp(&b.slot()));
+    }
+
+    /// Write the description as pretty printed JSON
+    #[cfg(feature = "serialize")]
+    pub fn to_json(&self) -> String {
+        let compact = json::encode(self);
+        // re-parsing our own output can't fail
+        json::from_str(compact.as_slice()).unwrap().to_pretty_str()
+    }
+
+    /// Read a description from JSON
+    #[cfg(feature = "serialize")]
+    pub fn from_json(s: &str) -> Result<MaterialDescription, String> {
+        json::decode(s).map_err(|e| format!("invalid material description: {}", e))
+    }
+}
+
+#[cfg(feature = "serialize")]
+impl<E, D: Decoder<E>> Decodable<D, E> for MaterialDescription {
+    fn decode(d: &mut D) -> Result<MaterialDescription, E> {
+        d.read_struct("MaterialDescription", 4, |d| {
+            let name = try!(d.read_struct_field("name", 0, |d| Decodable::decode(d)));
+            let shading_mode =
+                try!(d.read_struct_field("shading_mode", 1, |d| Decodable::decode(d)));
+            // override layers usually only name a few properties or textures
+            let properties: Option<Vec<PropertyDescription>> =
+                try!(d.read_struct_field("properties", 2, |d| Decodable::decode(d)));
+            let textures: Option<Vec<TextureSlot>> =
+                try!(d.read_struct_field("textures", 3, |d| Decodable::decode(d)));
+            Ok(MaterialDescription {
+                name: name,
+                shading_mode: shading_mode,
+                properties: properties.unwrap_or(Vec::new()),
+                textures: textures.unwrap_or(Vec::new()),
+            })
+        })
+    }
+}
+
+/// Key of the material name property
+pub const MATKEY_NAME : &'static str = "?mat.name";
+
+/// Key of the shading model property, see `ShadingMode`
+pub const MATKEY_SHADING_MODEL : &'static str = "$mat.shadingm";
+
+/// Key of the texture path properties
+pub const MATKEY_TEXTURE : &'static str = "$tex.file";
+
+/// Key of the UV channel index properties of textures
+pub const MATKEY_UVWSRC : &'static str = "$tex.uvwsrc";
+
+/// Key of the texture op properties, see `TextureOp`
+pub const MATKEY_TEXOP : &'static str = "$tex.op";
+
+/// Key of the texture mapping properties, see `TextureMapping`
+pub const MATKEY_MAPPING : &'static str = "$tex.mapping";
+
+/// Key of the texture blend factor properties
+pub const MATKEY_TEXBLEND : &'static str = "$tex.blend";
+
+/// Key of the u wrapping mode properties of textures, see `TextureMapMode`
+pub const MATKEY_MAPPINGMODE_U : &'static str = "$tex.mapmodeu";
+
+/// Key of the v wrapping mode properties of textures, see `TextureMapMode`
+pub const MATKEY_MAPPINGMODE_V : &'static str = "$tex.mapmodev";
+
+/// Key of the texture flag properties, see `TextureFlags`
+pub const MATKEY_TEXFLAGS : &'static str = "$tex.flags";
 
 // #define AI_MATKEY_NAME "?mat.name",0,0
 // #define AI_MATKEY_TWOSIDED "$mat.twosided",0,0
@@ -1017,4 +1903,218 @@ impl Material {
 //                                                       C_ENUM aiTextureType type);
 
 
+#[cfg(test)]
+mod test {
+    use std::hash;
+
+    use importer::Importer;
+    use types::Color4D;
+    use super::{EditableMaterial, PropertyValue, group_identical};
+    use super::{MaterialDescription, TextureSlot, TextureInfo, TextureType};
+    use super::{TextureMapping, TextureOp, TextureMapMode};
+    use super::{MATKEY_NAME, MATKEY_TEXTURE};
+
+    fn material(name: &str, diffuse: f32) -> EditableMaterial {
+        let mut mat = EditableMaterial::new();
+        mat.set_string(MATKEY_NAME, name);
+        mat.set_color("$clr.diffuse", &Color4D { r: diffuse, g: 0.5, b: 0.5, a: 1.0 });
+        mat.set_float("$mat.shininess", 0.0);
+        mat
+    }
+
+    fn textured_description() -> MaterialDescription {
+        MaterialDescription {
+            name: Some("textured".to_string()),
+            shading_mode: None,
+            properties: Vec::new(),
+            textures: vec![TextureSlot::new(TextureType::Diffuse, 0, &TextureInfo {
+                path: "diffuse.png".to_string(),
+                mapping: TextureMapping::Uv,
+                uv_index: 1,
+                blend: 0.5,
+                op: TextureOp::Add,
+                map_mode_u: TextureMapMode::Clamp,
+                map_mode_v: TextureMapMode::Wrap,
+                flags: 0,
+            })],
+        }
+    }
+
+    fn path_override() -> TextureSlot {
+        TextureSlot {
+            texture_type: TextureType::Diffuse,
+            index: 0,
+            path: Some("other.png".to_string()),
+            mapping: None,
+            uv_index: None,
+            blend: None,
+            op: None,
+            map_mode_u: None,
+            map_mode_v: None,
+            flags: None,
+        }
+    }
+
+    #[test]
+    fn test_material_equality() {
+        let a = material("a", 1.0);
+        let mut b = material("b", 1.0);
+        b.set_integer("~tmp.id", 7);
+        b.set_string("?bg.global", "sky.png");
+        // names and temporary properties don't take part in the comparison
+        assert!(*a == *b);
+        assert_eq!(hash::hash(&*a), hash::hash(&*b));
+
+        b.set_float("$mat.shininess", -0.0);
+        assert!(*a == *b);
+        assert_eq!(hash::hash(&*a), hash::hash(&*b));
+
+        b.set_float("$mat.shininess", 0.5);
+        assert!(*a != *b);
+
+        // the order of the properties doesn't matter
+        let mut c = EditableMaterial::new();
+        c.set_float("$mat.shininess", 0.0);
+        c.set_color("$clr.diffuse", &Color4D { r: 1.0, g: 0.5, b: 0.5, a: 1.0 });
+        assert!(*a == *c);
+    }
+
+    #[test]
+    fn test_group_identical() {
+        let (a, b, c, d) = (material("a", 1.0), material("b", 0.0),
+                            material("c", 1.0), material("d", 0.0));
+        let groups = group_identical(&[&*a, &*b, &*c, &*d, &*a]);
+        assert_eq!(groups.groups, vec![vec![0, 2, 4], vec![1, 3]]);
+        assert_eq!(groups.remap, vec![0, 1, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_group_imported() {
+        // importing the same file twice gives materials with the same
+        // content at different addresses
+        let imp = Importer::new();
+        let first = imp.import_from_file("examples/assets/cube.dae").unwrap();
+        let second = imp.import_from_file("examples/assets/cube.dae").unwrap();
+        let (a, b) = (first.get_materials()[0], second.get_materials()[0]);
+        assert!(*a == *b);
+        assert_eq!(hash::hash(a), hash::hash(b));
+
+        let groups = group_identical(&[a, b, a]);
+        assert_eq!(groups.groups, vec![vec![0u, 1, 2]]);
+        assert_eq!(groups.remap, vec![0u, 0, 0]);
+    }
+
+    #[test]
+    fn test_apply_partial_description() {
+        let mut desc = textured_description();
+        desc.apply(&MaterialDescription {
+            name: None,
+            shading_mode: None,
+            properties: Vec::new(),
+            textures: vec![path_override()],
+        });
+
+        assert_eq!(desc.name, Some("textured".to_string()));
+        assert_eq!(desc.textures.len(), 1);
+        let tex = &desc.textures[0];
+        assert_eq!(tex.path, Some("other.png".to_string()));
+        // fields missing from the override are kept
+        assert_eq!(tex.uv_index, Some(1));
+        assert_eq!(tex.blend, Some(0.5));
+        assert_eq!(tex.map_mode_u, Some(TextureMapMode::Clamp));
+    }
+
+    #[test]
+    fn test_apply_description_to_material() {
+        let mut mat = material("textured", 1.0);
+        mat.apply_description(&textured_description());
+        assert_eq!(mat.describe().textures, textured_description().textures);
+
+        let overrides = MaterialDescription {
+            name: None,
+            shading_mode: None,
+            properties: Vec::new(),
+            textures: vec![path_override()],
+        };
+        let mut desc = mat.describe();
+        desc.apply(&overrides);
+        mat.apply_description(&overrides);
+        assert_eq!(mat.describe(), desc);
+
+        let info = mat.get_texture_info(TextureType::Diffuse, 0).unwrap();
+        assert_eq!(info.path.as_slice(), "other.png");
+        assert_eq!(info.uv_index, 1);
+        assert_eq!(info.blend, 0.5);
+        assert_eq!(info.map_mode_u, TextureMapMode::Clamp);
+
+        // a material built from the description of another has the same
+        // content
+        let mut copy = EditableMaterial::new();
+        copy.apply_description(&mat.describe());
+        assert!(*copy == *mat);
+    }
+
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn test_description_json() {
+        let imp = Importer::new();
+        let scene = imp.import_from_file("examples/assets/cube.dae").unwrap();
+        let desc = scene.get_materials()[0].describe();
+        let decoded = MaterialDescription::from_json(desc.to_json().as_slice()).unwrap();
+        assert_eq!(decoded, desc);
+
+        let desc = textured_description();
+        let decoded = MaterialDescription::from_json(desc.to_json().as_slice()).unwrap();
+        assert_eq!(decoded, desc);
+
+        // an override layer only names what it changes
+        let json = r#"{"textures": [{"texture_type": "Diffuse", "index": 0,
+                                     "path": "other.png"}]}"#;
+        let overrides = MaterialDescription::from_json(json).unwrap();
+        assert_eq!(overrides.name, None);
+        assert!(overrides.properties.is_empty());
+        assert_eq!(overrides.textures, vec![path_override()]);
+    }
+
+    #[test]
+    fn test_edit_scene_material() {
+        let imp = Importer::new();
+        let mut scene = imp.import_from_file("examples/assets/cube.dae").unwrap();
+        assert!(scene.num_materials > 0);
+
+        {
+            let mat = scene.edit_material(0).unwrap();
+            mat.set_color("$clr.diffuse", &Color4D { r: 1.0, g: 0.5, b: 0.25, a: 1.0 });
+            mat.set_property(MATKEY_TEXTURE, 1, 0,
+                             &PropertyValue::String("diffuse.png".to_string()));
+            mat.set_string("$test.removed", "gone");
+            assert!(mat.remove_property("$test.removed", 0, 0));
+            assert!(!mat.remove_property("$test.removed", 0, 0));
+            // grow the property list beyond its initial capacity
+            for i in range(0i32, 20) {
+                mat.set_integer(format!("$test.{}", i).as_slice(), i);
+            }
+            assert!(mat.num_properties <= mat.num_allocated);
+        }
+
+        {
+            let mat = scene.get_materials()[0];
+            assert_eq!(mat.get_property("$clr.diffuse", 0, 0).unwrap().get_value(),
+                       PropertyValue::Float(vec![1.0, 0.5, 0.25, 1.0]));
+            assert_eq!(mat.get_property(MATKEY_TEXTURE, 1, 0).unwrap().get_value(),
+                       PropertyValue::String("diffuse.png".to_string()));
+            assert!(mat.get_property("$test.removed", 0, 0).is_none());
+        }
+
+        // the copy holds the edits in memory owned by assimp, and both scenes
+        // can be released
+        let copy = scene.clone();
+        drop(scene);
+        let mat = copy.get_materials()[0];
+        assert_eq!(mat.get_property("$test.19", 0, 0).unwrap().get_value(),
+                   PropertyValue::Integer(vec![19]));
+        drop(copy);
+    }
+}
+
 // vim: et tw=78 sw=4:
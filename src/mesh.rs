@@ -1,9 +1,13 @@
 //! Declares the data structures used for imported geometry.
 
 use libc::{c_uint, c_float};
-use std::fmt;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::num::Float;
+use std::{fmt, mem};
 
-use types::{Vector3D, Color4D, Matrix4x4, AiString};
+use types::{Vector3D, Color4D, Matrix4x4, AiString, Aabb, BoundingSphere};
+use animation::MeshAnim;
 use util::{ptr_ptr_to_slice, ptr_to_slice};
 
 use mesh::PrimitiveType::{Point, Line, Triangle, Polygon};
@@ -26,6 +30,10 @@ pub const MAX_NUMBER_OF_COLOR_SETS : uint = 0x8;
 /// Supported number of texture coord sets (uv[w] channels) per mesh
 pub const MAX_NUMBER_OF_TEXTURECOORDS : uint = 0x8;
 
+/// Fraction of a vertex's total bone weight that may be dropped by
+/// `Mesh::vertex_influences` before the vertex is reported as lossy.
+pub const INFLUENCE_LOSS_THRESHOLD : f32 = 0.01;
+
 /// A single face in a mesh, referring to multiple vertices.
 ///
 /// If num_indices is 3, we call the face 'triangle', for num_indices > 3
@@ -61,7 +69,7 @@ impl Face {
 }
 
 /// A single influence of a bone on a vertex.
-#[deriving(Show)]
+#[deriving(Clone, PartialEq, Show)]
 #[repr(C)]
 pub struct VertexWeight {
     /// Index of the vertex which is influenced by the bone.
@@ -103,6 +111,7 @@ impl Bone {
 }
 
 /// Enumerates the types of geometric primitives supported by Assimp.
+#[deriving(Clone, PartialEq, Show)]
 #[repr(C)]
 pub enum PrimitiveType {
     /// A point primitive.
@@ -184,35 +193,39 @@ impl AnimMesh {
     /// animation meshes may neither add or nor remove vertex components (if a
     /// replacement array is `None` and the corresponding source array is not,
     /// the source data is taken instead)
-    pub fn get_vertices(&self) -> &[Vector3D] {
-        unsafe { ptr_to_slice(self.vertices, self.num_vertices as uint) }
+    pub fn get_vertices(&self) -> Option<&[Vector3D]> {
+        if self.vertices.is_null() {
+            None
+        } else {
+            unsafe { Some(ptr_to_slice(self.vertices, self.num_vertices as uint)) }
+        }
     }
 
     /// Replacement for Mesh normals.
-    pub fn get_normals(&self) -> &[Vector3D] {
-        let len = match self.normals.is_null() {
-            true => 0,
-            false => self.num_vertices as uint,
-        };
-        unsafe { ptr_to_slice(self.normals, len) }
+    pub fn get_normals(&self) -> Option<&[Vector3D]> {
+        if self.normals.is_null() {
+            None
+        } else {
+            unsafe { Some(ptr_to_slice(self.normals, self.num_vertices as uint)) }
+        }
     }
 
     /// Replacement for Mesh tangents.
-    pub fn get_tangents(&self) -> &[Vector3D] {
-        let len = match self.tangents.is_null() {
-            true => 0,
-            false => self.num_vertices as uint,
-        };
-        unsafe { ptr_to_slice(self.tangents, len) }
+    pub fn get_tangents(&self) -> Option<&[Vector3D]> {
+        if self.tangents.is_null() {
+            None
+        } else {
+            unsafe { Some(ptr_to_slice(self.tangents, self.num_vertices as uint)) }
+        }
     }
 
     /// Replacement for Mesh bitangents.
-    pub fn get_bitangents(&self) -> &[Vector3D] {
-        let len = match self.bitangents.is_null() {
-            true => 0,
-            false => self.num_vertices as uint,
-        };
-        unsafe { ptr_to_slice(self.bitangents, len) }
+    pub fn get_bitangents(&self) -> Option<&[Vector3D]> {
+        if self.bitangents.is_null() {
+            None
+        } else {
+            unsafe { Some(ptr_to_slice(self.bitangents, self.num_vertices as uint)) }
+        }
     }
 
     /// Replacement for Mesh colors
@@ -246,6 +259,32 @@ impl AnimMesh {
 
         list
     }
+
+    /// Check if the anim mesh replaces the vertex positions
+    pub fn has_vertices(&self) -> bool {
+        !self.vertices.is_null()
+    }
+
+    /// Check if the anim mesh replaces the vertex normals
+    pub fn has_normals(&self) -> bool {
+        !self.normals.is_null()
+    }
+
+    /// Check if the anim mesh replaces the vertex tangents and bitangents
+    pub fn has_tangents_and_bitangents(&self) -> bool {
+        !self.tangents.is_null() && !self.bitangents.is_null()
+    }
+
+    /// The number of replaced vertex color sets, see `get_colors`
+    pub fn num_color_channels(&self) -> uint {
+        self.colors.iter().take_while(|colors| !colors.is_null()).count()
+    }
+
+    /// The number of replaced texture coordinate sets, see
+    /// `get_texture_coords`
+    pub fn num_uv_channels(&self) -> uint {
+        self.texture_coords.iter().take_while(|uvs| !uvs.is_null()).count()
+    }
 }
 
 /// A mesh represents a geometry or model with a single material.
@@ -292,7 +331,7 @@ pub struct Mesh {
 
     /// Vertex normals.
     ///
-    /// The array contains normalized vectors, NULL if not present.
+    /// The array contains normalized vectors, `None` if not present.
     /// The array is num_vertices in size. Normals are undefined for
     /// point and line primitives. A mesh consisting of points and
     /// lines only may not have normal vectors. Meshes with mixed
@@ -406,15 +445,340 @@ pub struct Mesh {
     ///  * Vertex animations refer to meshes by their names.
     pub name: AiString,
 
-    /// NOT CURRENTLY IN USE. The number of attachment meshes.
+    /// The number of attachment meshes.
     pub num_anim_meshes: c_uint,
 
-    /// NOT CURRENTLY IN USE. Attachment meshes for this mesh, for vertex-based animation.
+    /// Attachment meshes for this mesh, for vertex-based animation.
     /// Attachment meshes carry replacement data for some of the mesh'es
     /// vertex components (usually positions, normals).
     anim_meshes: *mut*mut AnimMesh,
 }
 
+/// Interpolate a vertex component between two attachment meshes, using
+/// `base` for attachment meshes which are missing or don't replace the
+/// component
+fn lerp_components(base: &[Vector3D],
+                   first: Option<&[Vector3D]>,
+                   second: Option<&[Vector3D]>,
+                   s: f32) -> Vec<Vector3D> {
+    let replacement = |component: Option<&[Vector3D]>| match component {
+        Some(values) if values.len() == base.len() => values,
+        _ => base,
+    };
+    let (first, second) = (replacement(first), replacement(second));
+    first.iter().zip(second.iter()).map(|(a, b)| a.lerp(b, s)).collect()
+}
+
+/// Add the difference between `target` and `base` scaled by `weight` to
+/// `values`. Targets not replacing the component are ignored.
+fn add_target_delta(values: &mut [Vector3D],
+                    base: Option<&[Vector3D]>,
+                    target: Option<&[Vector3D]>,
+                    weight: f32) {
+    let (base, target) = match (base, target) {
+        (Some(base), Some(target)) if base.len() == target.len() => (base, target),
+        _ => return,
+    };
+    for ((value, b), t) in values.iter_mut().zip(base.iter()).zip(target.iter()) {
+        *value = *value + (*t - *b) * weight;
+    }
+}
+
+/// Copy an optional vertex component, empty if it isn't present
+fn to_vec_or_empty(values: Option<&[Vector3D]>) -> Vec<Vector3D> {
+    match values {
+        Some(values) => values.to_vec(),
+        None => Vec::new(),
+    }
+}
+
+/// Integer types which can be used for indices, see `Mesh::get_indices`
+pub trait IndexType: Copy {
+    /// Convert a vertex index, returns `None` if it doesn't fit
+    fn from_u32(value: u32) -> Option<Self>;
+}
+
+impl IndexType for u16 {
+    fn from_u32(value: u32) -> Option<u16> {
+        if value <= 0xffff { Some(value as u16) } else { None }
+    }
+}
+
+impl IndexType for u32 {
+    fn from_u32(value: u32) -> Option<u32> {
+        Some(value)
+    }
+}
+
+/// An index list using the smallest index type fitting the mesh, see
+/// `Mesh::get_index_buffer`
+#[deriving(Clone, PartialEq, Show)]
+pub enum IndexBuffer {
+    /// 16 bit indices
+    U16(Vec<u16>),
+    /// 32 bit indices
+    U32(Vec<u32>),
+}
+
+impl IndexBuffer {
+    /// The number of indices
+    pub fn len(&self) -> uint {
+        match *self {
+            IndexBuffer::U16(ref indices) => indices.len(),
+            IndexBuffer::U32(ref indices) => indices.len(),
+        }
+    }
+}
+
+/// The meaning of a vertex attribute, see `VertexLayout`
+#[deriving(Clone, PartialEq, Show)]
+pub enum Semantic {
+    /// The vertex position
+    Position,
+    /// The vertex normal
+    Normal,
+    /// The vertex tangent
+    Tangent,
+    /// The vertex bitangent
+    Bitangent,
+    /// The vertex color set with the given index
+    Color(uint),
+    /// The texture coordinate set with the given index
+    TexCoord(uint),
+    /// The ids of the bones influencing the vertex, sorted by weight
+    BoneIds,
+    /// The weights of the bones in `BoneIds`
+    BoneWeights,
+}
+
+/// The storage format of the components of a vertex attribute
+#[deriving(Clone, PartialEq, Show)]
+pub enum Format {
+    /// 32 bit float
+    F32,
+    /// 16 bit float
+    F16,
+    /// 8 bit unsigned integer, mapping `[0, 1]` to `[0, 255]`
+    Unorm8,
+    /// 16 bit signed integer, mapping `[-1, 1]` to `[-32767, 32767]`
+    Snorm16,
+    /// 8 bit unsigned integer, for bone ids
+    U8,
+    /// 16 bit unsigned integer, for bone ids
+    U16,
+    /// 32 bit unsigned integer, for bone ids
+    U32,
+}
+
+impl Format {
+    /// The size of a single component in bytes
+    pub fn size(&self) -> uint {
+        match *self {
+            Format::F32 | Format::U32 => 4,
+            Format::F16 | Format::Snorm16 | Format::U16 => 2,
+            Format::Unorm8 | Format::U8 => 1,
+        }
+    }
+
+    /// Check if the format stores integers rather than real numbers
+    pub fn is_integer(&self) -> bool {
+        match *self {
+            Format::U8 | Format::U16 | Format::U32 => true,
+            _ => false,
+        }
+    }
+}
+
+/// A single attribute of a `VertexLayout`
+#[deriving(Clone, PartialEq, Show)]
+pub struct Attribute {
+    /// What the attribute holds
+    pub semantic: Semantic,
+    /// How the components are stored
+    pub format: Format,
+    /// The number of components, from 1 to 4
+    pub components: uint,
+    /// The offset of the attribute from the start of the vertex in bytes
+    pub offset: uint,
+}
+
+/// The layout of interleaved vertices, used by `Mesh::write_vertices`.
+///
+/// Attributes are stored in the order they are added. Each attribute and
+/// the whole vertex are aligned to 4 bytes.
+#[deriving(Clone, PartialEq, Show)]
+pub struct VertexLayout {
+    attributes: Vec<Attribute>,
+    stride: uint,
+}
+
+impl VertexLayout {
+    /// Create an empty layout
+    pub fn new() -> VertexLayout {
+        VertexLayout { attributes: Vec::new(), stride: 0 }
+    }
+
+    /// Append an attribute with `components` components to the layout
+    pub fn add_attribute(&mut self, semantic: Semantic, format: Format, components: uint) {
+        let offset = self.stride;
+        self.stride = align4(offset + format.size() * components);
+        self.attributes.push(Attribute {
+            semantic: semantic,
+            format: format,
+            components: components,
+            offset: offset,
+        });
+    }
+
+    /// The attributes of the layout
+    pub fn get_attributes(&self) -> &[Attribute] {
+        self.attributes.as_slice()
+    }
+
+    /// The size of a vertex in bytes
+    pub fn get_stride(&self) -> uint {
+        self.stride
+    }
+
+    /// Find the attribute with the given semantic
+    pub fn find_attribute(&self, semantic: Semantic) -> Option<&Attribute> {
+        self.attributes.iter().find(|attribute| attribute.semantic == semantic)
+    }
+
+    /// Check that the layout can be written
+    fn validate(&self) -> Result<(), String> {
+        for attribute in self.attributes.iter() {
+            if attribute.components == 0 || attribute.components > 4 {
+                return Err(format!("{} has {} components, expected 1 to 4",
+                                   attribute.semantic, attribute.components))
+            }
+            let wants_integer = attribute.semantic == Semantic::BoneIds;
+            if attribute.format.is_integer() != wants_integer {
+                return Err(format!("{} can't be stored as {}",
+                                   attribute.semantic, attribute.format))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Round `n` up to a multiple of 4
+fn align4(n: uint) -> uint {
+    (n + 3) & !3
+}
+
+/// Shift `value` right by `shift` bits, rounding to nearest with ties to
+/// even
+fn shift_round_even(value: u32, shift: uint) -> u32 {
+    let truncated = value >> shift;
+    let rest = value & ((1 << shift) - 1);
+    let midpoint = 1 << (shift - 1);
+    if rest > midpoint || (rest == midpoint && truncated & 1 == 1) {
+        truncated + 1
+    } else {
+        truncated
+    }
+}
+
+/// Convert a float to the bits of a half precision float, rounding to
+/// nearest with ties to even
+fn f32_to_f16(value: f32) -> u16 {
+    let bits: u32 = unsafe { mem::transmute(value) };
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7fffff;
+
+    if exponent == 0xff {
+        // infinity or NaN
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        // too large, becomes infinity
+        sign | 0x7c00
+    } else if exponent <= 0 {
+        if exponent < -10 {
+            // too small, becomes zero
+            return sign
+        }
+        // subnormal
+        let mantissa = mantissa | 0x800000;
+        sign | shift_round_even(mantissa, (14 - exponent) as uint) as u16
+    } else {
+        // rounding may carry into the exponent, which is correct
+        sign | shift_round_even(((exponent as u32) << 23) | mantissa, 13) as u16
+    }
+}
+
+/// Append the components of an attribute in the given format
+fn write_components(out: &mut Vec<u8>, format: &Format, values: &[f32]) {
+    for &value in values.iter() {
+        match *format {
+            Format::F32 => {
+                let bits: u32 = unsafe { mem::transmute(value) };
+                write_le(out, bits, 4);
+            }
+            Format::F16 => write_le(out, f32_to_f16(value) as u32, 2),
+            Format::Unorm8 => {
+                let v = (value.max(0.0).min(1.0) * 255.0).round();
+                out.push(v as u8);
+            }
+            Format::Snorm16 => {
+                let v = (value.max(-1.0).min(1.0) * 32767.0).round() as i16;
+                write_le(out, v as u16 as u32, 2);
+            }
+            Format::U8 => out.push(value as u8),
+            Format::U16 => write_le(out, value as u32, 2),
+            Format::U32 => write_le(out, value as u32, 4),
+        }
+    }
+}
+
+/// Append the lowest `bytes` bytes of `value` in little endian order
+fn write_le(out: &mut Vec<u8>, value: u32, bytes: uint) {
+    for i in range(0, bytes) {
+        out.push((value >> (8 * i)) as u8);
+    }
+}
+
+/// The bone influences of every vertex of a mesh, as produced by
+/// `Mesh::vertex_influences`.
+///
+/// Each vertex has `max_influences` slots in `bone_ids` and `weights`,
+/// sorted by decreasing weight. Unused slots have bone id 0 and weight 0.
+#[deriving(Clone, Show)]
+pub struct VertexInfluences {
+    /// The number of influences stored per vertex
+    pub max_influences: uint,
+
+    /// The bone ids, `max_influences` per vertex
+    pub bone_ids: Vec<u32>,
+
+    /// The weights of the bones, `max_influences` per vertex. The weights
+    /// of a vertex sum up to one, unless it isn't influenced by any bone.
+    pub weights: Vec<f32>,
+
+    /// The vertices which had more influences than `max_influences` and
+    /// lost more than `INFLUENCE_LOSS_THRESHOLD` of their weight.
+    pub lossy_vertices: Vec<uint>,
+}
+
+impl VertexInfluences {
+    /// The bone ids influencing `vertex`
+    pub fn get_bone_ids(&self, vertex: uint) -> &[u32] {
+        let start = vertex * self.max_influences;
+        self.bone_ids.slice(start, start + self.max_influences)
+    }
+
+    /// The weights of the bones influencing `vertex`
+    pub fn get_weights(&self, vertex: uint) -> &[f32] {
+        let start = vertex * self.max_influences;
+        self.weights.slice(start, start + self.max_influences)
+    }
+}
+
 impl Mesh {
     /// Check if the mesh has a given primitive type
     pub fn has_primitive(&self, prim: PrimitiveType) -> bool {
@@ -431,7 +795,7 @@ impl Mesh {
 
     /// Vertex normals.
     ///
-    /// The array contains normalized vectors, NULL if not present.
+    /// The array contains normalized vectors, `None` if not present.
     /// The array is num_vertices in size. Normals are undefined for
     /// point and line primitives. A mesh consisting of points and
     /// lines only may not have normal vectors. Meshes with mixed
@@ -451,12 +815,12 @@ impl Mesh {
     /// Note: Normal vectors computed by Assimp are always unit-length.
     /// However, this needn't apply for normals that have been taken directly
     /// from the model file.
-    pub fn get_normals(&self) -> &[Vector3D] {
-        let len = match self.normals.is_null() {
-            true => 0,
-            false => self.num_vertices as uint,
-        };
-        unsafe { ptr_to_slice(self.normals, len) }
+    pub fn get_normals(&self) -> Option<&[Vector3D]> {
+        if self.normals.is_null() {
+            None
+        } else {
+            unsafe { Some(ptr_to_slice(self.normals, self.num_vertices as uint)) }
+        }
     }
 
     /// Vertex tangents.
@@ -472,28 +836,28 @@ impl Mesh {
     ///
     /// Note: If the mesh contains tangents, it automatically also
     /// contains bitangents.
-    pub fn get_tangents(&self) -> &[Vector3D] {
-        let len = match self.tangents.is_null() {
-            true => 0,
-            false => self.num_vertices as uint,
-        };
-        unsafe { ptr_to_slice(self.tangents, len) }
+    pub fn get_tangents(&self) -> Option<&[Vector3D]> {
+        if self.tangents.is_null() {
+            None
+        } else {
+            unsafe { Some(ptr_to_slice(self.tangents, self.num_vertices as uint)) }
+        }
     }
 
     /// Vertex bitangents.
     ///
     /// The bitangent of a vertex points in the direction of the positive
-    /// Y texture axis. The array contains normalized vectors, NULL if not
+    /// Y texture axis. The array contains normalized vectors, `None` if not
     /// present. The array is num_vertices in size.
     ///
     /// Note: If the mesh contains tangents, it automatically also contains
     /// bitangents.
-    pub fn get_bitangents(&self) -> &[Vector3D] {
-        let len = match self.bitangents.is_null() {
-            true => 0,
-            false => self.num_vertices as uint,
-        };
-        unsafe { ptr_to_slice(self.bitangents, len) }
+    pub fn get_bitangents(&self) -> Option<&[Vector3D]> {
+        if self.bitangents.is_null() {
+            None
+        } else {
+            unsafe { Some(ptr_to_slice(self.bitangents, self.num_vertices as uint)) }
+        }
     }
 
     /// Vertex color sets.
@@ -519,7 +883,7 @@ impl Mesh {
     /// A mesh may contain 0 to `MAX_NUMBER_OF_TEXTURECOORDS` per
     /// vertex. `None` if not present. The array is num_vertices in size.
     pub fn get_texture_coords(&self) -> Vec<&[Vector3D]> {
-        let mut list = Vec::with_capacity(MAX_NUMBER_OF_COLOR_SETS);
+        let mut list = Vec::with_capacity(MAX_NUMBER_OF_TEXTURECOORDS);
 
         for tex_coords in self.texture_coords.iter() {
             if tex_coords.is_null() { break; }
@@ -531,6 +895,26 @@ impl Mesh {
         list
     }
 
+    /// Check if the mesh has vertex normals
+    pub fn has_normals(&self) -> bool {
+        !self.normals.is_null()
+    }
+
+    /// Check if the mesh has vertex tangents and bitangents
+    pub fn has_tangents_and_bitangents(&self) -> bool {
+        !self.tangents.is_null() && !self.bitangents.is_null()
+    }
+
+    /// The number of vertex color sets, see `get_colors`
+    pub fn num_color_channels(&self) -> uint {
+        self.colors.iter().take_while(|colors| !colors.is_null()).count()
+    }
+
+    /// The number of texture coordinate sets, see `get_texture_coords`
+    pub fn num_uv_channels(&self) -> uint {
+        self.texture_coords.iter().take_while(|uvs| !uvs.is_null()).count()
+    }
+
     /// The faces the mesh is constructed from.
     ///
     /// Each face refers to a number of vertices by their indices.
@@ -548,6 +932,609 @@ impl Mesh {
     pub fn get_bones(&self) -> &[&Bone] {
         unsafe { ptr_ptr_to_slice(self.bones, self.num_bones as uint) }
     }
+
+    /// Collect the bone influences of every vertex.
+    ///
+    /// The weights stored in the bones are inverted into a table holding the
+    /// `max` strongest influences of each vertex. `bone_index_map` translates
+    /// the index of a bone in `get_bones` into the bone id stored in the
+    /// table, see `animation::Skeleton::mesh_bone_ids`. The remaining
+    /// weights are renormalized, vertices which lose a significant part of
+    /// their weight are reported in `VertexInfluences::lossy_vertices`.
+    ///
+    /// Returns an error if `bone_index_map` has fewer entries than the mesh
+    /// has bones.
+    ///
+    /// Use `Process::LimitBoneWeights` to let assimp limit the number of
+    /// influences while importing instead.
+    pub fn vertex_influences(&self, max: uint, bone_index_map: &[u32])
+        -> Result<VertexInfluences, String> {
+        let bones = self.get_bones();
+        if bone_index_map.len() < bones.len() {
+            return Err(format!("the bone index map has {} entries, but the mesh has {} bones",
+                               bone_index_map.len(), bones.len()))
+        }
+
+        let num_vertices = self.num_vertices as uint;
+        let mut candidates: Vec<Vec<(u32, f32)>> =
+            Vec::from_fn(num_vertices, |_| Vec::new());
+
+        for (bone, &bone_id) in bones.iter().zip(bone_index_map.iter()) {
+            for weight in bone.get_weights().iter() {
+                if weight.weight > 0.0 {
+                    candidates[weight.vertex_id as uint].push((bone_id, weight.weight));
+                }
+            }
+        }
+
+        let mut bone_ids = Vec::from_elem(num_vertices * max, 0u32);
+        let mut weights = Vec::from_elem(num_vertices * max, 0.0f32);
+        let mut lossy_vertices = Vec::new();
+
+        for (vertex, influences) in candidates.iter_mut().enumerate() {
+            influences.sort_by(|&(_, a), &(_, b)| {
+                b.partial_cmp(&a).unwrap_or(Ordering::Equal)
+            });
+
+            let total = influences.iter().fold(0.0, |sum, &(_, w)| sum + w);
+            let kept = influences.iter().take(max).fold(0.0, |sum, &(_, w)| sum + w);
+            if total > 0.0 && (total - kept) / total > INFLUENCE_LOSS_THRESHOLD {
+                lossy_vertices.push(vertex);
+            }
+
+            for (slot, &(bone_id, weight)) in influences.iter().take(max).enumerate() {
+                bone_ids[vertex * max + slot] = bone_id;
+                weights[vertex * max + slot] = weight / kept;
+            }
+        }
+
+        Ok(VertexInfluences {
+            max_influences: max,
+            bone_ids: bone_ids,
+            weights: weights,
+            lossy_vertices: lossy_vertices,
+        })
+    }
+
+    /// Attachment meshes for vertex-based animation.
+    ///
+    /// Attachment meshes carry replacement data for some of the mesh'es
+    /// vertex components (usually positions, normals). Only few importers
+    /// fill them in.
+    pub fn get_anim_meshes(&self) -> &[&AnimMesh] {
+        unsafe { ptr_ptr_to_slice(self.anim_meshes, self.num_anim_meshes as uint) }
+    }
+
+    /// Evaluate the vertex animation `anim` at `time`, given in ticks.
+    ///
+    /// Returns the vertex positions and normals interpolated between the
+    /// attachment meshes referenced by the keys of `anim`. Components which
+    /// an attachment mesh doesn't replace are taken from this mesh, and keys
+    /// referring to missing attachment meshes use this mesh. The list of
+    /// normals is empty if the mesh has no normals.
+    pub fn morph(&self, anim: &MeshAnim, time: f64) -> (Vec<Vector3D>, Vec<Vector3D>) {
+        let (first, second, s) = match anim.get_frames(time) {
+            Some(frames) => frames,
+            None => return (self.get_vertices().to_vec(),
+                            to_vec_or_empty(self.get_normals())),
+        };
+        let anim_meshes = self.get_anim_meshes();
+        let frame = |index: u32| {
+            if (index as uint) < anim_meshes.len() {
+                Some(anim_meshes[index as uint])
+            } else {
+                None
+            }
+        };
+        let (first, second) = (frame(first), frame(second));
+
+        let vertices = lerp_components(self.get_vertices(),
+                                       first.and_then(|m| m.get_vertices()),
+                                       second.and_then(|m| m.get_vertices()),
+                                       s);
+        let mut normals = match self.get_normals() {
+            Some(normals) => lerp_components(normals,
+                                             first.and_then(|m| m.get_normals()),
+                                             second.and_then(|m| m.get_normals()),
+                                             s),
+            None => Vec::new(),
+        };
+        for normal in normals.iter_mut() {
+            if normal.dot(normal) > 0.0 {
+                *normal = normal.normalize();
+            }
+        }
+        (vertices, normals)
+    }
+
+    /// Blend the attachment meshes as weighted morph targets.
+    ///
+    /// `weights` holds the weight of each attachment mesh, in the order of
+    /// `get_anim_meshes`. The difference between each attachment mesh and
+    /// this mesh is scaled by its weight and added to this mesh. Returns the
+    /// vertex positions and normals like `morph`.
+    pub fn blend_morph_targets(&self, weights: &[f32]) -> (Vec<Vector3D>, Vec<Vector3D>) {
+        let mut vertices = self.get_vertices().to_vec();
+        let mut normals = to_vec_or_empty(self.get_normals());
+
+        for (target, &weight) in self.get_anim_meshes().iter().zip(weights.iter()) {
+            if weight == 0.0 {
+                continue
+            }
+            add_target_delta(vertices.as_mut_slice(), Some(self.get_vertices()),
+                             target.get_vertices(), weight);
+            add_target_delta(normals.as_mut_slice(), self.get_normals(),
+                             target.get_normals(), weight);
+        }
+
+        for normal in normals.iter_mut() {
+            if normal.dot(normal) > 0.0 {
+                *normal = normal.normalize();
+            }
+        }
+        (vertices, normals)
+    }
+
+    /// Flatten the faces of type `primitive` into a point, line or triangle
+    /// list.
+    ///
+    /// Faces of other types are skipped. Returns an error when asking for
+    /// triangles from a mesh containing polygons, use
+    /// `Process::Triangulate` first, when asking for polygons, which
+    /// can't be stored in a list, or when an index doesn't fit into `I`.
+    pub fn get_indices<I: IndexType>(&self, primitive: PrimitiveType)
+        -> Result<Vec<I>, &'static str> {
+        let size = match primitive {
+            Point => 1,
+            Line => 2,
+            Triangle if self.has_primitive(Polygon) =>
+                return Err("the mesh contains polygons, triangulate it first"),
+            Triangle => 3,
+            Polygon => return Err("polygons can't be stored in an index list"),
+        };
+
+        let mut indices = Vec::with_capacity(self.num_faces as uint * size);
+        for face in self.get_faces().iter() {
+            let face_indices = face.get_indices();
+            if face_indices.len() != size {
+                continue
+            }
+            for &index in face_indices.iter() {
+                match IndexType::from_u32(index) {
+                    Some(index) => indices.push(index),
+                    None => return Err("an index doesn't fit into the index type"),
+                }
+            }
+        }
+        Ok(indices)
+    }
+
+    /// Flatten the faces of type `primitive` like `get_indices`, using 16
+    /// bit indices if the mesh has few enough vertices.
+    pub fn get_index_buffer(&self, primitive: PrimitiveType)
+        -> Result<IndexBuffer, &'static str> {
+        if self.num_vertices <= 0x10000 {
+            self.get_indices::<u16>(primitive).map(IndexBuffer::U16)
+        } else {
+            self.get_indices::<u32>(primitive).map(IndexBuffer::U32)
+        }
+    }
+
+    /// Append the vertices of the mesh to `out`, interleaved according to
+    /// `layout`.
+    ///
+    /// Texture coordinates use the number of components of their channel,
+    /// see `num_uv_components`, and are padded with zeros. Attributes the
+    /// mesh doesn't have are filled with zeros, except for colors which are
+    /// white. Bone ids are the indices of the bones in `get_bones`, use
+    /// `write_vertices_with_bones` to translate them.
+    ///
+    /// Returns an error if the layout is invalid, e.g. if bone ids are not
+    /// stored in an integer format, or if a bone id doesn't fit into the
+    /// format of the bone ids, e.g. with more than 256 bones stored as
+    /// `Format::U8`.
+    pub fn write_vertices(&self, layout: &VertexLayout, out: &mut Vec<u8>) -> Result<(), String> {
+        let bone_index_map: Vec<u32> = range(0, self.num_bones).collect();
+        self.write_vertices_with_bones(layout, out, bone_index_map.as_slice())
+    }
+
+    /// Append the vertices of the mesh to `out` like `write_vertices`,
+    /// translating the bone ids with `bone_index_map`, see
+    /// `vertex_influences`.
+    pub fn write_vertices_with_bones(&self,
+                                     layout: &VertexLayout,
+                                     out: &mut Vec<u8>,
+                                     bone_index_map: &[u32]) -> Result<(), String> {
+        try!(layout.validate());
+
+        let num_bones = self.num_bones as uint;
+        for attribute in layout.get_attributes().iter() {
+            if attribute.semantic != Semantic::BoneIds {
+                continue
+            }
+            let limit = match attribute.format {
+                Format::U8 => 0xff,
+                Format::U16 => 0xffff,
+                _ => continue,
+            };
+            match bone_index_map.iter().take(num_bones).max() {
+                Some(&id) if id > limit =>
+                    return Err(format!("bone id {} doesn't fit into {} bits",
+                                       id, attribute.format.size() * 8)),
+                _ => { }
+            }
+        }
+
+        let max_influences = layout.get_attributes().iter()
+            .filter(|a| a.semantic == Semantic::BoneIds || a.semantic == Semantic::BoneWeights)
+            .map(|a| a.components)
+            .max();
+        let influences = match max_influences {
+            Some(max) => Some(try!(self.vertex_influences(max, bone_index_map))),
+            None => None,
+        };
+
+        let vertices = self.get_vertices();
+        let normals = self.get_normals();
+        let tangents = self.get_tangents();
+        let bitangents = self.get_bitangents();
+        let colors = self.get_colors();
+        let tex_coords = self.get_texture_coords();
+
+        let vector = |values: Option<&[Vector3D]>, i: uint| -> [f32, ..4] {
+            match values {
+                Some(values) => [values[i].x, values[i].y, values[i].z, 0.0],
+                None => [0.0, ..4],
+            }
+        };
+
+        out.reserve(vertices.len() * layout.get_stride());
+        for i in range(0, vertices.len()) {
+            let start = out.len();
+            for attribute in layout.get_attributes().iter() {
+                // pad to the start of the attribute
+                while out.len() < start + attribute.offset {
+                    out.push(0);
+                }
+
+                let mut values = match attribute.semantic {
+                    Semantic::Position => vector(Some(vertices), i),
+                    Semantic::Normal => vector(normals, i),
+                    Semantic::Tangent => vector(tangents, i),
+                    Semantic::Bitangent => vector(bitangents, i),
+                    Semantic::Color(set) if set < colors.len() => {
+                        let c = &colors[set][i];
+                        [c.r, c.g, c.b, c.a]
+                    }
+                    Semantic::Color(_) => [1.0, ..4],
+                    Semantic::TexCoord(set) if set < tex_coords.len() => {
+                        let mut uv = vector(Some(tex_coords[set]), i);
+                        for c in range(self.num_uv_components[set] as uint, 4) {
+                            uv[c] = 0.0;
+                        }
+                        uv
+                    }
+                    Semantic::TexCoord(_) => [0.0, ..4],
+                    Semantic::BoneIds | Semantic::BoneWeights => [0.0, ..4],
+                };
+
+                if attribute.semantic == Semantic::BoneWeights {
+                    let weights = influences.as_ref().unwrap().get_weights(i);
+                    for c in range(0, attribute.components) {
+                        values[c] = weights[c];
+                    }
+                }
+
+                // bone ids may exceed the precision of a float, write them
+                // directly
+                if attribute.semantic == Semantic::BoneIds {
+                    let ids = influences.as_ref().unwrap().get_bone_ids(i);
+                    for c in range(0, attribute.components) {
+                        match attribute.format {
+                            Format::U8 => out.push(ids[c] as u8),
+                            Format::U16 => write_le(out, ids[c], 2),
+                            _ => write_le(out, ids[c], 4),
+                        }
+                    }
+                } else {
+                    write_components(out, &attribute.format,
+                                     values.slice_to(attribute.components));
+                }
+            }
+            while out.len() < start + layout.get_stride() {
+                out.push(0);
+            }
+        }
+        Ok(())
+    }
+
+    /// The axis-aligned bounding box of the vertices
+    pub fn aabb(&self) -> Aabb {
+        Aabb::from_points(self.get_vertices())
+    }
+
+    /// A sphere enclosing the vertices
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        BoundingSphere::from_points(self.get_vertices())
+    }
+
+    /// The axis-aligned bounding box of the vertices posed by the bone
+    /// matrices in `palette`, see `skin`
+    pub fn posed_aabb(&self, palette: &[Matrix4x4]) -> Aabb {
+        let vertices = self.skin_vectors(palette, Some(self.get_vertices()), true);
+        Aabb::from_points(vertices.as_slice())
+    }
+
+    /// A sphere enclosing the vertices posed by the bone matrices in
+    /// `palette`, see `skin`
+    pub fn posed_bounding_sphere(&self, palette: &[Matrix4x4]) -> BoundingSphere {
+        let vertices = self.skin_vectors(palette, Some(self.get_vertices()), true);
+        BoundingSphere::from_points(vertices.as_slice())
+    }
+
+    /// Compute the posed vertex positions and normals on the CPU.
+    ///
+    /// `palette` holds a bone matrix for each bone of this mesh, in the order
+    /// of `get_bones`, see `animation::Skeleton::mesh_palette`. Each vertex is
+    /// transformed by the matrices of the bones influencing it, weighted by
+    /// the bone weights. Vertices without any weights are left untouched.
+    ///
+    /// The normals are transformed without the translation and renormalized,
+    /// which is exact as long as the bone matrices don't contain non-uniform
+    /// scaling. The list of normals is empty if the mesh has no normals.
+    ///
+    /// Panics if `palette` has fewer matrices than the mesh has bones.
+    pub fn skin(&self, palette: &[Matrix4x4]) -> (Vec<Vector3D>, Vec<Vector3D>) {
+        (self.skin_vectors(palette, Some(self.get_vertices()), true),
+         self.skin_vectors(palette, self.get_normals(), false))
+    }
+
+    /// Compute the posed tangents and bitangents on the CPU.
+    ///
+    /// See `skin`. The lists are empty if the mesh has no tangents.
+    pub fn skin_tangents(&self, palette: &[Matrix4x4]) -> (Vec<Vector3D>, Vec<Vector3D>) {
+        (self.skin_vectors(palette, self.get_tangents(), false),
+         self.skin_vectors(palette, self.get_bitangents(), false))
+    }
+
+    /// Blend `values` by the bone matrices in `palette`. Points are
+    /// translated, directions are renormalized instead.
+    fn skin_vectors(&self,
+                    palette: &[Matrix4x4],
+                    values: Option<&[Vector3D]>,
+                    is_point: bool) -> Vec<Vector3D> {
+        assert!(palette.len() >= self.get_bones().len(),
+                "the palette has fewer matrices than the mesh has bones");
+        let values = match values {
+            Some(values) => values,
+            None => return Vec::new(),
+        };
+
+        let zero = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+        let mut blended = Vec::from_elem(values.len(), zero);
+        let mut totals = Vec::from_elem(values.len(), 0.0f32);
+
+        for (bone, matrix) in self.get_bones().iter().zip(palette.iter()) {
+            for weight in bone.get_weights().iter() {
+                let id = weight.vertex_id as uint;
+                let value = if is_point {
+                    matrix.transform_point(&values[id])
+                } else {
+                    matrix.transform_vector(&values[id])
+                };
+                blended[id] = blended[id] + value * weight.weight;
+                totals[id] += weight.weight;
+            }
+        }
+
+        for (i, total) in totals.iter().enumerate() {
+            if *total <= 0.0 {
+                blended[i] = values[i];
+            } else if is_point {
+                blended[i] = blended[i] * (1.0 / *total);
+            } else if blended[i].dot(&blended[i]) > 0.0 {
+                blended[i] = blended[i].normalize();
+            }
+        }
+        blended
+    }
+}
+
+/// A bone of an `OwnedMesh`
+#[deriving(Clone, Show)]
+pub struct OwnedBone {
+    /// The name of the bone
+    pub name: String,
+    /// The vertices affected by this bone
+    pub weights: Vec<VertexWeight>,
+    /// Matrix that transforms from mesh space to bone space in bind pose
+    pub offset_matrix: Matrix4x4,
+}
+
+/// A triangle mesh owning its data, which unlike `Mesh` can be modified
+/// and outlives the `Scene` it was created from.
+///
+/// Optional vertex components are empty if not present; if present they
+/// have one entry per vertex.
+#[deriving(Clone, Show)]
+pub struct OwnedMesh {
+    /// The name of the mesh
+    pub name: String,
+    /// The vertex positions
+    pub vertices: Vec<Vector3D>,
+    /// The vertex normals
+    pub normals: Vec<Vector3D>,
+    /// The vertex tangents, with the handedness of the tangent space in
+    /// `w`: the bitangent is `w * cross(normal, tangent)`.
+    pub tangents: Vec<[f32, ..4]>,
+    /// The vertex color sets
+    pub colors: Vec<Vec<Color4D>>,
+    /// The texture coordinate sets
+    pub texture_coords: Vec<Vec<Vector3D>>,
+    /// The number of components of each texture coordinate set
+    pub num_uv_components: Vec<u32>,
+    /// The bones of the mesh
+    pub bones: Vec<OwnedBone>,
+    /// The triangles of the mesh, three indices per triangle
+    pub indices: Vec<u32>,
+    /// The index of the material used by this mesh
+    pub material_index: u32,
+}
+
+impl OwnedMesh {
+    /// Copy the triangles of `mesh`.
+    ///
+    /// Points and lines are dropped. The tangents are converted from the
+    /// tangents and bitangents of the mesh, if present. Returns an error if
+    /// the mesh contains polygons, see `Mesh::get_indices`.
+    pub fn from_mesh(mesh: &Mesh) -> Result<OwnedMesh, &'static str> {
+        let indices = try!(mesh.get_indices::<u32>(Triangle));
+
+        let tangents = match (mesh.get_normals(), mesh.get_tangents(), mesh.get_bitangents()) {
+            (Some(normals), Some(tangents), Some(bitangents)) => {
+                range(0, tangents.len()).map(|i| {
+                    let (n, t, b) = (&normals[i], &tangents[i], &bitangents[i]);
+                    let w = if n.cross(t).dot(b) < 0.0 { -1.0 } else { 1.0 };
+                    [t.x, t.y, t.z, w]
+                }).collect()
+            }
+            _ => Vec::new(),
+        };
+
+        let num_uv_channels = mesh.num_uv_channels();
+        Ok(OwnedMesh {
+            name: mesh.name.to_string(),
+            vertices: mesh.get_vertices().to_vec(),
+            normals: to_vec_or_empty(mesh.get_normals()),
+            tangents: tangents,
+            colors: mesh.get_colors().iter().map(|c| c.to_vec()).collect(),
+            texture_coords: mesh.get_texture_coords().iter().map(|uv| uv.to_vec()).collect(),
+            num_uv_components: mesh.num_uv_components.slice_to(num_uv_channels).to_vec(),
+            bones: mesh.get_bones().iter().map(|bone| {
+                OwnedBone {
+                    name: bone.name.to_string(),
+                    weights: bone.get_weights().to_vec(),
+                    offset_matrix: bone.offset_matrix,
+                }
+            }).collect(),
+            indices: indices,
+            material_index: mesh.material_index,
+        })
+    }
+
+    /// The number of vertices
+    pub fn num_vertices(&self) -> uint {
+        self.vertices.len()
+    }
+
+    /// The number of triangles
+    pub fn num_triangles(&self) -> uint {
+        self.indices.len() / 3
+    }
+
+    /// Remove the vertices not referenced by any triangle, keeping the order
+    /// of the remaining vertices
+    pub fn remove_unused_vertices(&mut self) {
+        let mut remap: Vec<Option<u32>> = Vec::from_elem(self.vertices.len(), None);
+        for &index in self.indices.iter() {
+            remap[index as uint] = Some(0);
+        }
+        let mut next = 0u32;
+        for entry in remap.iter_mut() {
+            if entry.is_some() {
+                *entry = Some(next);
+                next += 1;
+            }
+        }
+        if next as uint == self.vertices.len() {
+            return
+        }
+
+        fn retain<T: Clone>(values: &mut Vec<T>, remap: &[Option<u32>]) {
+            if values.is_empty() {
+                return
+            }
+            let kept = values.iter().zip(remap.iter())
+                .filter(|&(_, r)| r.is_some())
+                .map(|(v, _)| v.clone())
+                .collect();
+            *values = kept;
+        }
+        retain(&mut self.vertices, remap.as_slice());
+        retain(&mut self.normals, remap.as_slice());
+        retain(&mut self.tangents, remap.as_slice());
+        for colors in self.colors.iter_mut() {
+            retain(colors, remap.as_slice());
+        }
+        for uvs in self.texture_coords.iter_mut() {
+            retain(uvs, remap.as_slice());
+        }
+        for bone in self.bones.iter_mut() {
+            bone.weights = bone.weights.iter().filter_map(|w| {
+                remap[w.vertex_id as uint].map(|id| {
+                    VertexWeight { vertex_id: id, weight: w.weight }
+                })
+            }).collect();
+        }
+        for index in self.indices.iter_mut() {
+            *index = remap[*index as uint].unwrap();
+        }
+    }
+
+    /// Append copies of the vertices in `sources` with all their components
+    /// and bone weights.
+    ///
+    /// The copy of `sources[i]` gets the index `num_vertices() + i`, where
+    /// `num_vertices()` is taken before the call.
+    pub fn duplicate_vertices(&mut self, sources: &[u32]) {
+        let first = self.vertices.len() as u32;
+        for &source in sources.iter() {
+            let source = source as uint;
+            let vertex = self.vertices[source];
+            self.vertices.push(vertex);
+            if !self.normals.is_empty() {
+                let normal = self.normals[source];
+                self.normals.push(normal);
+            }
+            if !self.tangents.is_empty() {
+                let tangent = self.tangents[source];
+                self.tangents.push(tangent);
+            }
+            for colors in self.colors.iter_mut() {
+                let color = colors[source];
+                colors.push(color);
+            }
+            for uvs in self.texture_coords.iter_mut() {
+                let uv = uvs[source];
+                uvs.push(uv);
+            }
+        }
+
+        if self.bones.is_empty() {
+            return
+        }
+        let mut copies: HashMap<u32, Vec<u32>> = HashMap::new();
+        for (i, &source) in sources.iter().enumerate() {
+            if !copies.contains_key(&source) {
+                let _ = copies.insert(source, Vec::new());
+            }
+            copies.get_mut(&source).unwrap().push(first + i as u32);
+        }
+        for bone in self.bones.iter_mut() {
+            let mut added = Vec::new();
+            for weight in bone.weights.iter() {
+                match copies.get(&weight.vertex_id) {
+                    Some(targets) => {
+                        for &target in targets.iter() {
+                            added.push(VertexWeight { vertex_id: target, weight: weight.weight });
+                        }
+                    }
+                    None => { }
+                }
+            }
+            bone.weights.push_all(added.as_slice());
+        }
+    }
 }
 
 impl fmt::Show for Mesh {
@@ -567,4 +1554,488 @@ impl fmt::Show for Mesh {
     }
 }
 
+#[cfg(test)]
+mod test {
+    use std::num::Float;
+    use std::ptr;
+
+    use test_util::vec3;
+    use animation::{MeshAnim, MeshKey};
+    use types::{Vector3D, Matrix4x4, Quaternion, AiString};
+    use super::{Mesh, Face, Bone, VertexWeight, PrimitiveType, AnimMesh, IndexBuffer};
+    use super::{MAX_NUMBER_OF_COLOR_SETS, MAX_NUMBER_OF_TEXTURECOORDS};
+    use super::{VertexLayout, Semantic, Format, f32_to_f16};
+
+    /// Owns the arrays a test `Mesh` points into
+    struct TestMesh {
+        _vertices: Vec<Vector3D>,
+        _indices: Vec<Vec<u32>>,
+        _faces: Vec<Face>,
+        _weights: Vec<Vec<VertexWeight>>,
+        _bones: Vec<Bone>,
+        _bone_ptrs: Vec<*mut Bone>,
+        _normals: Vec<Vector3D>,
+        _tangents: Vec<Vector3D>,
+        _bitangents: Vec<Vector3D>,
+        _targets: Vec<Vec<Vector3D>>,
+        _target_normals: Vec<Vec<Vector3D>>,
+        _anim_meshes: Vec<AnimMesh>,
+        _anim_mesh_ptrs: Vec<*mut AnimMesh>,
+        mesh: Mesh,
+    }
+
+    /// A mesh with the given faces and bones. Each bone is a list of
+    /// `(vertex id, weight)` pairs.
+    fn test_mesh(vertices: Vec<Vector3D>,
+                 indices: Vec<Vec<u32>>,
+                 bones: Vec<Vec<(u32, f32)>>) -> TestMesh {
+        let mut vertices = vertices;
+        let mut indices = indices;
+        let mut primitive_types = 0;
+        let mut faces: Vec<Face> = indices.iter_mut().map(|face| {
+            primitive_types |= PrimitiveType::get_primitive_type(face.len() as u32) as u32;
+            Face { num_indices: face.len() as u32, indices: face.as_mut_ptr() }
+        }).collect();
+
+        let mut weights: Vec<Vec<VertexWeight>> = bones.iter().map(|bone| {
+            bone.iter().map(|&(id, weight)| {
+                VertexWeight { vertex_id: id, weight: weight }
+            }).collect()
+        }).collect();
+        let mut bones: Vec<Bone> = weights.iter_mut().map(|weights| Bone {
+            name: AiString::new(),
+            num_weights: weights.len() as u32,
+            weights: weights.as_mut_ptr(),
+            offset_matrix: Matrix4x4::identity(),
+        }).collect();
+        let mut bone_ptrs: Vec<*mut Bone> = bones.iter_mut()
+            .map(|bone| bone as *mut Bone)
+            .collect();
+
+        let mesh = Mesh {
+            primitive_types: primitive_types,
+            num_vertices: vertices.len() as u32,
+            num_faces: faces.len() as u32,
+            vertices: vertices.as_mut_ptr(),
+            normals: ptr::null_mut(),
+            tangents: ptr::null_mut(),
+            bitangents: ptr::null_mut(),
+            colors: [ptr::null_mut(), ..MAX_NUMBER_OF_COLOR_SETS],
+            texture_coords: [ptr::null_mut(), ..MAX_NUMBER_OF_TEXTURECOORDS],
+            num_uv_components: [0, ..MAX_NUMBER_OF_TEXTURECOORDS],
+            faces: faces.as_mut_ptr(),
+            num_bones: bone_ptrs.len() as u32,
+            bones: bone_ptrs.as_mut_ptr(),
+            material_index: 0,
+            name: AiString::new(),
+            num_anim_meshes: 0,
+            anim_meshes: ptr::null_mut(),
+        };
+
+        TestMesh {
+            _vertices: vertices,
+            _indices: indices,
+            _faces: faces,
+            _weights: weights,
+            _bones: bones,
+            _bone_ptrs: bone_ptrs,
+            _normals: Vec::new(),
+            _tangents: Vec::new(),
+            _bitangents: Vec::new(),
+            _targets: Vec::new(),
+            _target_normals: Vec::new(),
+            _anim_meshes: Vec::new(),
+            _anim_mesh_ptrs: Vec::new(),
+            mesh: mesh,
+        }
+    }
+
+    /// Attach normals, tangents and bitangents to the mesh
+    fn set_directions(test: &mut TestMesh,
+                      normals: Vec<Vector3D>,
+                      tangents: Vec<Vector3D>,
+                      bitangents: Vec<Vector3D>) {
+        test._normals = normals;
+        test._tangents = tangents;
+        test._bitangents = bitangents;
+        test.mesh.normals = test._normals.as_mut_ptr();
+        test.mesh.tangents = test._tangents.as_mut_ptr();
+        test.mesh.bitangents = test._bitangents.as_mut_ptr();
+    }
+
+    /// Attach morph targets replacing the vertex positions and normals. An
+    /// empty list of normals leaves the target without normals.
+    fn set_targets(test: &mut TestMesh,
+                   targets: Vec<Vec<Vector3D>>,
+                   normals: Vec<Vec<Vector3D>>) {
+        test._targets = targets;
+        test._target_normals = normals;
+        let targets = test._targets.iter_mut().zip(test._target_normals.iter_mut());
+        test._anim_meshes = targets.map(|(vertices, normals)| AnimMesh {
+            vertices: vertices.as_mut_ptr(),
+            normals: if normals.is_empty() { ptr::null_mut() } else { normals.as_mut_ptr() },
+            tangents: ptr::null_mut(),
+            bitangents: ptr::null_mut(),
+            colors: [ptr::null_mut(), ..MAX_NUMBER_OF_COLOR_SETS],
+            texture_coords: [ptr::null_mut(), ..MAX_NUMBER_OF_TEXTURECOORDS],
+            num_vertices: vertices.len() as u32,
+        }).collect();
+        test._anim_mesh_ptrs = test._anim_meshes.iter_mut()
+            .map(|anim_mesh| anim_mesh as *mut AnimMesh)
+            .collect();
+        test.mesh.num_anim_meshes = test._anim_mesh_ptrs.len() as u32;
+        test.mesh.anim_meshes = test._anim_mesh_ptrs.as_mut_ptr();
+    }
+
+    /// A triangle with one vertex bound to each of two bones and one
+    /// shared by both, and the bone matrices: a quarter turn around z and a
+    /// translation
+    fn two_bones() -> (TestMesh, [Matrix4x4, ..2]) {
+        let half = (0.5f32).sqrt();
+        let quarter_turn = Quaternion { w: half, x: 0.0, y: 0.0, z: half };
+        let upper = Matrix4x4::compose(&vec3(0.0, 1.0, 0.0), &quarter_turn,
+                                       &vec3(1.0, 1.0, 1.0));
+        let lower = Matrix4x4::compose(&vec3(0.0, 0.0, 2.0), &Quaternion::identity(),
+                                       &vec3(1.0, 1.0, 1.0));
+        let test = test_mesh(
+            vec![vec3(1.0, 0.0, 0.0), vec3(0.0, 2.0, 0.0), vec3(0.0, 0.0, 3.0)],
+            vec![vec![0, 1, 2]],
+            vec![vec![(0, 1.0), (2, 0.5)], vec![(1, 1.0), (2, 0.5)]]);
+        (test, [upper, lower])
+    }
+
+    fn assert_near(a: &Vector3D, b: &Vector3D) {
+        assert!((*a - *b).norm() < 1e-5);
+    }
+
+    #[test]
+    fn test_skin() {
+        let (test, palette) = two_bones();
+        let (upper, lower) = (palette[0], palette[1]);
+        let (vertices, normals) = test.mesh.skin(&palette);
+        let original = test.mesh.get_vertices();
+
+        // vertices bound to a single bone move with that bone
+        assert!((vertices[0] - upper.transform_point(&original[0])).norm() < 1e-5);
+        assert!((vertices[1] - lower.transform_point(&original[1])).norm() < 1e-5);
+        // shared vertices are blended by weight
+        let blended = (upper.transform_point(&original[2]) +
+                       lower.transform_point(&original[2])) * 0.5;
+        assert!((vertices[2] - blended).norm() < 1e-5);
+        assert!(normals.is_empty());
+    }
+
+    #[test]
+    fn test_skin_directions() {
+        let (mut test, palette) = two_bones();
+        let (x, y, z) = (vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, 1.0));
+        set_directions(&mut test, vec![x, z, x], vec![x, x, x], vec![y, y, y]);
+        let half = (0.5f32).sqrt();
+
+        // directions are turned but not translated, and the blend of the
+        // shared vertex is renormalized
+        let (_, normals) = test.mesh.skin(&palette);
+        assert_eq!(normals.len(), 3);
+        assert_near(&normals[0], &y);
+        assert_near(&normals[1], &z);
+        assert_near(&normals[2], &vec3(half, half, 0.0));
+
+        let (tangents, bitangents) = test.mesh.skin_tangents(&palette);
+        assert_near(&tangents[0], &y);
+        assert_near(&tangents[1], &x);
+        assert_near(&tangents[2], &vec3(half, half, 0.0));
+        assert_near(&bitangents[0], &vec3(-1.0, 0.0, 0.0));
+        assert_near(&bitangents[1], &y);
+        assert_near(&bitangents[2], &vec3(-half, half, 0.0));
+    }
+
+    #[test]
+    #[should_fail]
+    fn test_skin_short_palette() {
+        let (test, palette) = two_bones();
+        let _ = test.mesh.skin(palette.slice_to(1));
+    }
+
+    #[test]
+    fn test_posed_bounds() {
+        let (test, palette) = two_bones();
+        let aabb = test.mesh.aabb();
+        assert_near(&aabb.min, &vec3(0.0, 0.0, 0.0));
+        assert_near(&aabb.max, &vec3(1.0, 2.0, 3.0));
+
+        // the first vertex is turned onto the y axis and moved up, the
+        // second moved along z and the third blended between both
+        let posed = [vec3(0.0, 2.0, 0.0), vec3(0.0, 2.0, 2.0), vec3(0.0, 0.5, 4.0)];
+        let aabb = test.mesh.posed_aabb(&palette);
+        assert_near(&aabb.min, &vec3(0.0, 0.5, 0.0));
+        assert_near(&aabb.max, &vec3(0.0, 2.0, 4.0));
+        let sphere = test.mesh.posed_bounding_sphere(&palette);
+        for p in posed.iter() {
+            assert!((*p - sphere.center).norm() <= sphere.radius + 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_morph() {
+        let z = vec3(0.0, 0.0, 1.0);
+        let mut test = test_mesh(
+            vec![vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0)],
+            vec![vec![0, 1, 2]],
+            vec![]);
+        test._normals = vec![z, z, z];
+        test.mesh.normals = test._normals.as_mut_ptr();
+        // the second target has no normals of its own
+        set_targets(&mut test,
+                    vec![vec![vec3(0.0, 0.0, 1.0), vec3(1.0, 0.0, 1.0), vec3(0.0, 1.0, 1.0)],
+                         vec![vec3(0.0, 0.0, 3.0), vec3(2.0, 0.0, 3.0), vec3(0.0, 2.0, 3.0)]],
+                    vec![vec![vec3(1.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0)],
+                         vec![]]);
+        // the last key refers to a missing attachment mesh
+        let mut keys = vec![MeshKey { time: 0.0, value: 0 },
+                            MeshKey { time: 2.0, value: 1 },
+                            MeshKey { time: 4.0, value: 5 }];
+        let anim = MeshAnim::from_keys(&mut keys);
+
+        assert_eq!(anim.get_frames(1.0), Some((0, 1, 0.5)));
+        assert_eq!(anim.get_frames(3.0), Some((1, 5, 0.5)));
+        assert_eq!(anim.get_frames(-1.0), Some((0, 0, 0.0)));
+
+        let half = (0.5f32).sqrt();
+        let (vertices, normals) = test.mesh.morph(&anim, 1.0);
+        assert_near(&vertices[0], &vec3(0.0, 0.0, 2.0));
+        assert_near(&vertices[1], &vec3(1.5, 0.0, 2.0));
+        assert_near(&vertices[2], &vec3(0.0, 1.5, 2.0));
+        for normal in normals.iter() {
+            assert_near(normal, &vec3(half, 0.0, half));
+        }
+
+        let (vertices, normals) = test.mesh.morph(&anim, 3.0);
+        assert_near(&vertices[0], &vec3(0.0, 0.0, 1.5));
+        assert_near(&vertices[1], &vec3(1.5, 0.0, 1.5));
+        assert_near(&vertices[2], &vec3(0.0, 1.5, 1.5));
+        for normal in normals.iter() {
+            assert_near(normal, &z);
+        }
+
+        // without keys the mesh keeps its shape
+        let mut keys = Vec::new();
+        let anim = MeshAnim::from_keys(&mut keys);
+        assert_eq!(anim.get_frames(1.0), None);
+        let (vertices, normals) = test.mesh.morph(&anim, 1.0);
+        assert_eq!(vertices.as_slice(), test.mesh.get_vertices());
+        assert_eq!(normals, vec![z, z, z]);
+    }
+
+    #[test]
+    fn test_get_indices() {
+        let vertices = Vec::from_elem(5, vec3(0.0, 0.0, 0.0));
+        let test = test_mesh(vertices.clone(),
+                             vec![vec![0, 1, 2], vec![3, 4], vec![2, 3, 4], vec![1]],
+                             vec![]);
+        let mesh = &test.mesh;
+
+        // only faces of the requested type are returned
+        assert_eq!(mesh.get_indices::<u32>(PrimitiveType::Triangle),
+                   Ok(vec![0, 1, 2, 2, 3, 4]));
+        assert_eq!(mesh.get_indices::<u16>(PrimitiveType::Line), Ok(vec![3, 4]));
+        assert_eq!(mesh.get_indices::<u16>(PrimitiveType::Point), Ok(vec![1]));
+        assert!(mesh.get_indices::<u32>(PrimitiveType::Polygon).is_err());
+
+        // polygons need to be triangulated first
+        let test = test_mesh(vertices.clone(), vec![vec![0, 1, 2], vec![0, 2, 3, 4]], vec![]);
+        assert!(test.mesh.get_indices::<u32>(PrimitiveType::Triangle).is_err());
+        let test = test_mesh(vertices, vec![vec![0, 1, 2], vec![0, 2, 3], vec![0, 3, 4]],
+                             vec![]);
+        assert_eq!(test.mesh.get_indices::<u32>(PrimitiveType::Triangle).unwrap().len(), 9);
+    }
+
+    #[test]
+    fn test_get_index_buffer() {
+        // 16 bits are enough to address 0x10000 vertices
+        let vertices = Vec::from_elem(0x10000, vec3(0.0, 0.0, 0.0));
+        let test = test_mesh(vertices, vec![vec![0, 1, 0xffff]], vec![]);
+        match test.mesh.get_index_buffer(PrimitiveType::Triangle) {
+            Ok(IndexBuffer::U16(indices)) => assert_eq!(indices, vec![0, 1, 0xffff]),
+            _ => panic!("expected 16 bit indices"),
+        }
+
+        let vertices = Vec::from_elem(0x10001, vec3(0.0, 0.0, 0.0));
+        let test = test_mesh(vertices, vec![vec![0, 1, 0x10000]], vec![]);
+        match test.mesh.get_index_buffer(PrimitiveType::Triangle) {
+            Ok(IndexBuffer::U32(indices)) => assert_eq!(indices, vec![0, 1, 0x10000]),
+            _ => panic!("expected 32 bit indices"),
+        }
+        assert!(test.mesh.get_indices::<u16>(PrimitiveType::Triangle).is_err());
+    }
+
+    #[test]
+    fn test_blend_morph_targets() {
+        let base = vec![vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0)];
+        let smile = vec![vec3(0.0, 1.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 2.0)];
+        let frown = vec![vec3(4.0, 0.0, 0.0), vec3(1.0, -2.0, 0.0), vec3(0.0, 1.0, 0.0)];
+        let mut test = test_mesh(base.clone(), vec![vec![0, 1, 2]], vec![]);
+        set_targets(&mut test, vec![smile.clone(), frown.clone()], vec![vec![], vec![]]);
+
+        let weights = [0.5, 0.25];
+        let (vertices, normals) = test.mesh.blend_morph_targets(&weights);
+        for i in range(0, base.len()) {
+            let expected = base[i] + (smile[i] - base[i]) * weights[0] +
+                           (frown[i] - base[i]) * weights[1];
+            assert!((vertices[i] - expected).norm() < 1e-6);
+        }
+        assert!(normals.is_empty());
+
+        // without weights the mesh keeps its shape
+        let (vertices, _) = test.mesh.blend_morph_targets(&[0.0, 0.0]);
+        assert_eq!(vertices, base);
+    }
+
+    #[test]
+    fn test_vertex_influences() {
+        let test = test_mesh(
+            vec![vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0)],
+            vec![vec![0, 1, 2]],
+            vec![vec![(0, 1.0), (1, 0.5), (2, 0.6)],
+                 vec![(1, 0.5), (2, 0.3)],
+                 vec![(2, 0.1)]]);
+
+        let influences = test.mesh.vertex_influences(2, &[7, 8, 9]).unwrap();
+        assert_eq!(influences.get_bone_ids(0), [7, 0].as_slice());
+        assert_eq!(influences.get_weights(0), [1.0, 0.0].as_slice());
+        assert_eq!(influences.get_bone_ids(2), [7, 8].as_slice());
+        assert!((influences.get_weights(2)[0] - 0.6 / 0.9).abs() < 1e-6);
+        assert_eq!(influences.lossy_vertices, vec![2]);
+
+        // every bone needs an id
+        assert!(test.mesh.vertex_influences(2, &[7, 8]).is_err());
+    }
+
+    #[test]
+    fn test_layout_offsets() {
+        let mut layout = VertexLayout::new();
+        layout.add_attribute(Semantic::Position, Format::F32, 3);
+        layout.add_attribute(Semantic::Normal, Format::Snorm16, 3);
+        layout.add_attribute(Semantic::TexCoord(0), Format::F16, 2);
+        layout.add_attribute(Semantic::BoneIds, Format::U8, 4);
+
+        let offsets: Vec<uint> = layout.get_attributes().iter().map(|a| a.offset).collect();
+        assert_eq!(offsets, vec![0, 12, 20, 24]);
+        assert_eq!(layout.get_stride(), 28);
+        assert!(layout.validate().is_ok());
+
+        layout.add_attribute(Semantic::BoneWeights, Format::U8, 4);
+        assert!(layout.validate().is_err());
+    }
+
+    #[test]
+    fn test_write_vertices() {
+        let mut test = test_mesh(vec![vec3(1.0, -2.0, 0.5), vec3(0.0, 0.0, -1.0)],
+                                 vec![vec![0, 1]],
+                                 vec![]);
+        let mut normals = vec![vec3(0.0, 0.0, 1.0), vec3(1.0, 0.0, 0.0)];
+        let mut uvs = vec![vec3(0.0, 1.0, 0.0), vec3(0.5, 0.25, 1.0)];
+        test.mesh.normals = normals.as_mut_ptr();
+        test.mesh.texture_coords[0] = uvs.as_mut_ptr();
+        test.mesh.num_uv_components[0] = 2;
+
+        let mut layout = VertexLayout::new();
+        layout.add_attribute(Semantic::Position, Format::F32, 3);
+        layout.add_attribute(Semantic::Normal, Format::F16, 3);
+        layout.add_attribute(Semantic::TexCoord(0), Format::Unorm8, 2);
+        let mut out = Vec::new();
+        assert!(test.mesh.write_vertices(&layout, &mut out).is_ok());
+
+        let expected: [u8, ..48] = [
+            // position, little endian f32
+            0x00, 0x00, 0x80, 0x3f, 0x00, 0x00, 0x00, 0xc0, 0x00, 0x00, 0x00, 0x3f,
+            // normal, little endian f16 and padding
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x3c, 0x00, 0x00,
+            // uv, unorm8 and padding
+            0x00, 0xff, 0x00, 0x00,
+
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0xbf,
+            0x00, 0x3c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            // 0.5 and 0.25 round to 128 and 64, the third component is dropped
+            0x80, 0x40, 0x00, 0x00,
+        ];
+        assert_eq!(out.as_slice(), expected.as_slice());
+    }
+
+    #[test]
+    fn test_missing_channels() {
+        let (test, palette) = two_bones();
+        let mesh = &test.mesh;
+        assert!(!mesh.has_normals());
+        assert!(!mesh.has_tangents_and_bitangents());
+        assert_eq!(mesh.num_color_channels(), 0);
+        assert_eq!(mesh.num_uv_channels(), 0);
+        assert!(mesh.get_normals().is_none());
+        assert!(mesh.get_tangents().is_none());
+        assert!(mesh.get_bitangents().is_none());
+        assert!(mesh.get_colors().is_empty());
+        assert!(mesh.get_texture_coords().is_empty());
+
+        // only the positions are skinned
+        let (vertices, normals) = mesh.skin(&palette);
+        assert_eq!(vertices.len(), 3);
+        assert!(normals.is_empty());
+        let (tangents, bitangents) = mesh.skin_tangents(&palette);
+        assert!(tangents.is_empty());
+        assert!(bitangents.is_empty());
+
+        // missing attributes are written as zeros, colors as white
+        let mut layout = VertexLayout::new();
+        layout.add_attribute(Semantic::Normal, Format::F32, 3);
+        layout.add_attribute(Semantic::TexCoord(0), Format::F32, 2);
+        layout.add_attribute(Semantic::Color(0), Format::Unorm8, 4);
+        assert_eq!(layout.get_stride(), 24);
+        let mut out = Vec::new();
+        assert!(mesh.write_vertices(&layout, &mut out).is_ok());
+        assert_eq!(out.len(), 3 * 24);
+        for vertex in out.chunks(24) {
+            assert!(vertex.slice_to(20).iter().all(|&b| b == 0));
+            assert_eq!(vertex.slice_from(20), [0xff, 0xff, 0xff, 0xff].as_slice());
+        }
+    }
+
+    #[test]
+    fn test_f32_to_f16() {
+        assert_eq!(f32_to_f16(0.0), 0x0000);
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        assert_eq!(f32_to_f16(-2.0), 0xc000);
+        assert_eq!(f32_to_f16(0.5), 0x3800);
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16(1e6), 0x7c00);
+        // smallest subnormal
+        assert_eq!(f32_to_f16(5.9604645e-8), 0x0001);
+
+        // ties round to even
+        assert_eq!(f32_to_f16(1.00048828125), 0x3c00);
+        assert_eq!(f32_to_f16(1.00146484375), 0x3c02);
+        assert_eq!(f32_to_f16(1.0006), 0x3c01);
+        assert_eq!(f32_to_f16(65520.0), 0x7c00);
+        assert_eq!(f32_to_f16(2.98023223876953125e-8), 0x0000);
+        assert_eq!(f32_to_f16(8.94069671630859375e-8), 0x0002);
+    }
+
+    #[test]
+    fn test_bone_id_range() {
+        let bones: Vec<Vec<(u32, f32)>> = Vec::from_fn(300, |i| vec![(0, i as f32)]);
+        let test = test_mesh(vec![vec3(0.0, 0.0, 0.0)], vec![vec![0]], bones);
+        let mut out = Vec::new();
+
+        let mut layout = VertexLayout::new();
+        layout.add_attribute(Semantic::BoneIds, Format::U8, 4);
+        layout.add_attribute(Semantic::BoneWeights, Format::Unorm8, 4);
+        assert!(test.mesh.write_vertices(&layout, &mut out).is_err());
+
+        let mut layout = VertexLayout::new();
+        layout.add_attribute(Semantic::BoneIds, Format::U16, 4);
+        layout.add_attribute(Semantic::BoneWeights, Format::Unorm8, 4);
+        assert!(test.mesh.write_vertices(&layout, &mut out).is_ok());
+        // the strongest bone comes first
+        assert_eq!(out.slice_to(2), [43, 1].as_slice());
+    }
+}
+
 // vim: et tw=78 sw=4:
@@ -0,0 +1,291 @@
+//! Partitioning of meshes into meshlets for mesh shader pipelines.
+//!
+//! A meshlet is a small cluster of triangles with its own vertex list, so
+//! the triangles can index the vertices with 8 bit local indices. Each
+//! meshlet has a bounding sphere for frustum and occlusion culling and a
+//! normal cone for backface culling of the whole cluster.
+//!
+//! The triangles are first reordered for vertex cache locality, see
+//! `optimize::optimize_vertex_cache`, and then split into meshlets in that
+//! order, so neighbouring triangles end up in the same meshlet.
+
+use std::num::Float;
+
+use mesh::{Mesh, PrimitiveType};
+use optimize;
+use types::{Vector3D, BoundingSphere};
+
+/// The largest number of vertices of a meshlet supported by 8 bit local
+/// indices
+pub const MAX_VERTICES: uint = 256;
+
+/// A vertex limit suited to most mesh shader hardware
+pub const DEFAULT_MAX_VERTICES: uint = 64;
+/// A triangle limit suited to most mesh shader hardware
+pub const DEFAULT_MAX_TRIANGLES: uint = 124;
+
+/// A cone containing the normals of all triangles of a meshlet.
+///
+/// The whole meshlet faces away from a camera at `camera` if
+/// `dot(normalize(apex - camera), axis) >= cutoff`.
+#[deriving(Clone, PartialEq, Show)]
+pub struct NormalCone {
+    /// The apex of the cone
+    pub apex: Vector3D,
+    /// The unit axis of the cone
+    pub axis: Vector3D,
+    /// The sine of the half angle of the cone, 1.0 if the meshlet can't be
+    /// culled
+    pub cutoff: f32,
+}
+
+impl NormalCone {
+    /// Whether all triangles of the meshlet face away from `camera`
+    pub fn is_backfacing(&self, camera: &Vector3D) -> bool {
+        if self.cutoff >= 1.0 {
+            return false
+        }
+        let direction = self.apex - *camera;
+        let length = direction.norm();
+        length > 0.0 && direction.dot(&self.axis) >= self.cutoff * length
+    }
+}
+
+/// A cluster of triangles sharing a small set of vertices
+#[deriving(Clone, PartialEq, Show)]
+pub struct Meshlet {
+    /// The vertices used by the meshlet, as indices into the vertices of the
+    /// mesh
+    pub vertices: Vec<u32>,
+    /// The triangles of the meshlet, three indices into `vertices` per
+    /// triangle
+    pub triangles: Vec<u8>,
+    /// A sphere containing all vertices of the meshlet
+    pub bounds: BoundingSphere,
+    /// A cone containing the normals of all triangles of the meshlet
+    pub cone: NormalCone,
+}
+
+impl Meshlet {
+    /// The number of triangles of the meshlet
+    pub fn num_triangles(&self) -> uint {
+        self.triangles.len() / 3
+    }
+
+    /// The triangles of the meshlet as indices into the vertices of the
+    /// mesh
+    pub fn get_indices(&self) -> Vec<u32> {
+        self.triangles.iter().map(|&i| self.vertices[i as uint]).collect()
+    }
+}
+
+/// Partition the triangles of `mesh` into meshlets with at most
+/// `max_vertices` vertices and `max_triangles` triangles each.
+///
+/// Points and lines are ignored. Returns an error if the limits are too
+/// small to hold a triangle, if `max_vertices` exceeds `MAX_VERTICES` or if
+/// the mesh contains polygons.
+pub fn build(mesh: &Mesh, max_vertices: uint, max_triangles: uint)
+    -> Result<Vec<Meshlet>, &'static str> {
+    let indices = try!(mesh.get_indices::<u32>(PrimitiveType::Triangle));
+    build_from_indices(indices.as_slice(), mesh.get_vertices(), max_vertices, max_triangles)
+}
+
+/// Partition the triangle list `indices` over `vertices` into meshlets with
+/// at most `max_vertices` vertices and `max_triangles` triangles each.
+///
+/// Returns an error if the limits are too small to hold a triangle or if
+/// `max_vertices` exceeds `MAX_VERTICES`.
+pub fn build_from_indices(indices: &[u32], vertices: &[Vector3D],
+                          max_vertices: uint, max_triangles: uint)
+    -> Result<Vec<Meshlet>, &'static str> {
+    if max_vertices < 3 || max_triangles < 1 {
+        return Err("a meshlet must be able to hold a triangle")
+    }
+    if max_vertices > MAX_VERTICES {
+        return Err("a meshlet can have at most 256 vertices")
+    }
+    if indices.len() % 3 != 0 {
+        return Err("the indices aren't a triangle list")
+    }
+
+    let ordered = optimize::optimize_vertex_cache(indices, vertices.len(),
+                                                  optimize::DEFAULT_CACHE_SIZE);
+
+    let mut meshlets = Vec::new();
+    // the local index of each vertex in the current meshlet
+    let mut local: Vec<Option<u8>> = Vec::from_elem(vertices.len(), None);
+    let mut meshlet_vertices: Vec<u32> = Vec::new();
+    let mut meshlet_triangles: Vec<u8> = Vec::new();
+
+    for triangle in ordered.chunks(3) {
+        let new_vertices = triangle.iter().enumerate().filter(|&(i, &v)| {
+            // count repeated vertices of degenerate triangles only once
+            local[v as uint].is_none() && !triangle.slice_to(i).contains(&v)
+        }).count();
+        if meshlet_vertices.len() + new_vertices > max_vertices ||
+           meshlet_triangles.len() / 3 + 1 > max_triangles {
+            meshlets.push(finish(meshlet_vertices.as_slice(),
+                                 meshlet_triangles.as_slice(), vertices));
+            for &v in meshlet_vertices.iter() {
+                local[v as uint] = None;
+            }
+            meshlet_vertices.clear();
+            meshlet_triangles.clear();
+        }
+
+        for &v in triangle.iter() {
+            let index = match local[v as uint] {
+                Some(index) => index,
+                None => {
+                    let index = meshlet_vertices.len() as u8;
+                    local[v as uint] = Some(index);
+                    meshlet_vertices.push(v);
+                    index
+                }
+            };
+            meshlet_triangles.push(index);
+        }
+    }
+    if !meshlet_triangles.is_empty() {
+        meshlets.push(finish(meshlet_vertices.as_slice(),
+                             meshlet_triangles.as_slice(), vertices));
+    }
+    Ok(meshlets)
+}
+
+/// Create a meshlet and compute its bounds
+fn finish(meshlet_vertices: &[u32], triangles: &[u8], vertices: &[Vector3D]) -> Meshlet {
+    let points: Vec<Vector3D> = meshlet_vertices.iter()
+        .map(|&v| vertices[v as uint])
+        .collect();
+    let bounds = BoundingSphere::from_points(points.as_slice());
+
+    // the unit normals of the triangles
+    let normals: Vec<Vector3D> = triangles.chunks(3).filter_map(|t| {
+        let (p0, p1, p2) = (points[t[0] as uint], points[t[1] as uint], points[t[2] as uint]);
+        let normal = (p1 - p0).cross(&(p2 - p0));
+        if normal.norm() > 0.0 { Some(normal.normalize()) } else { None }
+    }).collect();
+
+    Meshlet {
+        vertices: meshlet_vertices.to_vec(),
+        triangles: triangles.to_vec(),
+        cone: normal_cone(points.as_slice(), triangles, normals.as_slice(), &bounds),
+        bounds: bounds,
+    }
+}
+
+/// The cone containing the normals of the non-degenerate triangles
+fn normal_cone(points: &[Vector3D], triangles: &[u8], normals: &[Vector3D],
+               bounds: &BoundingSphere) -> NormalCone {
+    let unculled = NormalCone {
+        apex: bounds.center,
+        axis: Vector3D { x: 0.0, y: 0.0, z: 0.0 },
+        cutoff: 1.0,
+    };
+
+    let mut sum = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+    for normal in normals.iter() {
+        sum = sum + *normal;
+    }
+    if sum.norm() <= 0.0 {
+        return unculled
+    }
+    let axis = sum.normalize();
+
+    let min_dot = normals.iter().fold(1.0f32, |m, n| m.min(n.dot(&axis)));
+    if min_dot <= 0.1 {
+        // the cone is too wide to ever cull the meshlet
+        return NormalCone { axis: axis, ..unculled }
+    }
+
+    // move the apex back along the axis until the planes of all triangles
+    // are in front of it
+    let mut max_t = 0.0f32;
+    for triangle in triangles.chunks(3) {
+        let (p0, p1, p2) = (points[triangle[0] as uint],
+                            points[triangle[1] as uint],
+                            points[triangle[2] as uint]);
+        let normal = (p1 - p0).cross(&(p2 - p0));
+        let along = normal.dot(&axis);
+        if along > 0.0 {
+            max_t = max_t.max((bounds.center - p0).dot(&normal) / along);
+        }
+    }
+
+    NormalCone {
+        apex: bounds.center - axis * max_t,
+        axis: axis,
+        cutoff: (1.0 - min_dot * min_dot).sqrt(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test_util::grid;
+    use types::Vector3D;
+    use super::build_from_indices;
+
+    #[test]
+    fn test_meshlet_limits() {
+        let (vertices, indices) = grid(10);
+        let meshlets = build_from_indices(indices.as_slice(), vertices.as_slice(),
+                                          16, 20).unwrap();
+
+        let mut num_triangles = 0;
+        for meshlet in meshlets.iter() {
+            assert!(meshlet.vertices.len() <= 16);
+            assert!(meshlet.num_triangles() <= 20);
+            num_triangles += meshlet.num_triangles();
+
+            // every vertex is inside the bounding sphere
+            for &v in meshlet.vertices.iter() {
+                let d = vertices[v as uint] - meshlet.bounds.center;
+                assert!(d.norm() <= meshlet.bounds.radius + 1e-4);
+            }
+
+            // a flat meshlet is culled from below but not from above
+            let center = meshlet.bounds.center;
+            assert!(meshlet.cone.is_backfacing(&Vector3D { z: -10.0, ..center }));
+            assert!(!meshlet.cone.is_backfacing(&Vector3D { z: 10.0, ..center }));
+        }
+        assert_eq!(num_triangles, 200);
+    }
+
+    #[test]
+    fn test_curved_cone_apex() {
+        // a bowl and a dome, so the bounding sphere center lies in front of
+        // some triangles and behind others
+        let curvatures = [0.1f32, -0.1];
+        for &curvature in curvatures.iter() {
+            let (mut vertices, indices) = grid(4);
+            for v in vertices.iter_mut() {
+                v.z = curvature * ((v.x - 2.0) * (v.x - 2.0) + (v.y - 2.0) * (v.y - 2.0));
+            }
+            let meshlets = build_from_indices(indices.as_slice(), vertices.as_slice(),
+                                              64, 64).unwrap();
+            assert_eq!(meshlets.len(), 1);
+
+            // the apex is behind the plane of every triangle
+            let meshlet = &meshlets[0];
+            assert!(meshlet.cone.cutoff < 1.0);
+            for t in indices.chunks(3) {
+                let (p0, p1, p2) = (vertices[t[0] as uint],
+                                    vertices[t[1] as uint],
+                                    vertices[t[2] as uint]);
+                let normal = (p1 - p0).cross(&(p2 - p0));
+                assert!((meshlet.cone.apex - p0).dot(&normal) <= 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_limits() {
+        let (vertices, indices) = grid(1);
+        assert!(build_from_indices(indices.as_slice(), vertices.as_slice(), 2, 10).is_err());
+        assert!(build_from_indices(indices.as_slice(), vertices.as_slice(), 300, 10).is_err());
+    }
+}
+
+// vim: et tw=78 sw=4:
@@ -0,0 +1,500 @@
+//! Vertex cache, overdraw and vertex fetch optimisation.
+//!
+//! `Process::ImproveCacheLocality` reorders triangles inside assimp but
+//! doesn't report how well it did. The functions in this module implement
+//! the same kind of optimisation in Rust and measure the result:
+//!
+//!  * triangles are reordered for the post-transform vertex cache with the
+//!    Tipsify algorithm of Sander, Nehab and Barczak,
+//!  * the clusters found by Tipsify are sorted so triangles facing outwards
+//!    are drawn first, which reduces overdraw,
+//!  * vertices are reordered in the order the triangles first use them, which
+//!    improves the locality of vertex fetches.
+//!
+//! The quality of the vertex cache usage is reported as the average cache
+//! miss ratio (ACMR), the number of transformed vertices per triangle, and
+//! the average transformed vertex ratio (ATVR), the number of transformed
+//! vertices per vertex used. The ATVR is 1.0 for a perfect ordering.
+
+use std::cmp::Ordering;
+
+use mesh::{Mesh, OwnedMesh, VertexWeight};
+use types::Vector3D;
+
+/// The cache size assumed by `optimize`, matching the default of
+/// `PP_ICL_PTCACHE_SIZE`.
+pub const DEFAULT_CACHE_SIZE: uint = 12;
+
+/// Statistics of a simulated FIFO vertex cache
+#[deriving(Clone, PartialEq, Show)]
+pub struct CacheStats {
+    /// The number of vertices transformed
+    pub transformed: uint,
+    /// The average cache miss ratio: transformed vertices per triangle
+    pub acmr: f32,
+    /// The average transformed vertex ratio: transformed vertices per vertex
+    /// used by the triangles
+    pub atvr: f32,
+}
+
+/// The cache statistics of a mesh before and after optimisation
+#[deriving(Clone, PartialEq, Show)]
+pub struct Report {
+    /// The statistics of the original triangle order
+    pub before: CacheStats,
+    /// The statistics of the optimised triangle order
+    pub after: CacheStats,
+}
+
+/// Simulate a FIFO vertex cache of `cache_size` entries drawing the triangle
+/// list `indices`.
+///
+/// All indices must be less than `num_vertices`.
+pub fn analyze(indices: &[u32], num_vertices: uint, cache_size: uint) -> CacheStats {
+    // a vertex is in the cache if fewer than cache_size vertices were
+    // transformed since it was
+    let mut cache_time = Vec::from_elem(num_vertices, 0u);
+    let mut timestamp = cache_size + 1;
+    let mut used = Vec::from_elem(num_vertices, false);
+    let mut num_used = 0u;
+
+    for &index in indices.iter() {
+        let v = index as uint;
+        if timestamp - cache_time[v] > cache_size {
+            cache_time[v] = timestamp;
+            timestamp += 1;
+        }
+        if !used[v] {
+            used[v] = true;
+            num_used += 1;
+        }
+    }
+
+    let transformed = timestamp - cache_size - 1;
+    let num_triangles = indices.len() / 3;
+    CacheStats {
+        transformed: transformed,
+        acmr: if num_triangles > 0 { transformed as f32 / num_triangles as f32 } else { 0.0 },
+        atvr: if num_used > 0 { transformed as f32 / num_used as f32 } else { 0.0 },
+    }
+}
+
+/// Reorder the triangle list `indices` for a vertex cache of `cache_size`
+/// entries.
+///
+/// All indices must be less than `num_vertices`.
+pub fn optimize_vertex_cache(indices: &[u32], num_vertices: uint, cache_size: uint) -> Vec<u32> {
+    let (result, _) = tipsify(indices, num_vertices, cache_size);
+    result
+}
+
+/// Reorder the triangle list `indices` for a vertex cache of `cache_size`
+/// entries, then sort the resulting clusters of triangles to reduce
+/// overdraw.
+///
+/// Clusters whose triangles face away from the center of the mesh are drawn
+/// first, since they are likely to occlude the rest of the mesh.
+pub fn optimize_overdraw(indices: &[u32], vertices: &[Vector3D], cache_size: uint) -> Vec<u32> {
+    let (ordered, clusters) = tipsify(indices, vertices.len(), cache_size);
+    let num_triangles = ordered.len() / 3;
+    if clusters.len() < 2 {
+        return ordered
+    }
+
+    // the area weighted center of the mesh
+    let mut area_sum = 0.0f32;
+    let mut center = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+    for triangle in ordered.chunks(3) {
+        let (normal, centroid) = triangle_info(triangle, vertices);
+        let area = normal.norm();
+        center = center + centroid * area;
+        area_sum += area;
+    }
+    if area_sum > 0.0 {
+        center = center * (1.0 / area_sum);
+    }
+
+    let mut sorted: Vec<(f32, uint, uint)> = Vec::with_capacity(clusters.len());
+    for (i, &start) in clusters.iter().enumerate() {
+        let end = if i + 1 < clusters.len() { clusters[i + 1] } else { num_triangles };
+        let mut normal = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+        let mut centroid = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+        let mut area_sum = 0.0f32;
+        for triangle in ordered.slice(start * 3, end * 3).chunks(3) {
+            let (n, c) = triangle_info(triangle, vertices);
+            let area = n.norm();
+            normal = normal + n;
+            centroid = centroid + c * area;
+            area_sum += area;
+        }
+        let key = if area_sum > 0.0 && normal.norm() > 0.0 {
+            (centroid * (1.0 / area_sum) - center).dot(&normal.normalize())
+        } else {
+            0.0
+        };
+        sorted.push((key, start, end));
+    }
+    sorted.sort_by(|&(a, _, _), &(b, _, _)| b.partial_cmp(&a).unwrap_or(Ordering::Equal));
+
+    let mut result = Vec::with_capacity(ordered.len());
+    for &(_, start, end) in sorted.iter() {
+        result.push_all(ordered.slice(start * 3, end * 3));
+    }
+    result
+}
+
+/// Reorder the vertices of `mesh` in the order its triangles first use
+/// them.
+///
+/// Unused vertices are moved to the end, keeping their order.
+pub fn optimize_vertex_fetch(mesh: &mut OwnedMesh) {
+    let num_vertices = mesh.vertices.len();
+    let mut remap: Vec<Option<u32>> = Vec::from_elem(num_vertices, None);
+    let mut order = Vec::with_capacity(num_vertices);
+    for &index in mesh.indices.iter() {
+        if remap[index as uint].is_none() {
+            remap[index as uint] = Some(order.len() as u32);
+            order.push(index);
+        }
+    }
+    for v in range(0, num_vertices) {
+        if remap[v].is_none() {
+            remap[v] = Some(order.len() as u32);
+            order.push(v as u32);
+        }
+    }
+
+    fn permute<T: Clone>(values: &mut Vec<T>, order: &[u32]) {
+        if values.is_empty() {
+            return
+        }
+        let permuted = order.iter().map(|&v| values[v as uint].clone()).collect();
+        *values = permuted;
+    }
+    permute(&mut mesh.vertices, order.as_slice());
+    permute(&mut mesh.normals, order.as_slice());
+    permute(&mut mesh.tangents, order.as_slice());
+    for colors in mesh.colors.iter_mut() {
+        permute(colors, order.as_slice());
+    }
+    for uvs in mesh.texture_coords.iter_mut() {
+        permute(uvs, order.as_slice());
+    }
+    for bone in mesh.bones.iter_mut() {
+        for weight in bone.weights.iter_mut() {
+            *weight = VertexWeight {
+                vertex_id: remap[weight.vertex_id as uint].unwrap(),
+                weight: weight.weight,
+            };
+        }
+    }
+    for index in mesh.indices.iter_mut() {
+        *index = remap[*index as uint].unwrap();
+    }
+}
+
+/// Optimise the triangle and vertex order of `mesh` for a vertex cache of
+/// `cache_size` entries, and report the cache statistics before and after.
+///
+/// This runs `optimize_overdraw` followed by `optimize_vertex_fetch`. The
+/// optimised order is only kept if it doesn't transform more vertices than
+/// the original order.
+pub fn optimize(mesh: &mut OwnedMesh, cache_size: uint) -> Report {
+    let num_vertices = mesh.vertices.len();
+    let before = analyze(mesh.indices.as_slice(), num_vertices, cache_size);
+
+    let indices = optimize_overdraw(mesh.indices.as_slice(), mesh.vertices.as_slice(),
+                                    cache_size);
+    let after = analyze(indices.as_slice(), num_vertices, cache_size);
+    let after = if after.transformed <= before.transformed {
+        mesh.indices = indices;
+        after
+    } else {
+        before.clone()
+    };
+    optimize_vertex_fetch(mesh);
+
+    Report { before: before, after: after }
+}
+
+/// Create an `OwnedMesh` from `mesh` and optimise it, see `optimize`.
+///
+/// Returns an error if the mesh contains polygons, see
+/// `OwnedMesh::from_mesh`.
+pub fn optimize_mesh(mesh: &Mesh, cache_size: uint)
+    -> Result<(OwnedMesh, Report), &'static str> {
+    let mut owned = try!(OwnedMesh::from_mesh(mesh));
+    let report = optimize(&mut owned, cache_size);
+    Ok((owned, report))
+}
+
+/// The Tipsify triangle ordering, returns the reordered indices and the
+/// first triangle of each cluster
+fn tipsify(indices: &[u32], num_vertices: uint, cache_size: uint) -> (Vec<u32>, Vec<uint>) {
+    let num_triangles = indices.len() / 3;
+
+    // the triangles using each vertex
+    let mut live = Vec::from_elem(num_vertices, 0u);
+    for &index in indices.slice_to(num_triangles * 3).iter() {
+        live[index as uint] += 1;
+    }
+    let mut offsets = Vec::with_capacity(num_vertices + 1);
+    let mut offset = 0u;
+    for &count in live.iter() {
+        offsets.push(offset);
+        offset += count;
+    }
+    offsets.push(offset);
+    let mut adjacency = Vec::from_elem(offset, 0u);
+    let mut filled = offsets.clone();
+    for (i, &index) in indices.slice_to(num_triangles * 3).iter().enumerate() {
+        adjacency[filled[index as uint]] = i / 3;
+        filled[index as uint] += 1;
+    }
+
+    let mut cache_time = Vec::from_elem(num_vertices, 0u);
+    let mut timestamp = cache_size + 1;
+    let mut emitted = Vec::from_elem(num_triangles, false);
+    let mut dead_end: Vec<u32> = Vec::new();
+    let mut cursor = 0u;
+
+    let mut result = Vec::with_capacity(num_triangles * 3);
+    let mut clusters = Vec::new();
+    let mut fanning = if num_vertices > 0 && num_triangles > 0 {
+        clusters.push(0);
+        Some(0u)
+    } else {
+        None
+    };
+
+    loop {
+        let f = match fanning {
+            Some(f) => f,
+            None => break,
+        };
+
+        let mut candidates = Vec::new();
+        for &t in adjacency.slice(offsets[f], offsets[f + 1]).iter() {
+            if emitted[t] {
+                continue
+            }
+            let triangle = indices.slice(t * 3, t * 3 + 3);
+            for &index in triangle.iter() {
+                let v = index as uint;
+                dead_end.push(index);
+                candidates.push(v);
+                live[v] -= 1;
+                if timestamp - cache_time[v] > cache_size {
+                    cache_time[v] = timestamp;
+                    timestamp += 1;
+                }
+            }
+            result.push_all(triangle);
+            emitted[t] = true;
+        }
+
+        // the candidate which stays in the cache while its remaining
+        // triangles are drawn, and entered the cache earliest
+        let mut best = None;
+        let mut best_priority = 0u;
+        for &v in candidates.iter() {
+            if live[v] == 0 {
+                continue
+            }
+            let age = timestamp - cache_time[v];
+            if age + 2 * live[v] <= cache_size && age > best_priority {
+                best_priority = age;
+                best = Some(v);
+            }
+        }
+
+        fanning = match best {
+            Some(v) => Some(v),
+            None => {
+                let next = skip_dead_end(live.as_slice(), &mut dead_end, &mut cursor);
+                let start = result.len() / 3;
+                if next.is_some() && clusters.last() != Some(&start) {
+                    clusters.push(start);
+                }
+                next
+            }
+        };
+    }
+
+    (result, clusters)
+}
+
+/// The most recently used vertex with triangles left, or the next one in
+/// index order
+fn skip_dead_end(live: &[uint], dead_end: &mut Vec<u32>, cursor: &mut uint) -> Option<uint> {
+    loop {
+        match dead_end.pop() {
+            Some(v) => if live[v as uint] > 0 { return Some(v as uint) },
+            None => break,
+        }
+    }
+    while *cursor < live.len() {
+        *cursor += 1;
+        if live[*cursor - 1] > 0 {
+            return Some(*cursor - 1)
+        }
+    }
+    None
+}
+
+/// The unnormalized normal and the centroid of a triangle
+fn triangle_info(triangle: &[u32], vertices: &[Vector3D]) -> (Vector3D, Vector3D) {
+    let (p0, p1, p2) = (vertices[triangle[0] as uint],
+                        vertices[triangle[1] as uint],
+                        vertices[triangle[2] as uint]);
+    let normal = (p1 - p0).cross(&(p2 - p0));
+    (normal, (p0 + p1 + p2) * (1.0 / 3.0))
+}
+
+#[cfg(test)]
+mod test {
+    use mesh::{OwnedMesh, OwnedBone, VertexWeight};
+    use test_util::{vec3, grid, grid_mesh};
+    use types::{Color4D, Matrix4x4, Vector3D};
+    use super::{analyze, optimize_vertex_cache, optimize_overdraw,
+                optimize_vertex_fetch, optimize};
+
+    /// A grid of `n` by `n` quads with the rows of triangles interleaved, so
+    /// the original order has poor locality
+    fn scrambled_grid(n: u32) -> Vec<u32> {
+        let (_, grid_indices) = grid(n);
+        let rows: Vec<&[u32]> = grid_indices.chunks(n as uint * 6).collect();
+        let mut indices = Vec::new();
+        for t in range(0, n as uint * 2) {
+            for row in rows.iter() {
+                indices.push_all(row.slice(t * 3, t * 3 + 3));
+            }
+        }
+        indices
+    }
+
+    /// The triangles of a mesh on the grid of `grid`, as sorted lists of
+    /// grid coordinates
+    fn grid_triangles(mesh: &OwnedMesh) -> Vec<Vec<(i32, i32)>> {
+        let mut triangles: Vec<Vec<(i32, i32)>> = mesh.indices.chunks(3).map(|t| {
+            t.iter().map(|&i| {
+                let v = mesh.vertices[i as uint];
+                (v.x as i32, v.y as i32)
+            }).collect()
+        }).collect();
+        triangles.sort();
+        triangles
+    }
+
+    #[test]
+    fn test_analyze() {
+        // two triangles sharing an edge transform four vertices
+        let stats = analyze(&[0, 1, 2, 2, 1, 3], 4, 12);
+        assert_eq!(stats.transformed, 4);
+        assert_eq!(stats.acmr, 2.0);
+        assert_eq!(stats.atvr, 1.0);
+
+        // a cache of three vertices evicts vertex 0 before it is reused
+        let stats = analyze(&[0, 1, 2, 3, 4, 5, 0, 1, 2], 6, 3);
+        assert_eq!(stats.transformed, 9);
+    }
+
+    #[test]
+    fn test_optimize_vertex_cache() {
+        let n = 16;
+        let num_vertices = ((n + 1) * (n + 1)) as uint;
+        let indices = scrambled_grid(n);
+        let optimized = optimize_vertex_cache(indices.as_slice(), num_vertices, 12);
+
+        // every triangle is kept
+        assert_eq!(optimized.len(), indices.len());
+        let mut a: Vec<Vec<u32>> = indices.chunks(3).map(|t| t.to_vec()).collect();
+        let mut b: Vec<Vec<u32>> = optimized.chunks(3).map(|t| t.to_vec()).collect();
+        a.sort();
+        b.sort();
+        assert!(a == b);
+
+        let before = analyze(indices.as_slice(), num_vertices, 12);
+        let after = analyze(optimized.as_slice(), num_vertices, 12);
+        assert!(after.acmr < before.acmr);
+    }
+
+    #[test]
+    fn test_optimize_overdraw() {
+        // two separate triangles facing +z, the first below the center of
+        // the mesh facing into it, the second above it facing out
+        let vertices = [vec3(0.0, 0.0, -1.0), vec3(1.0, 0.0, -1.0), vec3(0.0, 1.0, -1.0),
+                        vec3(0.0, 0.0, 1.0), vec3(1.0, 0.0, 1.0), vec3(0.0, 1.0, 1.0)];
+        let indices = [0, 1, 2, 3, 4, 5];
+        assert_eq!(optimize_vertex_cache(&indices, 6, 12), indices.to_vec());
+        assert_eq!(optimize_overdraw(&indices, &vertices, 12), vec![3, 4, 5, 0, 1, 2]);
+    }
+
+    #[test]
+    fn test_optimize_vertex_fetch() {
+        let mut mesh = grid_mesh(4);
+        mesh.indices = scrambled_grid(4);
+        let n = mesh.vertices.len();
+        // give every vertex attributes which identify it
+        mesh.normals = range(0, n).map(|i| vec3(i as f32, 0.0, 1.0)).collect();
+        mesh.colors = vec![range(0, n).map(|i| {
+            Color4D { r: i as f32, g: 0.0, b: 0.0, a: 1.0 }
+        }).collect()];
+        mesh.texture_coords = vec![range(0, n).map(|i| vec3(0.0, i as f32, 0.0)).collect()];
+        mesh.num_uv_components = vec![2];
+        mesh.bones = vec![OwnedBone {
+            name: "bone".to_string(),
+            weights: range(0, n).filter(|&i| i % 3 == 0).map(|i| {
+                VertexWeight { vertex_id: i as u32, weight: i as f32 / n as f32 }
+            }).collect(),
+            offset_matrix: Matrix4x4::identity(),
+        }];
+        let original = mesh.clone();
+        optimize_vertex_fetch(&mut mesh);
+
+        // the vertices are numbered in the order the triangles use them
+        assert_eq!(mesh.indices.slice_to(3), [0, 1, 2].as_slice());
+        assert!(mesh.indices != original.indices);
+        // and every corner keeps all of its attributes
+        for (&a, &b) in original.indices.iter().zip(mesh.indices.iter()) {
+            let (a, b) = (a as uint, b as uint);
+            assert_eq!(original.vertices[a], mesh.vertices[b]);
+            assert_eq!(original.normals[a], mesh.normals[b]);
+            assert_eq!(original.colors[0][a], mesh.colors[0][b]);
+            assert_eq!(original.texture_coords[0][a], mesh.texture_coords[0][b]);
+        }
+        // as does every bone weight
+        let weights = original.bones[0].weights.iter().zip(mesh.bones[0].weights.iter());
+        for (a, b) in weights {
+            assert_eq!(original.vertices[a.vertex_id as uint], mesh.vertices[b.vertex_id as uint]);
+            assert_eq!(a.weight, b.weight);
+        }
+    }
+
+    #[test]
+    fn test_optimize() {
+        let mut mesh = grid_mesh(8);
+        mesh.indices = scrambled_grid(8);
+        let original = mesh.clone();
+        let report = optimize(&mut mesh, 12);
+
+        let num_vertices = mesh.vertices.len();
+        assert_eq!(report.before, analyze(original.indices.as_slice(), num_vertices, 12));
+        assert_eq!(report.after, analyze(mesh.indices.as_slice(), num_vertices, 12));
+        assert!(report.after.transformed < report.before.transformed);
+        assert!(grid_triangles(&mesh) == grid_triangles(&original));
+
+        // Tipsify does worse than the row by row order of a small grid, so
+        // that order is kept and only the vertices are renumbered
+        let mut mesh = grid_mesh(4);
+        let original = mesh.clone();
+        let report = optimize(&mut mesh, 12);
+        assert_eq!(report.after, report.before);
+        let positions = |mesh: &OwnedMesh| -> Vec<Vector3D> {
+            mesh.indices.iter().map(|&i| mesh.vertices[i as uint]).collect()
+        };
+        assert!(positions(&mesh) == positions(&original));
+    }
+}
+
+// vim: et tw=78 sw=4:
@@ -194,7 +194,8 @@ pub enum Process {
     ///
     /// If you intend to render huge models in hardware, this step might
     /// be of interest to you. The `Property::PP_ICL_PTCACHE_SIZE` config
-    /// setting can be used to fine-tune the cache optimization.
+    /// setting can be used to fine-tune the cache optimization. See the
+    /// `optimize` module for an alternative which reports the cache usage.
     ImproveCacheLocality = 0x800,
 
     /// Searches for redundant/unreferenced materials and removes them.
@@ -1,18 +1,26 @@
 //! Defines the data structures in which the imported scene is returned.
 
-use libc::{c_uint, c_void};
-use std::mem;
+use libc::{c_uchar, c_uint, c_void};
+use std::collections::HashMap;
+use std::io::{fs, File, IoError, IoResult, InvalidInput};
+use std::io;
+use std::{mem, ptr};
 use std::fmt;
 
 use animation::Animation;
+use bvh::Bvh;
 use camera::Camera;
 use light::Light;
-use material::Material;
-use mesh::Mesh;
-use texture::Texture;
-use types::{Matrix4x4, AiString, MemoryInfo};
+use material::{Material, MaterialProperty, EditableMaterial, MaterialGroups};
+use material;
+use mesh::{Mesh, OwnedMesh};
+use texture::{Texture, TextureData};
+use types::{Matrix4x4, Vector3D, AiString, MemoryInfo, Aabb, BoundingSphere};
 use util::{ptr_ptr_to_slice, ptr_to_slice};
 use postprocess::Process;
+use meshlet;
+use optimize;
+use simplify;
 use ffi;
 
 /// A node in the imported hierarchy.
@@ -78,6 +86,93 @@ impl Node {
     pub fn get_meshes(&self) -> &[u32] {
         unsafe { ptr_to_slice(self.meshes, self.num_meshes as uint) }
     }
+
+    /// The transformation from this node to the root of the hierarchy
+    pub fn get_global_transformation(&self) -> Matrix4x4 {
+        let mut global = self.transformation;
+        let mut node = self;
+        loop {
+            match node.get_parent() {
+                Some(parent) => {
+                    global = parent.transformation * global;
+                    node = parent;
+                }
+                None => return global,
+            }
+        }
+    }
+
+    /// The axis-aligned bounding box of the meshes of this node and all its
+    /// descendants, in global coordinates.
+    ///
+    /// `scene` is the scene the node belongs to. Meshes referenced by
+    /// several nodes are accounted for at each of their positions.
+    pub fn aabb(&self, scene: &Scene) -> Aabb {
+        let mut aabb = Aabb::empty();
+        self.visit_vertices(scene.get_meshes(), |p| aabb.add_point(p));
+        aabb
+    }
+
+    /// A sphere enclosing the meshes of this node and all its descendants,
+    /// see `aabb`.
+    ///
+    /// Uses Ritter's algorithm like `BoundingSphere::from_points`, without
+    /// collecting the vertices.
+    pub fn bounding_sphere(&self, scene: &Scene) -> BoundingSphere {
+        let meshes = scene.get_meshes();
+        let origin = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+        let start = self.get_global_transformation().transform_point(&origin);
+        let a = match self.farthest_vertex(meshes, &start) {
+            Some(a) => a,
+            None => return BoundingSphere::empty(),
+        };
+        let b = self.farthest_vertex(meshes, &a).unwrap_or(a);
+
+        let mut sphere = BoundingSphere {
+            center: (a + b) * 0.5,
+            radius: (b - a).norm() * 0.5,
+        };
+        self.visit_vertices(meshes, |p| sphere.add_point(p));
+        sphere
+    }
+
+    /// The vertex of this subtree farthest from `from`, `None` if there are
+    /// no vertices
+    fn farthest_vertex(&self, meshes: &[&Mesh], from: &Vector3D) -> Option<Vector3D> {
+        let mut best = None;
+        let mut best_dist = -1.0;
+        self.visit_vertices(meshes, |p| {
+            let d = *p - *from;
+            let dist = d.dot(&d);
+            if dist > best_dist {
+                best = Some(*p);
+                best_dist = dist;
+            }
+        });
+        best
+    }
+
+    /// Call `f` with every vertex of the meshes of this node and all its
+    /// descendants, in global coordinates
+    fn visit_vertices(&self, meshes: &[&Mesh], f: |&Vector3D|) {
+        let mut stack = vec![(self, self.get_global_transformation())];
+        loop {
+            let (node, global) = match stack.pop() {
+                Some(entry) => entry,
+                None => break,
+            };
+            for &index in node.get_meshes().iter() {
+                if (index as uint) < meshes.len() {
+                    for v in meshes[index as uint].get_vertices().iter() {
+                        f(&global.transform_point(v));
+                    }
+                }
+            }
+            for child in node.get_children().iter() {
+                stack.push((*child, global * child.transformation));
+            }
+        }
+    }
 }
 
 
@@ -227,7 +322,20 @@ pub struct RawScene<'a> {
 pub struct Scene<'a> {
     /// Note we use this struct to wrap the RawScene so that we
     /// can call `aiReleaseImport` when it gets dropped.
-    raw_scene: &'a RawScene<'a>,
+    ///
+    /// Every `Scene` owns its raw scene, whether it was imported or copied
+    /// with `clone`, so it may be modified through `&mut self`.
+    raw_scene: *mut RawScene<'a>,
+
+    /// The materials replaced by `edit_material`: their index, the material
+    /// allocated by assimp, which has to be put back before the scene is
+    /// released, and the edited copy
+    edited_materials: Vec<(uint, *mut Material, Box<EditableMaterial>)>,
+
+    /// The texture references changed by `rewrite_texture_paths`, in order:
+    /// the property, the data buffer and length it had before, which have
+    /// to be put back before the scene is released, and the new buffer
+    rewritten_paths: Vec<(*mut MaterialProperty, *mut c_uchar, c_uint, Vec<u8>)>,
 
     /// Any combination of the flags in `SceneFlags`.
     ///
@@ -259,9 +367,12 @@ pub struct Scene<'a> {
 impl<'a> Scene<'a> {
     #[doc(hidden)]
     pub unsafe fn from_raw_scene(raw: *const RawScene<'a>) -> Scene<'a> {
+        let raw_scene = raw as *mut RawScene<'a>;
         let raw = &*raw;
         Scene {
-            raw_scene: raw,
+            raw_scene: raw_scene,
+            edited_materials: Vec::new(),
+            rewritten_paths: Vec::new(),
             flags: raw.flags,
             num_meshes: raw.num_meshes,
             num_materials: raw.num_materials,
@@ -272,6 +383,10 @@ impl<'a> Scene<'a> {
         }
     }
 
+    fn raw(&self) -> &RawScene<'a> {
+        unsafe { &*self.raw_scene }
+    }
+
     /// Check if the given scene flag is set
     ///
     /// See `SceneFlags`
@@ -286,7 +401,7 @@ impl<'a> Scene<'a> {
     /// the format and content of the imported file.
     pub fn get_root_node(&self) -> &Node {
         unsafe {
-            &*(self.raw_scene.root_node)
+            &*(self.raw().root_node)
         }
     }
 
@@ -294,8 +409,8 @@ impl<'a> Scene<'a> {
     ///
     /// All animations imported from the given file are listed here.
     pub fn get_animations(&self) -> &[&Animation] {
-        unsafe { ptr_ptr_to_slice(self.raw_scene.animations,
-                                  self.raw_scene.num_animations as uint) }
+        unsafe { ptr_ptr_to_slice(self.raw().animations,
+                                  self.raw().num_animations as uint) }
     }
 
     /// Get the array of meshes.
@@ -304,16 +419,16 @@ impl<'a> Scene<'a> {
     /// this array. If the `SceneFlags::Incomplete` flag is not set there
     /// will always be at least one mesh.
     pub fn get_meshes(&self) -> &[&Mesh] {
-        unsafe { ptr_ptr_to_slice(self.raw_scene.meshes,
-                                  self.raw_scene.num_meshes as uint) }
+        unsafe { ptr_ptr_to_slice(self.raw().meshes,
+                                  self.raw().num_meshes as uint) }
     }
 
     /// Get the array of light sources.
     ///
     /// All light sources imported from the given file are listed here.
     pub fn get_lights(&self) -> &[&Light] {
-        unsafe { ptr_ptr_to_slice(self.raw_scene.lights,
-                                  self.raw_scene.num_lights as uint) }
+        unsafe { ptr_ptr_to_slice(self.raw().lights,
+                                  self.raw().num_lights as uint) }
     }
 
     /// Get the array of cameras.
@@ -322,8 +437,8 @@ impl<'a> Scene<'a> {
     /// The first camera in the array (if existing) is the default camera view
     /// into the scene.
     pub fn get_cameras(&self) -> &[&Camera] {
-        unsafe { ptr_ptr_to_slice(self.raw_scene.cameras,
-                                  self.raw_scene.num_cameras as uint) }
+        unsafe { ptr_ptr_to_slice(self.raw().cameras,
+                                  self.raw().num_cameras as uint) }
     }
 
     /// Get the array of materials.
@@ -332,8 +447,100 @@ impl<'a> Scene<'a> {
     /// array. If the `SceneFlags::Incomplete` flag is not set there will
     /// always be at least ONE material.
     pub fn get_materials(&self) -> &[&Material] {
-        unsafe { ptr_ptr_to_slice(self.raw_scene.materials,
-                                  self.raw_scene.num_materials as uint) }
+        unsafe { ptr_ptr_to_slice(self.raw().materials,
+                                  self.raw().num_materials as uint) }
+    }
+
+    /// Get the material at `index` for modification.
+    ///
+    /// The first call for a material replaces it in the scene with an
+    /// `EditableMaterial` holding a copy of its properties, so the edits are
+    /// seen by `get_materials` and carried over by `clone`. Use `clone` to
+    /// get a copy of the scene first if the imported scene must stay
+    /// untouched.
+    ///
+    /// Returns `None` if the scene has no material at `index`.
+    pub fn edit_material(&mut self, index: uint) -> Option<&mut EditableMaterial> {
+        if index >= self.raw().num_materials as uint {
+            return None
+        }
+        let pos = match self.edited_materials.iter().position(|&(i, _, _)| i == index) {
+            Some(pos) => pos,
+            None => unsafe {
+                let slot = self.raw().materials.offset(index as int);
+                let original = *slot;
+                let mut edited = box EditableMaterial::from_material(&*original);
+                *slot = edited.as_mut_ptr();
+                self.edited_materials.push((index, original, edited));
+                self.edited_materials.len() - 1
+            },
+        };
+        match self.edited_materials.get_mut(pos) {
+            Some(&mut (_, _, ref mut edited)) => Some(&mut **edited),
+            None => None,
+        }
+    }
+
+    /// Find the groups of materials with identical content.
+    ///
+    /// Unlike `Process::RemoveRedundantMaterials` this leaves the scene
+    /// untouched and reports the mapping itself, see
+    /// `material::group_identical`.
+    pub fn find_identical_materials(&self) -> MaterialGroups {
+        material::group_identical(self.get_materials())
+    }
+
+    /// Create a chain of `levels` simplified versions of each mesh, see
+    /// `simplify::lod_chain`.
+    ///
+    /// Returns an error if a mesh contains polygons.
+    pub fn lod_chains(&self, levels: uint, ratio: f32)
+        -> Result<Vec<Vec<simplify::Lod>>, &'static str> {
+        simplify::lod_chains(self.get_meshes(), levels, ratio)
+    }
+
+    /// Optimise the triangle and vertex order of each mesh for a vertex cache
+    /// of `cache_size` entries, see `optimize::optimize`.
+    ///
+    /// Returns an error if a mesh contains polygons.
+    pub fn optimize_meshes(&self, cache_size: uint)
+        -> Result<Vec<(OwnedMesh, optimize::Report)>, &'static str> {
+        let mut result = Vec::with_capacity(self.num_meshes as uint);
+        for mesh in self.get_meshes().iter() {
+            result.push(try!(optimize::optimize_mesh(*mesh, cache_size)));
+        }
+        Ok(result)
+    }
+
+    /// Partition each mesh into meshlets, see `meshlet::build`.
+    ///
+    /// Returns an error if the limits are invalid or a mesh contains
+    /// polygons.
+    pub fn build_meshlets(&self, max_vertices: uint, max_triangles: uint)
+        -> Result<Vec<Vec<meshlet::Meshlet>>, &'static str> {
+        let mut result = Vec::with_capacity(self.num_meshes as uint);
+        for mesh in self.get_meshes().iter() {
+            result.push(try!(meshlet::build(*mesh, max_vertices, max_triangles)));
+        }
+        Ok(result)
+    }
+
+    /// Build a bounding volume hierarchy over the triangles of all mesh
+    /// instances, for ray casts and closest point queries.
+    pub fn bvh(&self) -> Bvh {
+        Bvh::new(self)
+    }
+
+    /// The axis-aligned bounding box of all meshes in the node hierarchy,
+    /// see `Node::aabb`.
+    pub fn aabb(&self) -> Aabb {
+        self.get_root_node().aabb(self)
+    }
+
+    /// A sphere enclosing all meshes in the node hierarchy, see
+    /// `Node::bounding_sphere`
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        self.get_root_node().bounding_sphere(self)
     }
 
     /// Get the array of embedded textures.
@@ -342,15 +549,109 @@ impl<'a> Scene<'a> {
     /// An example is Quake's MDL format (which is also used by
     /// some GameStudio versions)
     pub fn get_textures(&self) -> &[&Texture] {
-        unsafe { ptr_ptr_to_slice(self.raw_scene.textures,
-                                  self.raw_scene.num_textures as uint) }
+        unsafe { ptr_ptr_to_slice(self.raw().textures,
+                                  self.raw().num_textures as uint) }
+    }
+
+    /// Write the embedded textures of this scene to the directory `dir`.
+    ///
+    /// Compressed textures are written byte-for-byte with the extension of
+    /// their format, see `Texture::get_compressed_format`. The format hint
+    /// of an unknown format is only used as extension if it consists of
+    /// lower-case letters and digits, otherwise `bin` is used. Uncompressed
+    /// textures are encoded as PNG files. The directory is created if it
+    /// does not exist.
+    ///
+    /// The scene itself is not modified. Instead a map from the name
+    /// materials use to reference an embedded texture (`*0`, `*1`, ...) to
+    /// the path of the written file is returned, which is the way to find
+    /// the file of a texture reference. To make the extracted files part of
+    /// an exported scene, pass the map to `rewrite_texture_paths`.
+    ///
+    /// Returns an `InvalidInput` error for uncompressed textures without
+    /// any texels, which can't be written as PNG.
+    pub fn extract_embedded_textures(&self, dir: &Path)
+                                     -> IoResult<HashMap<String, Path>> {
+        try!(fs::mkdir_recursive(dir, io::USER_RWX));
+
+        let mut paths = HashMap::new();
+        for (i, texture) in self.get_textures().iter().enumerate() {
+            let path = match texture.get_texture_data() {
+                TextureData::Encoded { data, .. } => {
+                    let ext = match texture.get_compressed_format() {
+                        Some(format) => format.extension().to_string(),
+                        None => match texture.get_format_hint() {
+                            Some(ref hint) if hint.chars().all(|c| {
+                                (c >= 'a' && c <= 'z') || (c >= '0' && c <= '9')
+                            }) => hint.clone(),
+                            _ => "bin".to_string(),
+                        },
+                    };
+                    let path = dir.join(format!("texture_{}.{}", i, ext));
+                    try!(try!(File::create(&path)).write(data));
+                    path
+                }
+                TextureData::Decoded { .. } => {
+                    let img = try!(texture.to_rgba_image().map_err(|e| IoError {
+                        kind: InvalidInput,
+                        desc: "failed to convert the texture",
+                        detail: Some(e),
+                    }));
+                    let path = dir.join(format!("texture_{}.png", i));
+                    try!(img.write_png(&mut try!(File::create(&path))));
+                    path
+                }
+            };
+            let _ = paths.insert(format!("*{}", i), path);
+        }
+        Ok(paths)
+    }
+
+    /// Replace the texture references of the materials which are keys of
+    /// `paths` with the path they map to, such as the map returned by
+    /// `extract_embedded_textures`.
+    ///
+    /// The paths are stored as given, so pass relative paths if an exported
+    /// scene should reference the textures relative to the model. Returns
+    /// the number of references rewritten.
+    pub fn rewrite_texture_paths(&mut self,
+                                 paths: &HashMap<String, Path>) -> uint {
+        // compare the encoded values instead of decoding every property
+        let paths: Vec<(Vec<u8>, String)> = paths.iter().map(|(name, path)| {
+            (encode_string(name.as_slice()), path.display().to_string())
+        }).collect();
+
+        let mut count = 0;
+        for i in range(0, self.raw().num_materials as int) {
+            let material = unsafe { &**self.raw().materials.offset(i) };
+            for &property in material.get_raw_properties().iter() {
+                let path = unsafe {
+                    if (*property).key.as_str() != Some("$tex.file") {
+                        continue
+                    }
+                    match paths.iter().find(|&&(ref name, _)| {
+                        name.as_slice() == (*property).get_data()
+                    }) {
+                        Some(&(_, ref path)) => path,
+                        None => continue,
+                    }
+                };
+                let mut data = encode_string(path.as_slice());
+                let (previous, previous_length) = unsafe {
+                    (*property).swap_data(data.as_mut_ptr(), data.len() as c_uint)
+                };
+                self.rewritten_paths.push((property, previous, previous_length, data));
+                count += 1;
+            }
+        }
+        count
     }
 
     /// Get the amount of memory used to store this scene.
     pub fn get_memory_info(&self) -> MemoryInfo {
         unsafe {
             let mut mem_info = mem::zeroed();
-            ffi::aiGetMemoryRequirements(self.raw_scene, &mut mem_info);
+            ffi::aiGetMemoryRequirements(self.raw(), &mut mem_info);
             mem_info
         }
     }
@@ -367,12 +668,23 @@ impl<'a> Scene<'a> {
     ///
     /// This process can fail if using `Process::ValidateDS` in which case an
     /// error is returned and further usage of the scene is invalid.
+    ///
+    /// Scenes with materials changed by `edit_material` or texture
+    /// references changed by `rewrite_texture_paths` can't be
+    /// post-processed, since assimp would free the memory owned by Rust. Use
+    /// `clone` to get a copy with the edits in assimp's memory first.
     pub fn apply_postprocessing(&mut self,
                                 steps: &[Process])
                                 -> Result<(), &str> {
+        if !self.edited_materials.is_empty() {
+            return Err("Scenes with edited materials can't be post processed")
+        }
+        if !self.rewritten_paths.is_empty() {
+            return Err("Scenes with rewritten texture paths can't be post processed")
+        }
         unsafe {
             let flags = steps.iter().fold(0, |x, &y| x | y as u32);
-            let scene = ffi::aiApplyPostProcessing(self.raw_scene,
+            let scene = ffi::aiApplyPostProcessing(self.raw_scene as *const RawScene,
                                                    flags);
             if scene.is_null() {
                 //TODO: invalidate the scene
@@ -405,23 +717,201 @@ impl<'a> fmt::Show for Scene<'a> {
 #[unsafe_destructor]
 impl<'a> Drop for Scene<'a> {
     fn drop(&mut self) {
-        // NOTE: it's okay to call this on a scene returned by aiCopyScene
-        // based on comments in the assimp source code
-        unsafe { ffi::aiReleaseImport(mem::transmute(self.raw_scene)) }
+        unsafe {
+            // assimp frees the materials and property buffers with C++
+            // delete, so put back the ones it allocated, the edited copies
+            // and new paths are freed by Rust
+            for &(index, original, _) in self.edited_materials.iter() {
+                *self.raw().materials.offset(index as int) = original;
+            }
+            for &(property, data, data_length, _) in self.rewritten_paths.iter().rev() {
+                let _ = (*property).swap_data(data, data_length);
+            }
+            // NOTE: it's okay to call this on a scene returned by aiCopyScene
+            // based on comments in the assimp source code
+            ffi::aiReleaseImport(self.raw_scene as *const RawScene)
+        }
+    }
+}
+
+/// Encode a string the way assimp stores string properties: a 32 bit length
+/// followed by the zero terminated characters
+fn encode_string(s: &str) -> Vec<u8> {
+    let length: [u8, ..4] = unsafe { mem::transmute(s.len() as u32) };
+    let mut data = length.to_vec();
+    data.push_all(s.as_bytes());
+    data.push(0);
+    data
+}
+
+
+/// Creates a modifiable copy of the scene
+///
+/// Materials changed by `Scene::edit_material` and texture references
+/// changed by `Scene::rewrite_texture_paths` are copied with their edits
+/// into memory owned by assimp.
+impl<'a> Clone for Scene<'a> {
+    fn clone(&self) -> Scene<'a> {
+        unsafe {
+            let mut raw_copy : *mut RawScene = ptr::null_mut();
+            ffi::aiCopyScene(self.raw_scene as *const RawScene, &mut raw_copy);
+            if raw_copy.is_null() {
+                panic!("failed to clone scene")
+            } else {
+                Scene::from_raw_scene(raw_copy as *const RawScene)
+            }
+        }
     }
 }
 
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::io::{File, TempDir};
+    use std::ptr;
+
+    use importer::Importer;
+    use material::TextureType;
+    use postprocess::Process;
+    use test_util::vec3;
+    use texture::{Texture, Texel};
+    use types::{Vector3D, AiString};
+    use super::Scene;
+
+    /// Make `textures` the embedded textures of `scene`
+    fn set_textures(scene: &mut Scene, textures: *mut *mut Texture, num_textures: uint) {
+        unsafe {
+            (*scene.raw_scene).textures = textures;
+            (*scene.raw_scene).num_textures = num_textures as u32;
+        }
+    }
 
-// impl<'a> Clone for Scene<'a> {
-//     fn clone(&self) -> Scene<'a> {
-//         unsafe {
-//             let raw_copy : *mut*mut RawScene= ptr::null_mut();
-//             ffi::aiCopyScene(self.raw_scene, raw_copy);
-//             if raw_copy.is_null() {
-//                 panic!("failed to clone scene")
-//             } else {
-//                 Scene::from_raw_scene(*raw_copy)
-//             }
-//         }
-//     }
-// }
+    #[test]
+    fn test_extract_embedded_textures() {
+        let imp = Importer::new();
+        let mut scene = imp.import_from_file("examples/assets/cube.dae").unwrap();
+
+        let mut png = b"\x89PNG\r\n\x1a\nnot really a png".to_vec();
+        let mut texels = vec![Texel { b: 0, g: 0, r: 255, a: 255 }];
+        let mut compressed = Texture::from_encoded(png.as_mut_slice(), b"PNG");
+        let mut uncompressed = Texture::from_texels(1, 1, texels.as_mut_slice());
+        let mut textures = vec![&mut compressed as *mut Texture,
+                                &mut uncompressed as *mut Texture];
+        set_textures(&mut scene, textures.as_mut_ptr(), textures.len());
+
+        let dir = TempDir::new("extract_embedded_textures").unwrap();
+        let paths = scene.extract_embedded_textures(dir.path()).unwrap();
+        assert_eq!(paths.len(), 2);
+        let compressed_path = dir.path().join("texture_0.png");
+        let uncompressed_path = dir.path().join("texture_1.png");
+        assert_eq!(paths.get(&"*0".to_string()), Some(&compressed_path));
+        assert_eq!(paths.get(&"*1".to_string()), Some(&uncompressed_path));
+        assert_eq!(File::open(&compressed_path).read_to_end().unwrap(), png);
+        let written = File::open(&uncompressed_path).read_to_end().unwrap();
+        assert!(written.as_slice().starts_with(b"\x89PNG\r\n\x1a\n"));
+
+        // uncompressed textures without texels can't be written
+        let mut empty = Texture::from_texels(0, 1, &mut []);
+        let mut textures = vec![&mut empty as *mut Texture];
+        set_textures(&mut scene, textures.as_mut_ptr(), textures.len());
+        assert!(scene.extract_embedded_textures(dir.path()).is_err());
+
+        // the textures are owned by the test, not by assimp
+        set_textures(&mut scene, ptr::null_mut(), 0);
+    }
+
+    fn assert_near(a: &Vector3D, b: &Vector3D) {
+        assert!((*a - *b).norm() < 1e-4);
+    }
+
+    #[test]
+    fn test_instanced_bounds() {
+        // a cube instanced by a node and, scaled down, by its child
+        let imp = Importer::new();
+        let scene = imp.import_from_file("examples/assets/nested.dae").unwrap();
+        assert_eq!(scene.num_meshes, 1);
+
+        let mut inner = None;
+        let mut instances = 0u;
+        let mut stack = vec![scene.get_root_node()];
+        loop {
+            let node = match stack.pop() {
+                Some(node) => node,
+                None => break,
+            };
+            stack.push_all(node.get_children());
+            instances += node.get_meshes().len();
+            if node.name == AiString::from_str("Inner") {
+                inner = Some(node);
+            }
+        }
+        assert_eq!(instances, 2);
+
+        // the outer cube is turned around z and moved along x, the inner
+        // one is halved and moved along y before that
+        let outer_corners = [vec3(4.0, -1.0, -1.0), vec3(6.0, 1.0, 1.0)];
+        let inner_corners = [vec3(0.5, -0.5, -0.5), vec3(1.5, 0.5, 0.5)];
+        let aabb = scene.aabb();
+        assert_near(&aabb.min, &vec3(0.5, -1.0, -1.0));
+        assert_near(&aabb.max, &outer_corners[1]);
+        let inner_aabb = inner.unwrap().aabb(&scene);
+        assert_near(&inner_aabb.min, &inner_corners[0]);
+        assert_near(&inner_aabb.max, &inner_corners[1]);
+
+        let sphere = scene.bounding_sphere();
+        assert!(sphere.radius < aabb.size().norm() * 0.6);
+        for corners in [outer_corners, inner_corners].iter() {
+            for &x in [corners[0].x, corners[1].x].iter() {
+                for &y in [corners[0].y, corners[1].y].iter() {
+                    for &z in [corners[0].z, corners[1].z].iter() {
+                        let d = vec3(x, y, z) - sphere.center;
+                        assert!(d.norm() <= sphere.radius + 1e-4);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The first diffuse texture of each material of `scene`
+    fn diffuse(scene: &Scene) -> Vec<Option<String>> {
+        scene.get_materials().iter()
+             .map(|mat| mat.get_texture(TextureType::Diffuse, 0))
+             .collect()
+    }
+
+    #[test]
+    fn test_rewrite_texture_paths() {
+        let imp = Importer::new();
+        let mut scene = imp.import_from_file("examples/assets/guard-md5/guard.md5mesh")
+                           .unwrap();
+        let before = diffuse(&scene);
+        let body = Some("guard_body.tga".to_string());
+        let num_body = before.iter().filter(|path| **path == body).count();
+        assert!(num_body > 0);
+
+        let mut paths = HashMap::new();
+        let _ = paths.insert("guard_body.tga".to_string(),
+                             Path::new("textures/guard_body.png"));
+        let _ = paths.insert("*0".to_string(), Path::new("texture_0.png"));
+        assert_eq!(scene.rewrite_texture_paths(&paths), num_body);
+        let after = diffuse(&scene);
+        for (old, new) in before.iter().zip(after.iter()) {
+            if *old == body {
+                assert_eq!(*new, Some("textures/guard_body.png".to_string()));
+            } else {
+                assert_eq!(new, old);
+            }
+        }
+
+        // rewritten references can be rewritten again
+        let mut paths = HashMap::new();
+        let _ = paths.insert("textures/guard_body.png".to_string(),
+                             Path::new("body.png"));
+        assert_eq!(scene.rewrite_texture_paths(&paths), num_body);
+        assert_eq!(diffuse(&scene).iter().filter(|path| {
+            **path == Some("body.png".to_string())
+        }).count(), num_body);
+
+        assert!(scene.apply_postprocessing(&[Process::Triangulate]).is_err());
+    }
+}
@@ -0,0 +1,560 @@
+//! Mesh simplification using quadric error metrics.
+//!
+//! Meshes are simplified by repeatedly collapsing an edge into one of its
+//! vertices, picking the collapses which move the surface the least as
+//! measured by the quadric error metric of Garland and Heckbert. Since
+//! vertices only ever move onto other vertices, all their attributes such as
+//! normals, texture coordinates and bone weights are kept as they are.
+//!
+//! Vertices on the border of the mesh and on attribute seams, i.e. positions
+//! shared by several vertices with different attributes, are never removed,
+//! so the outline of the mesh and its texture mapping stay intact.
+
+use std::collections::{HashMap, BinaryHeap};
+use std::cmp::Ordering;
+use std::mem;
+use std::num::Float;
+
+use mesh::{Mesh, OwnedMesh};
+use types::Vector3D;
+use util::weld_exact;
+
+/// A simplified mesh, see `simplify`
+#[deriving(Clone, Show)]
+pub struct Lod {
+    /// The simplified mesh
+    pub mesh: OwnedMesh,
+    /// The largest error of any collapse: the root mean square distance of
+    /// the new vertex position to the planes of the triangles it replaces,
+    /// weighted by their area, in mesh units
+    pub error: f32,
+}
+
+/// Simplify `mesh` to at most `target_triangles` triangles, without
+/// collapses moving the surface further than `max_error`.
+///
+/// Simplification stops early if no further edge can be collapsed within
+/// `max_error`, use infinity to only limit the triangle count. Unused
+/// vertices are removed from the result.
+pub fn simplify(mesh: &OwnedMesh, target_triangles: uint, max_error: f32) -> Lod {
+    let mut simplifier = Simplifier::new(mesh);
+    let error = simplifier.run(target_triangles, max_error);
+
+    let mut result = mesh.clone();
+    result.indices = simplifier.indices();
+    result.remove_unused_vertices();
+    Lod { mesh: result, error: error }
+}
+
+/// Create a chain of `levels` simplified versions of `mesh`.
+///
+/// The first level is the mesh itself, each following level has `ratio`
+/// times the triangles of the previous one. Levels are derived from each
+/// other, and the chain ends early if a level couldn't be simplified any
+/// further.
+pub fn lod_chain(mesh: &OwnedMesh, levels: uint, ratio: f32) -> Vec<Lod> {
+    let mut chain = Vec::with_capacity(levels);
+    if levels == 0 {
+        return chain
+    }
+    chain.push(Lod { mesh: mesh.clone(), error: 0.0 });
+
+    let infinity: f32 = Float::infinity();
+    for _ in range(1, levels) {
+        let next = {
+            let previous = chain.last().unwrap();
+            let triangles = previous.mesh.num_triangles();
+            let target = (triangles as f32 * ratio) as uint;
+            let mut lod = simplify(&previous.mesh, target, infinity);
+            if lod.mesh.num_triangles() >= triangles {
+                break
+            }
+            lod.error = lod.error.max(previous.error);
+            lod
+        };
+        chain.push(next);
+    }
+    chain
+}
+
+/// Create a LOD chain for each mesh in `meshes`, see `lod_chain`.
+///
+/// Returns an error if a mesh contains polygons, see
+/// `OwnedMesh::from_mesh`.
+pub fn lod_chains(meshes: &[&Mesh], levels: uint, ratio: f32)
+    -> Result<Vec<Vec<Lod>>, &'static str> {
+    let mut chains = Vec::with_capacity(meshes.len());
+    for mesh in meshes.iter() {
+        let owned = try!(OwnedMesh::from_mesh(*mesh));
+        chains.push(lod_chain(&owned, levels, ratio));
+    }
+    Ok(chains)
+}
+
+/// A symmetric 4x4 matrix measuring the squared distance to a set of
+/// planes, and the total weight of the planes
+#[deriving(Clone)]
+struct Quadric {
+    a: [f64, ..10],
+    weight: f64,
+}
+
+impl Quadric {
+    fn zero() -> Quadric {
+        Quadric { a: [0.0, ..10], weight: 0.0 }
+    }
+
+    /// The quadric of the plane through `p` with the unit normal `n`,
+    /// scaled by `weight`
+    fn from_plane(n: &Vector3D, p: &Vector3D, weight: f64) -> Quadric {
+        let (a, b, c) = (n.x as f64, n.y as f64, n.z as f64);
+        let d = -(a * p.x as f64 + b * p.y as f64 + c * p.z as f64);
+        Quadric {
+            a: [a * a * weight, a * b * weight, a * c * weight, a * d * weight,
+                b * b * weight, b * c * weight, b * d * weight,
+                c * c * weight, c * d * weight,
+                d * d * weight],
+            weight: weight,
+        }
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for i in range(0u, 10) {
+            self.a[i] += other.a[i];
+        }
+        self.weight += other.weight;
+    }
+
+    /// The weighted mean of the squared distances of `p` to the planes
+    fn error(&self, p: &Vector3D) -> f64 {
+        if self.weight <= 0.0 {
+            return 0.0
+        }
+        let (x, y, z) = (p.x as f64, p.y as f64, p.z as f64);
+        let q = &self.a;
+        let e = q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x +
+                q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y +
+                q[7] * z * z + 2.0 * q[8] * z +
+                q[9];
+        (e / self.weight).max(0.0)
+    }
+}
+
+/// A candidate collapse of the vertex `from` into the vertex `to`, valid as
+/// long as the versions of both vertices haven't changed
+struct Collapse {
+    from: u32,
+    to: u32,
+    cost: f64,
+    versions: (uint, uint),
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Collapse) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse { }
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Collapse) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    /// Cheaper collapses are greater, so they come first in a `BinaryHeap`
+    fn cmp(&self, other: &Collapse) -> Ordering {
+        other.cost.partial_cmp(&self.cost).unwrap_or(Ordering::Equal)
+    }
+}
+
+/// The state of the simplification of a mesh
+struct Simplifier<'a> {
+    mesh: &'a OwnedMesh,
+    /// The vertices of each triangle, `None` once removed
+    triangles: Vec<Option<[u32, ..3]>>,
+    /// The triangles using each vertex, may contain removed triangles
+    vertex_triangles: Vec<Vec<uint>>,
+    quadrics: Vec<Quadric>,
+    /// Vertices which may not be removed
+    locked: Vec<bool>,
+    /// The vertex each vertex was collapsed into, or itself
+    remap: Vec<u32>,
+    /// Incremented whenever the quadric of a vertex changes, to invalidate
+    /// the candidate collapses using it
+    versions: Vec<uint>,
+    num_triangles: uint,
+}
+
+impl<'a> Simplifier<'a> {
+    fn new(mesh: &'a OwnedMesh) -> Simplifier<'a> {
+        let num_vertices = mesh.vertices.len();
+        let triangles: Vec<Option<[u32, ..3]>> = mesh.indices.chunks(3)
+            .map(|t| Some([t[0], t[1], t[2]]))
+            .collect();
+
+        let mut vertex_triangles = Vec::from_fn(num_vertices, |_| Vec::new());
+        let mut quadrics = Vec::from_elem(num_vertices, Quadric::zero());
+        for (i, triangle) in triangles.iter().enumerate() {
+            let t = triangle.unwrap();
+            let (p0, p1, p2) = (mesh.vertices[t[0] as uint],
+                                mesh.vertices[t[1] as uint],
+                                mesh.vertices[t[2] as uint]);
+            let normal = (p1 - p0).cross(&(p2 - p0));
+            let area = normal.norm();
+            let quadric = if area > 0.0 {
+                Quadric::from_plane(&(normal * (1.0 / area)), &p0, area as f64 * 0.5)
+            } else {
+                Quadric::zero()
+            };
+            for &v in t.iter() {
+                vertex_triangles[v as uint].push(i);
+                quadrics[v as uint].add(&quadric);
+            }
+        }
+
+        let locked = find_locked(mesh);
+        Simplifier {
+            mesh: mesh,
+            num_triangles: triangles.len(),
+            triangles: triangles,
+            vertex_triangles: vertex_triangles,
+            quadrics: quadrics,
+            locked: locked,
+            remap: range(0, num_vertices as u32).collect(),
+            versions: Vec::from_elem(num_vertices, 0),
+        }
+    }
+
+    /// The remaining triangles as a triangle list
+    fn indices(&self) -> Vec<u32> {
+        let mut indices = Vec::with_capacity(self.num_triangles * 3);
+        for triangle in self.triangles.iter() {
+            match *triangle {
+                Some(t) => indices.push_all(&t),
+                None => { }
+            }
+        }
+        indices
+    }
+
+    /// Collapse edges until the target is reached, returns the largest
+    /// error of a collapse
+    fn run(&mut self, target_triangles: uint, max_error: f32) -> f32 {
+        let max_cost = max_error as f64 * max_error as f64;
+        let mut worst = 0.0f64;
+
+        let mut heap = BinaryHeap::new();
+        for from in range(0, self.remap.len()) {
+            for to in self.neighbours(from).into_iter() {
+                self.push_collapse(&mut heap, from, to, max_cost);
+            }
+        }
+
+        while self.num_triangles > target_triangles {
+            let collapse = match heap.pop() {
+                Some(collapse) => collapse,
+                None => break,
+            };
+            let (from, to) = (collapse.from as uint, collapse.to as uint);
+            if collapse.versions != (self.versions[from], self.versions[to]) ||
+               self.flips(from, to) {
+                continue
+            }
+            self.collapse(from, to);
+            worst = worst.max(collapse.cost);
+
+            // the quadric of `to` changed, so all its edges cost differently
+            for n in self.neighbours(to).into_iter() {
+                self.push_collapse(&mut heap, to, n, max_cost);
+                self.push_collapse(&mut heap, n, to, max_cost);
+            }
+        }
+        worst.sqrt() as f32
+    }
+
+    /// The vertices sharing an edge with `vertex`
+    fn neighbours(&self, vertex: uint) -> Vec<uint> {
+        let mut neighbours = Vec::new();
+        for &t in self.vertex_triangles[vertex].iter() {
+            match self.triangles[t] {
+                Some(triangle) => {
+                    for &v in triangle.iter() {
+                        if v as uint != vertex {
+                            neighbours.push(v as uint);
+                        }
+                    }
+                }
+                None => { }
+            }
+        }
+        neighbours.sort();
+        neighbours.dedup();
+        neighbours
+    }
+
+    /// Add the collapse of `from` into `to` to `heap` if it is allowed and
+    /// costs at most `max_cost`
+    fn push_collapse(&self, heap: &mut BinaryHeap<Collapse>, from: uint, to: uint,
+                     max_cost: f64) {
+        if self.locked[from] {
+            return
+        }
+        let mut quadric = self.quadrics[from].clone();
+        quadric.add(&self.quadrics[to]);
+        let cost = quadric.error(&self.mesh.vertices[to]);
+        if cost <= max_cost {
+            heap.push(Collapse {
+                from: from as u32,
+                to: to as u32,
+                cost: cost,
+                versions: (self.versions[from], self.versions[to]),
+            });
+        }
+    }
+
+    /// Check if moving `from` onto `to` flips any triangle
+    fn flips(&self, from: uint, to: uint) -> bool {
+        let vertices = &self.mesh.vertices;
+        for &t in self.vertex_triangles[from].iter() {
+            let triangle = match self.triangles[t] {
+                Some(triangle) => triangle,
+                None => continue,
+            };
+            if triangle.iter().any(|&v| v as uint == to) {
+                // removed by the collapse
+                continue
+            }
+            let p = |v: u32| vertices[v as uint];
+            let before = (p(triangle[1]) - p(triangle[0]))
+                .cross(&(p(triangle[2]) - p(triangle[0])));
+            let moved: Vec<u32> = triangle.iter()
+                .map(|&v| if v as uint == from { to as u32 } else { v })
+                .collect();
+            let after = (p(moved[1]) - p(moved[0]))
+                .cross(&(p(moved[2]) - p(moved[0])));
+            if before.dot(&after) <= 0.0 {
+                return true
+            }
+        }
+        false
+    }
+
+    /// Collapse the vertex `from` into `to`
+    fn collapse(&mut self, from: uint, to: uint) {
+        let from_triangles = mem::replace(&mut self.vertex_triangles[from], Vec::new());
+        for &t in from_triangles.iter() {
+            let mut triangle = match self.triangles[t] {
+                Some(triangle) => triangle,
+                None => continue,
+            };
+            for v in triangle.iter_mut() {
+                if *v as uint == from {
+                    *v = to as u32;
+                }
+            }
+            if triangle[0] == triangle[1] || triangle[1] == triangle[2] ||
+               triangle[0] == triangle[2] {
+                self.triangles[t] = None;
+                self.num_triangles -= 1;
+            } else {
+                self.triangles[t] = Some(triangle);
+                self.vertex_triangles[to].push(t);
+            }
+        }
+
+        let quadric = self.quadrics[from].clone();
+        self.quadrics[to].add(&quadric);
+        self.remap[from] = to as u32;
+        self.versions[from] += 1;
+        self.versions[to] += 1;
+    }
+}
+
+/// Find the vertices on the border of the mesh or on attribute seams
+fn find_locked(mesh: &OwnedMesh) -> Vec<bool> {
+    // identify vertices by position, so seams don't look like borders, and
+    // lock the positions shared by several vertices
+    let (vertex_positions, num_positions) = weld_exact(mesh.vertices.as_slice());
+    let mut num_vertices = Vec::from_elem(num_positions, 0u);
+    for &id in vertex_positions.iter() {
+        num_vertices[id as uint] += 1;
+    }
+
+    // count the triangles using each edge in both directions
+    let mut edges: HashMap<(u32, u32), uint> = HashMap::new();
+    for t in mesh.indices.chunks(3) {
+        for i in range(0u, 3) {
+            let a = vertex_positions[t[i] as uint];
+            let b = vertex_positions[t[(i + 1) % 3] as uint];
+            let key = if a < b { (a, b) } else { (b, a) };
+            let count = match edges.get(&key) {
+                Some(count) => *count + 1,
+                None => 1,
+            };
+            let _ = edges.insert(key, count);
+        }
+    }
+
+    let mut locked_positions: Vec<bool> = num_vertices.iter().map(|&n| n > 1).collect();
+    for (&(a, b), &count) in edges.iter() {
+        if count != 2 {
+            locked_positions[a as uint] = true;
+            locked_positions[b as uint] = true;
+        }
+    }
+    vertex_positions.iter().map(|&id| locked_positions[id as uint]).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::Float;
+
+    use importer::Importer;
+    use mesh::{OwnedMesh, OwnedBone, VertexWeight};
+    use test_util::{vec3, grid_mesh};
+    use types::{Vector3D, Matrix4x4};
+    use super::{simplify, lod_chain};
+
+    #[test]
+    fn test_simplify_flat_grid() {
+        let mesh = grid_mesh(4);
+        let infinity: f32 = Float::infinity();
+        let lod = simplify(&mesh, 0, infinity);
+
+        // the border is locked, so at least its 16 vertices remain
+        assert!(lod.mesh.num_triangles() < mesh.num_triangles());
+        assert!(lod.mesh.vertices.len() >= 16);
+        assert!(lod.error < 1e-3);
+        for v in lod.mesh.vertices.iter() {
+            assert_eq!(v.z, 0.0);
+        }
+    }
+
+    #[test]
+    fn test_simplify_respects_max_error() {
+        let mut mesh = grid_mesh(2);
+        // raise the center vertex
+        mesh.vertices[4].z = 1.0;
+        let lod = simplify(&mesh, 0, 0.1);
+        assert_eq!(lod.mesh.num_triangles(), 8);
+    }
+
+    /// The weight of the test bone at `p`, different for each position
+    fn weight_at(p: &Vector3D) -> f32 {
+        (p.x + p.y * 5.0 + 1.0) / 32.0
+    }
+
+    /// A 4 by 4 grid with a texture seam down the middle and a bone
+    /// influencing every vertex
+    fn seam_mesh() -> OwnedMesh {
+        let mut mesh = grid_mesh(4);
+        let mut uvs: Vec<Vector3D> = mesh.vertices.iter()
+            .map(|v| vec3(v.x / 4.0, v.y / 4.0, 0.0))
+            .collect();
+        // the right half gets its own copies of the vertices at x = 2
+        for y in range(0u, 5) {
+            mesh.vertices.push(vec3(2.0, y as f32, 0.0));
+            mesh.normals.push(vec3(0.0, 0.0, 1.0));
+            uvs.push(vec3(1.0, y as f32 / 4.0, 0.0));
+        }
+        for t in mesh.indices.chunks_mut(3) {
+            let right = t.iter().all(|&i| i % 5 >= 2);
+            for i in t.iter_mut() {
+                if right && *i % 5 == 2 {
+                    *i = 25 + *i / 5;
+                }
+            }
+        }
+        mesh.texture_coords = vec![uvs];
+        mesh.num_uv_components = vec![2];
+
+        let weights = mesh.vertices.iter().enumerate().map(|(i, v)| {
+            VertexWeight { vertex_id: i as u32, weight: weight_at(v) }
+        }).collect();
+        mesh.bones = vec![OwnedBone {
+            name: "bone".to_string(),
+            weights: weights,
+            offset_matrix: Matrix4x4::identity(),
+        }];
+        mesh
+    }
+
+    #[test]
+    fn test_simplify_keeps_seams() {
+        let mesh = seam_mesh();
+        let infinity: f32 = Float::infinity();
+        let lod = simplify(&mesh, 0, infinity);
+        let result = &lod.mesh;
+        assert!(result.vertices.len() < mesh.vertices.len());
+
+        // both copies of the seam vertices survive, with their own
+        // texture coordinates
+        for y in range(1u, 4) {
+            let seam = vec3(2.0, y as f32, 0.0);
+            let uvs: Vec<f32> = range(0, result.vertices.len())
+                .filter(|&i| result.vertices[i] == seam)
+                .map(|i| result.texture_coords[0][i].x)
+                .collect();
+            assert_eq!(uvs, vec![0.5, 1.0]);
+        }
+
+        // the weights follow their vertices
+        let weights = &result.bones[0].weights;
+        assert_eq!(weights.len(), result.vertices.len());
+        for w in weights.iter() {
+            assert_eq!(w.weight, weight_at(&result.vertices[w.vertex_id as uint]));
+        }
+    }
+
+    #[test]
+    fn test_lod_chain() {
+        let mut mesh = grid_mesh(8);
+        for v in mesh.vertices.iter_mut() {
+            v.z = (v.x * 0.7).sin() * (v.y * 0.5).cos() * 0.5;
+        }
+        let chain = lod_chain(&mesh, 4, 0.5);
+        assert!(chain.len() >= 2);
+        assert_eq!(chain[0].mesh.num_triangles(), mesh.num_triangles());
+        assert_eq!(chain[0].error, 0.0);
+        for pair in chain.windows(2) {
+            assert!(pair[1].mesh.num_triangles() < pair[0].mesh.num_triangles());
+            assert!(pair[1].error >= pair[0].error);
+        }
+        assert!(chain.last().unwrap().error > 0.0);
+    }
+
+    #[test]
+    fn test_lod_chains() {
+        // every vertex of the cube lies on a normal seam, so none can be
+        // removed
+        let imp = Importer::new();
+        let scene = imp.import_from_file("examples/assets/nested.dae").unwrap();
+        let chains = scene.lod_chains(3, 0.5).unwrap();
+        assert_eq!(chains.len(), 1);
+        assert_eq!(chains[0].len(), 1);
+        assert_eq!(chains[0][0].mesh.num_triangles(), 12);
+    }
+
+    #[test]
+    fn test_error_scales_with_mesh() {
+        let mut mesh = grid_mesh(2);
+        mesh.vertices[4].z = 1.0;
+        let mut scaled = mesh.clone();
+        for v in scaled.vertices.iter_mut() {
+            *v = *v * 3.0;
+        }
+
+        let infinity: f32 = Float::infinity();
+        let lod = simplify(&mesh, 0, infinity);
+        let scaled_lod = simplify(&scaled, 0, infinity);
+        assert_eq!(lod.mesh.num_triangles(), 6);
+        assert_eq!(scaled_lod.mesh.num_triangles(), 6);
+        assert!(lod.error > 0.1);
+        assert!((scaled_lod.error - lod.error * 3.0).abs() < 1e-4 * scaled_lod.error);
+    }
+}
+
+// vim: et tw=78 sw=4:
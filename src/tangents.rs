@@ -0,0 +1,596 @@
+//! Tangent space generation following MikkTSpace.
+//!
+//! `Process::CalcTangentSpace` computes tangents inside assimp, which are
+//! quite different from what normal map bakers using MikkTSpace expect. The
+//! functions in this module follow the rules of MikkTSpace:
+//!
+//!  * vertices with identical position, normal and texture coordinates are
+//!    treated as one,
+//!  * the tangent of a triangle is derived from its texture coordinates,
+//!    triangles whose texture coordinates have no area don't have one, and
+//!    triangles whose positions have no area are left out entirely,
+//!  * the triangles around a vertex are grouped into fans of triangles
+//!    connected by edges and with the same orientation of their texture
+//!    coordinates. Triangles without a tangent join the first fan reaching
+//!    them and take over its orientation,
+//!  * within a fan, the tangents of the triangles are projected onto the
+//!    plane of the vertex normal and averaged, weighted by the angle of the
+//!    triangle at the vertex, measured between the edges projected onto the
+//!    same plane. Triangles whose tangent or bitangent points exactly the
+//!    other way aren't averaged with each other,
+//!  * triangles without area copy the tangents of the first other triangle
+//!    using the same vertex.
+//!
+//! Vertices whose corners end up with different tangents are split, with
+//! the orientation of the fan stored in the `w` component of the tangent.
+//!
+//! There are two deliberate differences. Where no tangent can be derived,
+//! MikkTSpace returns `(1, 0, 0)` with `w = -1`, while this module returns
+//! an arbitrary unit vector perpendicular to the normal with `w = 1`. And
+//! MikkTSpace moves triangles without area to the end of the list, which
+//! changes the order of the others; that order only matters for edges
+//! shared by more than two triangles, and for triangles without a tangent
+//! between fans of different orientation.
+
+use std::collections::HashMap;
+use std::f32::MIN_POS_VALUE;
+use std::num::Float;
+
+use mesh::{Mesh, OwnedMesh};
+use types::Vector3D;
+use util::float_bits;
+
+/// Create an `OwnedMesh` from `mesh` and generate its tangents, see
+/// `generate_tangents`.
+pub fn generate(mesh: &Mesh, uv_channel: uint) -> Result<OwnedMesh, &'static str> {
+    let mut owned = try!(OwnedMesh::from_mesh(mesh));
+    try!(generate_tangents(&mut owned, uv_channel));
+    Ok(owned)
+}
+
+/// Generate the tangents of `mesh` from the texture coordinate set
+/// `uv_channel`, replacing any existing tangents.
+///
+/// Vertices shared by triangles with differently oriented texture
+/// coordinates are split, so the mesh may have more vertices afterwards.
+/// Returns an error if the mesh has no normals or no texture coordinate set
+/// `uv_channel`.
+pub fn generate_tangents(mesh: &mut OwnedMesh, uv_channel: uint) -> Result<(), &'static str> {
+    if mesh.normals.len() != mesh.vertices.len() {
+        return Err("the mesh has no normals")
+    }
+    if uv_channel >= mesh.texture_coords.len() {
+        return Err("the mesh has no such texture coordinate set")
+    }
+    if mesh.indices.len() % 3 != 0 {
+        return Err("the mesh isn't a triangle list")
+    }
+
+    let canonical = weld(mesh, uv_channel);
+    let indices: Vec<u32> = mesh.indices.iter()
+        .map(|&vertex| canonical[vertex as uint])
+        .collect();
+    let indices = indices.as_slice();
+
+    let mut triangles: Vec<TriangleInfo> = indices.chunks(3)
+        .map(|triangle| triangle_info(mesh, uv_channel, triangle))
+        .collect();
+    find_neighbours(indices, triangles.as_mut_slice());
+    let fans = build_fans(indices, triangles.as_mut_slice());
+
+    // the tangent and orientation of every corner
+    let mut corners: Vec<Option<(Vector3D, bool)>> = Vec::from_elem(indices.len(), None);
+    for fan in fans.iter() {
+        let normal = mesh.normals[fan.vertex as uint];
+        for &t in fan.triangles.iter() {
+            let members = similar_triangles(triangles.as_slice(), fan, t, &normal);
+            let tangent = fan_tangent(mesh, indices, triangles.as_slice(),
+                                      members.as_slice(), fan.vertex, &normal);
+            let corner = corner_of(indices, t, fan.vertex).unwrap();
+            corners[3 * t + corner] = Some((tangent, fan.preserves_orientation));
+        }
+    }
+
+    for t in range(0, triangles.len()) {
+        if !triangles[t].degenerate {
+            continue
+        }
+        for corner in range(3 * t, 3 * t + 3) {
+            let vertex = indices[corner];
+            let tangent = range(0, indices.len())
+                .find(|&other| indices[other] == vertex && !triangles[other / 3].degenerate)
+                .and_then(|other| corners[other]);
+            corners[corner] = tangent;
+        }
+    }
+
+    // every vertex takes the tangent of its first corner, corners with
+    // another tangent get a copy
+    let mut vertex_tangents: Vec<Option<[f32, ..4]>> = Vec::from_elem(mesh.vertices.len(), None);
+    let mut copies: HashMap<(u32, u32, u32, u32, u32), u32> = HashMap::new();
+    let mut sources = Vec::new();
+    let mut copy_tangents = Vec::new();
+    let num_vertices = mesh.vertices.len() as u32;
+    for (i, corner) in corners.iter().enumerate() {
+        let vertex = mesh.indices[i];
+        let tangent = match *corner {
+            Some((tangent, preserves_orientation)) if not_zero_vector(&tangent) => {
+                let w = if preserves_orientation { 1.0 } else { -1.0 };
+                [tangent.x, tangent.y, tangent.z, w]
+            }
+            Some((_, preserves_orientation)) => {
+                let tangent = any_perpendicular(&mesh.normals[vertex as uint]);
+                let w = if preserves_orientation { 1.0 } else { -1.0 };
+                [tangent.x, tangent.y, tangent.z, w]
+            }
+            None => {
+                let tangent = any_perpendicular(&mesh.normals[vertex as uint]);
+                [tangent.x, tangent.y, tangent.z, 1.0]
+            }
+        };
+        match vertex_tangents[vertex as uint] {
+            None => vertex_tangents[vertex as uint] = Some(tangent),
+            Some(first) if first == tangent => { }
+            Some(_) => {
+                let key = (vertex, float_bits(tangent[0]), float_bits(tangent[1]),
+                           float_bits(tangent[2]), float_bits(tangent[3]));
+                let copy = match copies.get(&key) {
+                    Some(copy) => *copy,
+                    None => {
+                        let copy = num_vertices + sources.len() as u32;
+                        let _ = copies.insert(key, copy);
+                        sources.push(vertex);
+                        copy_tangents.push(tangent);
+                        copy
+                    }
+                };
+                mesh.indices[i] = copy;
+            }
+        }
+    }
+    mesh.tangents = Vec::new();
+    mesh.duplicate_vertices(sources.as_slice());
+
+    let mut tangents: Vec<[f32, ..4]> = vertex_tangents.iter()
+        .map(|&tangent| tangent.unwrap_or([1.0, 0.0, 0.0, 1.0]))
+        .collect();
+    tangents.push_all(copy_tangents.as_slice());
+    mesh.tangents = tangents;
+
+    Ok(())
+}
+
+/// What the tangent generation needs to know about a triangle
+struct TriangleInfo {
+    /// Unit tangent, zero if the texture coordinates don't define one
+    tangent: Vector3D,
+    /// Unit bitangent, zero if the texture coordinates don't define one
+    bitangent: Vector3D,
+    /// Whether the texture coordinates preserve the orientation of the
+    /// triangle
+    preserves_orientation: bool,
+    /// Whether the triangle has no tangent of its own and fits into any fan
+    group_with_any: bool,
+    /// Whether the positions of the triangle have no area
+    degenerate: bool,
+    /// The triangle across the edge from each corner to the next one
+    neighbours: [Option<uint>, ..3],
+    /// The fan each corner belongs to
+    fans: [Option<uint>, ..3],
+}
+
+/// Triangles connected by edges around a vertex, whose texture coordinates
+/// have the same orientation
+struct Fan {
+    vertex: u32,
+    preserves_orientation: bool,
+    triangles: Vec<uint>,
+}
+
+/// Find the first vertex with the same position, normal and texture
+/// coordinates for every vertex
+fn weld(mesh: &OwnedMesh, uv_channel: uint) -> Vec<u32> {
+    let uvs = mesh.texture_coords[uv_channel].as_slice();
+    let mut first: HashMap<Vec<u32>, u32> = HashMap::new();
+
+    range(0, mesh.vertices.len()).map(|i| {
+        let (p, n, uv) = (&mesh.vertices[i], &mesh.normals[i], &uvs[i]);
+        let key: Vec<u32> = [p.x, p.y, p.z, n.x, n.y, n.z, uv.x, uv.y].iter()
+            .map(|&f| float_bits(f))
+            .collect();
+        match first.get(&key) {
+            Some(vertex) => return *vertex,
+            None => { }
+        }
+        let _ = first.insert(key, i as u32);
+        i as u32
+    }).collect()
+}
+
+/// The unit tangent and bitangent of a triangle and how it can be grouped
+fn triangle_info(mesh: &OwnedMesh, uv_channel: uint, triangle: &[u32]) -> TriangleInfo {
+    let uvs = mesh.texture_coords[uv_channel].as_slice();
+    let (i0, i1, i2) = (triangle[0] as uint, triangle[1] as uint, triangle[2] as uint);
+    let (p0, p1, p2) = (mesh.vertices[i0], mesh.vertices[i1], mesh.vertices[i2]);
+
+    let s1 = p1 - p0;
+    let s2 = p2 - p0;
+    let (d1x, d1y) = (uvs[i1].x - uvs[i0].x, uvs[i1].y - uvs[i0].y);
+    let (d2x, d2y) = (uvs[i2].x - uvs[i0].x, uvs[i2].y - uvs[i0].y);
+    let area = d1x * d2y - d1y * d2x;
+
+    let mut info = TriangleInfo {
+        tangent: s1 * d2y - s2 * d1y,
+        bitangent: s2 * d1x - s1 * d2x,
+        preserves_orientation: area > 0.0,
+        group_with_any: true,
+        degenerate: p0 == p1 || p0 == p2 || p1 == p2,
+        neighbours: [None, None, None],
+        fans: [None, None, None],
+    };
+    if !not_zero(area) {
+        // degenerate texture coordinates don't define a tangent
+        info.tangent = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+        info.bitangent = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+        return info
+    }
+
+    let sign = if info.preserves_orientation { 1.0 } else { -1.0 };
+    let (tangent_length, bitangent_length) = (info.tangent.norm(), info.bitangent.norm());
+    if not_zero(tangent_length) {
+        info.tangent = info.tangent * (sign / tangent_length);
+    }
+    if not_zero(bitangent_length) {
+        info.bitangent = info.bitangent * (sign / bitangent_length);
+    }
+    info.group_with_any = !(not_zero(tangent_length / area.abs()) &&
+                            not_zero(bitangent_length / area.abs()));
+    info
+}
+
+/// Pair every edge with the first later triangle having the same edge in
+/// the opposite direction, which isn't paired yet
+fn find_neighbours(indices: &[u32], triangles: &mut [TriangleInfo]) {
+    let mut edges: HashMap<(u32, u32), Vec<(uint, uint)>> = HashMap::new();
+    for t in range(0, triangles.len()) {
+        if triangles[t].degenerate {
+            continue
+        }
+        for corner in range(0u, 3) {
+            let edge = (indices[3 * t + corner], indices[3 * t + (corner + 1) % 3]);
+            if !edges.contains_key(&edge) {
+                let _ = edges.insert(edge, Vec::new());
+            }
+            edges.get_mut(&edge).unwrap().push((t, corner));
+        }
+    }
+
+    for t in range(0, triangles.len()) {
+        if triangles[t].degenerate {
+            continue
+        }
+        for corner in range(0u, 3) {
+            if triangles[t].neighbours[corner].is_some() {
+                continue
+            }
+            let reversed = (indices[3 * t + (corner + 1) % 3], indices[3 * t + corner]);
+            let candidates = match edges.get(&reversed) {
+                Some(candidates) => candidates,
+                None => continue,
+            };
+            for &(other, other_corner) in candidates.iter() {
+                if other > t && triangles[other].neighbours[other_corner].is_none() {
+                    triangles[t].neighbours[corner] = Some(other);
+                    triangles[other].neighbours[other_corner] = Some(t);
+                    break
+                }
+            }
+        }
+    }
+}
+
+/// Group the corners of all triangles into fans, starting from the
+/// triangles which have a tangent
+fn build_fans(indices: &[u32], triangles: &mut [TriangleInfo]) -> Vec<Fan> {
+    let mut fans = Vec::new();
+    for t in range(0, triangles.len()) {
+        if triangles[t].degenerate || triangles[t].group_with_any {
+            continue
+        }
+        for corner in range(0u, 3) {
+            if triangles[t].fans[corner].is_some() {
+                continue
+            }
+            let fan = fans.len();
+            fans.push(Fan {
+                vertex: indices[3 * t + corner],
+                preserves_orientation: triangles[t].preserves_orientation,
+                triangles: vec![t],
+            });
+            triangles[t].fans[corner] = Some(fan);
+
+            let neighbours = [triangles[t].neighbours[corner],
+                              triangles[t].neighbours[(corner + 2) % 3]];
+            for neighbour in neighbours.iter() {
+                match *neighbour {
+                    Some(neighbour) => join_fan(indices, triangles, fans.as_mut_slice(),
+                                                neighbour, fan),
+                    None => { }
+                }
+            }
+        }
+    }
+    fans
+}
+
+/// Add triangle `t` to `fan` if it has the same orientation, and continue
+/// with its neighbours around the vertex of the fan
+fn join_fan(indices: &[u32], triangles: &mut [TriangleInfo], fans: &mut [Fan],
+            t: uint, fan: uint) {
+    let corner = match corner_of(indices, t, fans[fan].vertex) {
+        Some(corner) => corner,
+        None => return,
+    };
+    if triangles[t].fans[corner].is_some() {
+        return
+    }
+
+    let preserves_orientation = fans[fan].preserves_orientation;
+    if triangles[t].group_with_any && triangles[t].fans.iter().all(|f| f.is_none()) {
+        triangles[t].preserves_orientation = preserves_orientation;
+    }
+    if triangles[t].preserves_orientation != preserves_orientation {
+        return
+    }
+    triangles[t].fans[corner] = Some(fan);
+    fans[fan].triangles.push(t);
+
+    let neighbours = [triangles[t].neighbours[corner],
+                      triangles[t].neighbours[(corner + 2) % 3]];
+    for neighbour in neighbours.iter() {
+        match *neighbour {
+            Some(neighbour) => join_fan(indices, triangles, fans, neighbour, fan),
+            None => { }
+        }
+    }
+}
+
+/// The triangles of `fan` whose tangents are averaged for the corner of
+/// triangle `t`, in ascending order
+fn similar_triangles(triangles: &[TriangleInfo], fan: &Fan, t: uint,
+                     normal: &Vector3D) -> Vec<uint> {
+    let tangent = normalized(project(&triangles[t].tangent, normal));
+    let bitangent = normalized(project(&triangles[t].bitangent, normal));
+
+    let mut members: Vec<uint> = fan.triangles.iter().map(|&other| other).filter(|&other| {
+        let info = &triangles[other];
+        other == t || triangles[t].group_with_any || info.group_with_any ||
+            (tangent.dot(&normalized(project(&info.tangent, normal))) > -1.0 &&
+             bitangent.dot(&normalized(project(&info.bitangent, normal))) > -1.0)
+    }).collect();
+    members.sort();
+    members
+}
+
+/// The average of the tangents of `members` at `vertex`, weighted by their
+/// angles, or zero if they cancel out
+fn fan_tangent(mesh: &OwnedMesh, indices: &[u32], triangles: &[TriangleInfo],
+               members: &[uint], vertex: u32, normal: &Vector3D) -> Vector3D {
+    let mut sum = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+    for &t in members.iter() {
+        if triangles[t].group_with_any {
+            continue
+        }
+        let triangle = indices.slice(3 * t, 3 * t + 3);
+        let corner = corner_of(indices, t, vertex).unwrap();
+        let angle = corner_angle(mesh, triangle, corner, normal);
+        sum = sum + normalized(project(&triangles[t].tangent, normal)) * angle;
+    }
+    normalized(sum)
+}
+
+/// The corner of triangle `t` at `vertex`
+fn corner_of(indices: &[u32], t: uint, vertex: u32) -> Option<uint> {
+    range(0u, 3).find(|&corner| indices[3 * t + corner] == vertex)
+}
+
+/// The angle of a triangle at one of its corners, between the edges
+/// projected onto the plane perpendicular to the unit vector `normal`
+fn corner_angle(mesh: &OwnedMesh, triangle: &[u32], corner: uint, normal: &Vector3D) -> f32 {
+    let p = mesh.vertices[triangle[corner] as uint];
+    let a = normalized(project(&(mesh.vertices[triangle[(corner + 1) % 3] as uint] - p), normal));
+    let b = normalized(project(&(mesh.vertices[triangle[(corner + 2) % 3] as uint] - p), normal));
+    let cos = a.dot(&b).max(-1.0).min(1.0);
+    (cos as f64).acos() as f32
+}
+
+/// Project `v` onto the plane perpendicular to the unit vector `normal`
+fn project(v: &Vector3D, normal: &Vector3D) -> Vector3D {
+    *v - *normal * normal.dot(v)
+}
+
+/// `v` scaled to unit length, unless it's zero
+fn normalized(v: Vector3D) -> Vector3D {
+    if not_zero_vector(&v) {
+        v * (1.0 / v.norm())
+    } else {
+        v
+    }
+}
+
+/// Whether `x` is distinguishable from zero, with the same threshold as
+/// MikkTSpace
+fn not_zero(x: f32) -> bool {
+    x.abs() > MIN_POS_VALUE
+}
+
+/// Whether any component of `v` is distinguishable from zero
+fn not_zero_vector(v: &Vector3D) -> bool {
+    not_zero(v.x) || not_zero(v.y) || not_zero(v.z)
+}
+
+/// Any unit vector perpendicular to `normal`
+fn any_perpendicular(normal: &Vector3D) -> Vector3D {
+    let axis = if normal.x.abs() < 0.9 {
+        Vector3D { x: 1.0, y: 0.0, z: 0.0 }
+    } else {
+        Vector3D { x: 0.0, y: 1.0, z: 0.0 }
+    };
+    let perpendicular = project(&axis, normal);
+    if not_zero_vector(&perpendicular) {
+        normalized(perpendicular)
+    } else {
+        axis
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f32::consts::FRAC_PI_2;
+
+    use mesh::OwnedMesh;
+    use test_util::vec3;
+    use types::Vector3D;
+    use super::{generate_tangents, corner_angle};
+
+    fn new_mesh(vertices: Vec<Vector3D>, normals: Vec<Vector3D>, uvs: Vec<Vector3D>,
+                indices: Vec<u32>) -> OwnedMesh {
+        OwnedMesh {
+            name: "test".to_string(),
+            vertices: vertices,
+            normals: normals,
+            tangents: Vec::new(),
+            colors: Vec::new(),
+            texture_coords: vec![uvs],
+            num_uv_components: vec![2],
+            bones: Vec::new(),
+            indices: indices,
+            material_index: 0,
+        }
+    }
+
+    fn quad(uvs: Vec<Vector3D>, indices: Vec<u32>) -> OwnedMesh {
+        let vertices = vec![vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0),
+                            vec3(1.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0)];
+        new_mesh(vertices, Vec::from_elem(4, vec3(0.0, 0.0, 1.0)), uvs, indices)
+    }
+
+    #[test]
+    fn test_planar_tangents() {
+        let uvs = vec![vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0),
+                       vec3(1.0, 1.0, 0.0), vec3(0.0, 1.0, 0.0)];
+        let mut mesh = quad(uvs, vec![0, 1, 2, 0, 2, 3]);
+        generate_tangents(&mut mesh, 0).unwrap();
+
+        assert_eq!(mesh.vertices.len(), 4);
+        for tangent in mesh.tangents.iter() {
+            assert!((tangent[0] - 1.0).abs() < 1e-6);
+            assert!(tangent[1].abs() < 1e-6 && tangent[2].abs() < 1e-6);
+            assert_eq!(tangent[3], 1.0);
+        }
+    }
+
+    #[test]
+    fn test_projected_corner_angle() {
+        let uvs = Vec::from_elem(4, vec3(0.0, 0.0, 0.0));
+        let mut mesh = quad(uvs, vec![0, 1, 3]);
+        // lifting the other corners narrows the angle to 60 degrees, but
+        // seen along the normal it stays a right angle
+        mesh.vertices[1].z = 1.0;
+        mesh.vertices[3].z = 1.0;
+        let up = vec3(0.0, 0.0, 1.0);
+        let angle = corner_angle(&mesh, mesh.indices.as_slice(), 0, &up);
+        assert!((angle - FRAC_PI_2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_mirrored_uvs_split_vertices() {
+        // the second triangle mirrors the texture horizontally
+        let uvs = vec![vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0),
+                       vec3(1.0, 1.0, 0.0), vec3(2.0, 1.0, 0.0)];
+        let mut mesh = quad(uvs, vec![0, 1, 2, 0, 2, 3]);
+        generate_tangents(&mut mesh, 0).unwrap();
+
+        // vertices 0 and 2 are shared by both triangles and get split
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.tangents[mesh.indices[0] as uint][3], 1.0);
+        assert_eq!(mesh.tangents[mesh.indices[3] as uint][3], -1.0);
+        assert!(mesh.indices[0] != mesh.indices[3]);
+        assert!(mesh.indices[2] != mesh.indices[4]);
+    }
+
+    #[test]
+    fn test_degenerate_uvs_join_neighbours() {
+        let vertices = vec![vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0),
+                            vec3(2.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0),
+                            vec3(1.0, 1.0, 0.0), vec3(2.0, 1.0, 0.0)];
+        // the texture coordinates of the last triangle lie on a line
+        let uvs = vec![vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0),
+                       vec3(2.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0),
+                       vec3(1.5, 0.5, 0.0), vec3(2.0, 1.0, 0.0)];
+        let mut mesh = new_mesh(vertices, Vec::from_elem(6, vec3(0.0, 0.0, 1.0)), uvs,
+                                vec![0, 1, 4, 0, 4, 3, 1, 2, 5, 1, 5, 4]);
+        generate_tangents(&mut mesh, 0).unwrap();
+
+        // it takes the orientation of its neighbours instead of splitting
+        // their vertices
+        assert_eq!(mesh.vertices.len(), 6);
+        for tangent in mesh.tangents.iter() {
+            assert_eq!(tangent[3], 1.0);
+        }
+        // and doesn't contribute to their tangents
+        let tangent = mesh.tangents[5];
+        assert!((tangent[0] - 1.0).abs() < 1e-6);
+        assert!(tangent[1].abs() < 1e-6 && tangent[2].abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_reference_tangents() {
+        let vertices = vec![vec3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.2),
+                            vec3(2.0, 0.0, 0.0), vec3(0.0, 1.0, 0.3),
+                            vec3(1.0, 1.0, 0.0), vec3(2.0, 1.0, 0.4)];
+        let normals = [(0.0, 0.0), (0.1, 0.0), (0.0, 0.2),
+                       (-0.2, 0.0), (0.0, 0.0), (0.1, -0.1)].iter()
+            .map(|&(x, y)| {
+                let norm = (x * x + y * y + 1.0f32).sqrt();
+                vec3(x / norm, y / norm, 1.0 / norm)
+            })
+            .collect();
+        // the last triangle is mirrored
+        let uvs = vec![vec3(0.0, 0.0, 0.0), vec3(0.5, 0.1, 0.0),
+                       vec3(1.0, 0.0, 0.0), vec3(0.1, 0.6, 0.0),
+                       vec3(0.6, 0.5, 0.0), vec3(0.2, 0.9, 0.0)];
+        let mut mesh = new_mesh(vertices, normals, uvs,
+                                vec![0, 1, 4, 0, 4, 3, 1, 2, 5, 1, 5, 4]);
+        generate_tangents(&mut mesh, 0).unwrap();
+
+        // the tangent of every corner as computed by the MikkTSpace
+        // reference implementation
+        let expected = [
+            [0.9992035, -0.039904386, 0.0, 1.0],
+            [0.9635594, -0.24953718, -0.09635592, 1.0],
+            [0.9992035, -0.039904386, 0.0, 1.0],
+            [0.9992035, -0.039904386, 0.0, 1.0],
+            [0.9992035, -0.039904386, 0.0, 1.0],
+            [0.96525353, 0.17611645, 0.19305077, 1.0],
+            [0.98907614, 0.10929615, -0.09890763, 1.0],
+            [0.990415, 0.13544136, -0.027088266, 1.0],
+            [0.9907186, 0.102931805, -0.088778675, 1.0],
+            [-0.6797091, 0.7303257, 0.06797086, -1.0],
+            [-0.7001401, 0.70013994, 0.140028, -1.0],
+            [-0.7071069, 0.70710677, 0.0, -1.0],
+        ];
+        for (&vertex, expected) in mesh.indices.iter().zip(expected.iter()) {
+            let tangent = mesh.tangents[vertex as uint];
+            for i in range(0u, 4) {
+                assert!((tangent[i] - expected[i]).abs() < 1e-5);
+            }
+        }
+        // vertex 1 is split between the two fans separated by the mirrored
+        // triangle and the mirrored triangle itself, vertices 4 and 5
+        // between the orientations
+        assert_eq!(mesh.vertices.len(), 10);
+    }
+}
+
+// vim: et tw=78 sw=4:
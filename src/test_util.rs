@@ -0,0 +1,47 @@
+//! Fixtures shared by the tests of several modules
+
+use mesh::OwnedMesh;
+use types::Vector3D;
+
+/// Shorthand for creating a `Vector3D`
+pub fn vec3(x: f32, y: f32, z: f32) -> Vector3D {
+    Vector3D { x: x, y: y, z: z }
+}
+
+/// A flat grid of `n` by `n` quads facing +z, as vertices and a triangle
+/// list
+pub fn grid(n: u32) -> (Vec<Vector3D>, Vec<u32>) {
+    let mut vertices = Vec::new();
+    for y in range(0, n + 1) {
+        for x in range(0, n + 1) {
+            vertices.push(vec3(x as f32, y as f32, 0.0));
+        }
+    }
+    let mut indices = Vec::new();
+    for y in range(0, n) {
+        for x in range(0, n) {
+            let i = y * (n + 1) + x;
+            indices.push_all(&[i, i + 1, i + n + 2, i, i + n + 2, i + n + 1]);
+        }
+    }
+    (vertices, indices)
+}
+
+/// The grid of `grid` as an `OwnedMesh` with normals
+pub fn grid_mesh(n: u32) -> OwnedMesh {
+    let (vertices, indices) = grid(n);
+    OwnedMesh {
+        name: "grid".to_string(),
+        normals: Vec::from_elem(vertices.len(), vec3(0.0, 0.0, 1.0)),
+        vertices: vertices,
+        tangents: Vec::new(),
+        colors: Vec::new(),
+        texture_coords: Vec::new(),
+        num_uv_components: Vec::new(),
+        bones: Vec::new(),
+        indices: indices,
+        material_index: 0,
+    }
+}
+
+// vim: et tw=78 sw=4:
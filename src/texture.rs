@@ -6,6 +6,12 @@
 //! such as PNG or TGA.
 
 use libc::{c_uchar, c_char, c_uint};
+use std::io::{IoError, IoResult, InvalidInput};
+
+#[cfg(feature = "image")]
+use image;
+#[cfg(feature = "image")]
+use image::GenericImage;
 
 use util::{ptr_to_slice};
 
@@ -22,16 +28,146 @@ use util::{ptr_to_slice};
 
 
 /// Helper structure to represent a texel in a ARGB8888 format
+///
+/// Note that the components are stored in BGRA order in memory.
+#[deriving(Clone, PartialEq, Show)]
 #[repr(C, packed)]
 pub struct Texel {
     /// blue
-    b: c_uchar,
+    pub b: c_uchar,
     /// green
-    g: c_uchar,
+    pub g: c_uchar,
     /// red
-    r: c_uchar,
+    pub r: c_uchar,
     /// alpha
-    a: c_uchar,
+    pub a: c_uchar,
+}
+
+impl Texel {
+    /// Get the texel as an `[r, g, b, a]` array
+    pub fn to_rgba(&self) -> [u8, ..4] {
+        [self.r, self.g, self.b, self.a]
+    }
+}
+
+/// The file formats compressed embedded textures are commonly stored in
+#[deriving(Clone, PartialEq, Eq, Show)]
+pub enum CompressedFormat {
+    /// Portable Network Graphics
+    Png,
+    /// JPEG
+    Jpeg,
+    /// Truevision TGA
+    Tga,
+    /// DirectDraw Surface
+    Dds,
+    /// Windows bitmap
+    Bmp,
+}
+
+impl CompressedFormat {
+    /// Get the format from a `Texture::format_hint` such as "png" or "jpg"
+    ///
+    /// The hint is matched case-insensitively, some loaders don't keep to
+    /// the documented lower-case hints.
+    pub fn from_hint(hint: &str) -> Option<CompressedFormat> {
+        let hint: String = hint.chars().map(|c| c.to_lowercase()).collect();
+        match hint.as_slice() {
+            "png" => Some(CompressedFormat::Png),
+            "jpg" | "jpeg" => Some(CompressedFormat::Jpeg),
+            "tga" => Some(CompressedFormat::Tga),
+            "dds" => Some(CompressedFormat::Dds),
+            "bmp" => Some(CompressedFormat::Bmp),
+            _ => None,
+        }
+    }
+
+    /// Guess the format of an encoded image from its magic bytes
+    ///
+    /// TGA files have no magic number, they are only recognised if they
+    /// carry the optional TGA 2.0 footer.
+    pub fn from_magic(data: &[u8]) -> Option<CompressedFormat> {
+        const TGA_FOOTER: &'static [u8] = b"TRUEVISION-XFILE.\0";
+
+        if data.starts_with(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]) {
+            Some(CompressedFormat::Png)
+        } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some(CompressedFormat::Jpeg)
+        } else if data.starts_with(b"DDS ") {
+            Some(CompressedFormat::Dds)
+        } else if data.starts_with(b"BM") {
+            Some(CompressedFormat::Bmp)
+        } else if data.ends_with(TGA_FOOTER) {
+            Some(CompressedFormat::Tga)
+        } else {
+            None
+        }
+    }
+
+    /// The usual file extension of the format, without a leading dot
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            CompressedFormat::Png => "png",
+            CompressedFormat::Jpeg => "jpg",
+            CompressedFormat::Tga => "tga",
+            CompressedFormat::Dds => "dds",
+            CompressedFormat::Bmp => "bmp",
+        }
+    }
+}
+
+/// An embedded texture decoded into 8 bit RGBA texels
+#[deriving(Clone, Show)]
+pub struct RgbaImage {
+    /// The width of the image in texels
+    pub width: u32,
+    /// The height of the image in texels
+    pub height: u32,
+    /// The texels of the image in row-major order, four bytes per texel
+    pub data: Vec<u8>,
+}
+
+impl RgbaImage {
+    /// Write the image to the given writer as an uncompressed PNG file
+    ///
+    /// Returns an `InvalidInput` error if the image is empty, since PNG
+    /// files need at least one texel, or if the size of `data` doesn't
+    /// match the dimensions.
+    pub fn write_png(&self, w: &mut Writer) -> IoResult<()> {
+        if self.width == 0 || self.height == 0 {
+            return Err(IoError {
+                kind: InvalidInput,
+                desc: "PNG images can't be empty",
+                detail: None,
+            })
+        }
+        if self.data.len() != self.width as uint * self.height as uint * 4 {
+            return Err(IoError {
+                kind: InvalidInput,
+                desc: "the image data doesn't match its dimensions",
+                detail: None,
+            })
+        }
+
+        let mut header = Vec::with_capacity(13);
+        header.push_all(&be_u32(self.width));
+        header.push_all(&be_u32(self.height));
+        // 8 bits per channel, RGBA, default compression, filter and interlace
+        header.push_all(&[8, 6, 0, 0, 0]);
+
+        // every scanline starts with its filter type, we never filter
+        let row_len = self.width as uint * 4;
+        let mut raw = Vec::with_capacity((row_len + 1) * self.height as uint);
+        for row in self.data.chunks(row_len) {
+            raw.push(0);
+            raw.push_all(row);
+        }
+
+        try!(w.write(&[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a]));
+        try!(write_png_chunk(w, b"IHDR", header.as_slice()));
+        try!(write_png_chunk(w, b"IDAT", zlib_stored(raw.as_slice()).as_slice()));
+        write_png_chunk(w, b"IEND", &[])
+    }
 }
 
 ///  Helper structure to describe an embedded texture
@@ -106,6 +242,63 @@ pub enum TextureData<'a> {
 }
 
 impl Texture {
+    /// Get the format hint of a compressed texture.
+    ///
+    /// Returns `None` if the texture is uncompressed or the loader gave no
+    /// hint about the file format used.
+    pub fn get_format_hint(&self) -> Option<String> {
+        if self.height != 0 {
+            return None
+        }
+        let hint: String = self.format_hint.iter()
+                                           .take_while(|&&c| c != 0)
+                                           .map(|&c| c as u8 as char)
+                                           .collect();
+        if hint.is_empty() { None } else { Some(hint) }
+    }
+
+    /// Get the file format of a compressed texture.
+    ///
+    /// The format hint is used if the loader provided a known one, otherwise
+    /// the format is guessed from the magic bytes of the texture data.
+    pub fn get_compressed_format(&self) -> Option<CompressedFormat> {
+        match self.get_texture_data() {
+            TextureData::Decoded { .. } => None,
+            TextureData::Encoded { data, .. } => {
+                self.get_format_hint()
+                    .and_then(|hint| CompressedFormat::from_hint(hint.as_slice()))
+                    .or_else(|| CompressedFormat::from_magic(data))
+            }
+        }
+    }
+
+    /// Decode the texture into 8 bit RGBA texels.
+    ///
+    /// Uncompressed textures are always supported, compressed textures can
+    /// only be decoded if the crate is built with the `image` feature.
+    pub fn to_rgba_image(&self) -> Result<RgbaImage, String> {
+        match self.get_texture_data() {
+            TextureData::Decoded { width, height, data } => {
+                let mut pixels = Vec::with_capacity(data.len() * 4);
+                for texel in data.iter() {
+                    pixels.push_all(&texel.to_rgba());
+                }
+                Ok(RgbaImage {
+                    width: width,
+                    height: height,
+                    data: pixels,
+                })
+            }
+            TextureData::Encoded { data, .. } => {
+                match self.get_compressed_format() {
+                    Some(format) => decode_compressed(format, data),
+                    None => Err(format!("unknown texture format, hint: {}",
+                                        self.get_format_hint())),
+                }
+            }
+        }
+    }
+
     /// Get the embeded texture data
     pub fn get_texture_data(&self) -> TextureData {
         if self.height == 0 {
@@ -128,3 +321,242 @@ impl Texture {
         }
     }
 }
+
+#[cfg(test)]
+impl Texture {
+    /// A compressed texture over `data` with the given format hint
+    pub fn from_encoded(data: &mut [u8], hint: &[u8]) -> Texture {
+        let mut format_hint = [0, ..4];
+        for (dst, &src) in format_hint.iter_mut().zip(hint.iter()) {
+            *dst = src as c_char;
+        }
+        Texture {
+            width: data.len() as c_uint,
+            height: 0,
+            format_hint: format_hint,
+            pc_data: data.as_mut_ptr() as *mut Texel,
+        }
+    }
+
+    /// An uncompressed texture over `texels`, `height` must not be zero
+    pub fn from_texels(width: u32, height: u32, texels: &mut [Texel]) -> Texture {
+        assert!(height != 0 && texels.len() == width as uint * height as uint);
+        Texture {
+            width: width,
+            height: height,
+            format_hint: [0, ..4],
+            pc_data: texels.as_mut_ptr(),
+        }
+    }
+}
+
+fn be_u32(n: u32) -> [u8, ..4] {
+    [(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]
+}
+
+/// Continue the CRC-32 used by PNG over `data`, starting from `0xffffffff`
+/// and inverting the result when done
+fn update_crc32(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data.iter() {
+        crc ^= byte as u32;
+        for _ in range(0u, 8) {
+            crc = if crc & 1 != 0 { 0xedb88320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+/// The Adler-32 checksum used by zlib
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data.iter() {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn write_png_chunk(w: &mut Writer, kind: &[u8], data: &[u8]) -> IoResult<()> {
+    // the crc covers the chunk type and the chunk data
+    let crc = update_crc32(update_crc32(0xffffffff, kind), data) ^ 0xffffffff;
+
+    try!(w.write(&be_u32(data.len() as u32)));
+    try!(w.write(kind));
+    try!(w.write(data));
+    w.write(&be_u32(crc))
+}
+
+/// Wrap `data` in a zlib stream made of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    const MAX_BLOCK: uint = 0xffff;
+
+    let mut out = Vec::with_capacity(data.len() + data.len() / MAX_BLOCK * 5 + 11);
+    out.push_all(&[0x78, 0x01]);
+
+    let num_blocks = (data.len() + MAX_BLOCK - 1) / MAX_BLOCK;
+    if num_blocks == 0 {
+        out.push_all(&[0x01, 0x00, 0x00, 0xff, 0xff]);
+    }
+    for (i, block) in data.chunks(MAX_BLOCK).enumerate() {
+        let len = block.len() as u16;
+        out.push(if i + 1 == num_blocks { 1 } else { 0 });
+        out.push_all(&[len as u8, (len >> 8) as u8,
+                       !len as u8, (!len >> 8) as u8]);
+        out.push_all(block);
+    }
+
+    out.push_all(&be_u32(adler32(data)));
+    out
+}
+
+#[cfg(feature = "image")]
+fn decode_compressed(format: CompressedFormat,
+                     data: &[u8]) -> Result<RgbaImage, String> {
+    let image_format = match format {
+        CompressedFormat::Png => image::PNG,
+        CompressedFormat::Jpeg => image::JPEG,
+        CompressedFormat::Tga => image::TGA,
+        CompressedFormat::Dds => image::DDS,
+        CompressedFormat::Bmp => image::BMP,
+    };
+    let img = match image::load_from_memory(data, image_format) {
+        Ok(img) => img,
+        Err(e) => return Err(format!("failed to decode {} texture: {}",
+                                     format.extension(), e)),
+    };
+    let (width, height) = img.dimensions();
+    let img = image::DynamicImage::ImageRgba8(img.to_rgba());
+    Ok(RgbaImage {
+        width: width,
+        height: height,
+        data: img.raw_pixels(),
+    })
+}
+
+#[cfg(not(feature = "image"))]
+fn decode_compressed(format: CompressedFormat,
+                     _data: &[u8]) -> Result<RgbaImage, String> {
+    Err(format!("decoding {} textures requires the `image` feature",
+                format.extension()))
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::MemWriter;
+    use super::{Texel, Texture, CompressedFormat, RgbaImage};
+    use super::{update_crc32, adler32, zlib_stored};
+
+    #[test]
+    fn test_texel_to_rgba() {
+        let texel = Texel { b: 1, g: 2, r: 3, a: 4 };
+        assert_eq!(texel.to_rgba(), [3, 2, 1, 4]);
+    }
+
+    #[test]
+    fn test_compressed_format() {
+        assert_eq!(CompressedFormat::from_hint("jpg"),
+                   Some(CompressedFormat::Jpeg));
+        assert_eq!(CompressedFormat::from_hint("PNG"),
+                   Some(CompressedFormat::Png));
+        assert_eq!(CompressedFormat::from_hint(""), None);
+
+        let png = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0, 0];
+        assert_eq!(CompressedFormat::from_magic(&png),
+                   Some(CompressedFormat::Png));
+        assert_eq!(CompressedFormat::from_magic(b"DDS \x7c"),
+                   Some(CompressedFormat::Dds));
+        assert_eq!(CompressedFormat::from_magic(b"garbage"), None);
+    }
+
+    #[test]
+    fn test_uncompressed_to_rgba() {
+        let mut texels = vec![Texel { b: 0, g: 0, r: 255, a: 255 },
+                              Texel { b: 255, g: 0, r: 0, a: 128 }];
+        let texture = Texture {
+            width: 2,
+            height: 1,
+            format_hint: [0, ..4],
+            pc_data: texels.as_mut_ptr(),
+        };
+        let img = texture.to_rgba_image().unwrap();
+        assert_eq!(img.width, 2);
+        assert_eq!(img.height, 1);
+        assert_eq!(img.data, vec![255, 0, 0, 255, 0, 0, 255, 128]);
+    }
+
+    #[test]
+    fn test_get_compressed_format() {
+        let mut png = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0, 0];
+        let format = |hint: &[u8]| {
+            Texture::from_encoded(png.as_mut_slice(), hint).get_compressed_format()
+        };
+        assert_eq!(format(b"PNG"), Some(CompressedFormat::Png));
+        // unknown hints fall back to the magic bytes
+        assert_eq!(format(b"xyz"), Some(CompressedFormat::Png));
+        assert_eq!(format(b""), Some(CompressedFormat::Png));
+
+        let mut garbage = b"garbage".to_vec();
+        let texture = Texture::from_encoded(garbage.as_mut_slice(), b"xyz");
+        assert_eq!(texture.get_compressed_format(), None);
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn test_compressed_to_rgba() {
+        // a single red texel, as written by zlib at compression level 0
+        let mut png = vec![
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a,
+            0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01,
+            0x08, 0x06, 0x00, 0x00, 0x00, 0x1f, 0x15, 0xc4, 0x89,
+            0x00, 0x00, 0x00, 0x10, 0x49, 0x44, 0x41, 0x54,
+            0x78, 0x01, 0x01, 0x05, 0x00, 0xfa, 0xff,
+            0x00, 0xff, 0x00, 0x00, 0xff, 0x05, 0x00, 0x01, 0xff,
+            0xfa, 0x5c, 0x88, 0xd1,
+            0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82];
+
+        let texture = Texture::from_encoded(png.as_mut_slice(), b"PNG");
+        let decoded = texture.to_rgba_image().unwrap();
+        assert_eq!(decoded.width, 1);
+        assert_eq!(decoded.height, 1);
+        assert_eq!(decoded.data, vec![255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_checksums() {
+        assert_eq!(update_crc32(0xffffffff, b"123456789") ^ 0xffffffff, 0xcbf43926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        assert_eq!(adler32(&[]), 1);
+    }
+
+    #[test]
+    fn test_zlib_stored() {
+        assert_eq!(zlib_stored(&[0, 255, 0, 0, 255]),
+                   vec![0x78, 0x01, 0x01, 0x05, 0x00, 0xfa, 0xff,
+                        0x00, 0xff, 0x00, 0x00, 0xff, 0x05, 0x00, 0x01, 0xff]);
+    }
+
+    #[test]
+    fn test_write_png() {
+        // a single red texel, as written by zlib at compression level 0
+        let golden = [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a,
+            0x00, 0x00, 0x00, 0x0d, 0x49, 0x48, 0x44, 0x52,
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01,
+            0x08, 0x06, 0x00, 0x00, 0x00, 0x1f, 0x15, 0xc4, 0x89,
+            0x00, 0x00, 0x00, 0x10, 0x49, 0x44, 0x41, 0x54,
+            0x78, 0x01, 0x01, 0x05, 0x00, 0xfa, 0xff,
+            0x00, 0xff, 0x00, 0x00, 0xff, 0x05, 0x00, 0x01, 0xff,
+            0xfa, 0x5c, 0x88, 0xd1,
+            0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82];
+        let img = RgbaImage { width: 1, height: 1, data: vec![255, 0, 0, 255] };
+        let mut w = MemWriter::new();
+        img.write_png(&mut w).unwrap();
+        assert_eq!(w.get_ref(), golden.as_slice());
+
+        let empty = RgbaImage { width: 0, height: 4, data: Vec::new() };
+        assert!(empty.write_png(&mut MemWriter::new()).is_err());
+    }
+}
+
+// vim: et tw=78 sw=4:
@@ -0,0 +1,438 @@
+//! Edge adjacency of meshes, for diagnosing meshes which aren't watertight.
+//!
+//! Importers usually split vertices along normal and texture seams, so the
+//! faces of a mesh rarely share vertex indices across seams. A `Topology`
+//! first welds vertices with the same position, within a tolerance, and then
+//! collects the edges of the faces between the welded positions. From the
+//! edges it reports:
+//!
+//!  * boundary loops, made of edges used by only one face,
+//!  * non-manifold edges, used by more than two faces,
+//!  * edges whose two faces have inconsistent winding,
+//!  * the connected components of faces sharing edges.
+//!
+//! Vertices are reported by the index of the first mesh vertex at each
+//! welded position.
+
+use std::collections::HashMap;
+
+use mesh::Mesh;
+use types::Vector3D;
+use util::weld_exact;
+
+/// An undirected edge and the faces using it
+struct Edge {
+    /// The welded positions of the edge, the smaller one first
+    positions: (u32, u32),
+    /// The faces using the edge, and whether they traverse it from the
+    /// first to the second position
+    faces: Vec<(uint, bool)>,
+}
+
+/// The edge adjacency of the faces of a mesh
+pub struct Topology {
+    /// The welded position of each mesh vertex
+    position_ids: Vec<u32>,
+    /// The first mesh vertex of each welded position
+    representatives: Vec<u32>,
+    edges: Vec<Edge>,
+    num_faces: uint,
+}
+
+impl Topology {
+    /// Build the adjacency of the faces of `mesh`, welding vertices closer
+    /// than `tolerance`.
+    ///
+    /// Points and lines have no edges and are ignored. A tolerance of 0
+    /// only welds vertices with identical positions.
+    pub fn new(mesh: &Mesh, tolerance: f32) -> Topology {
+        let faces: Vec<&[u32]> = mesh.get_faces().iter()
+            .map(|face| face.get_indices())
+            .collect();
+        Topology::from_faces(mesh.get_vertices(), faces.as_slice(), tolerance)
+    }
+
+    /// Build the adjacency of `faces`, each a list of indices into
+    /// `vertices`, see `new`.
+    ///
+    /// Faces with indices beyond `vertices` are ignored like points and
+    /// lines.
+    pub fn from_faces(vertices: &[Vector3D], faces: &[&[u32]], tolerance: f32) -> Topology {
+        let position_ids = weld(vertices, tolerance);
+        let mut representatives = Vec::new();
+        for (i, &id) in position_ids.iter().enumerate() {
+            if id as uint == representatives.len() {
+                representatives.push(i as u32);
+            }
+        }
+
+        let mut edges: Vec<Edge> = Vec::new();
+        let mut edge_ids: HashMap<(u32, u32), uint> = HashMap::new();
+        for (face_index, face) in faces.iter().enumerate() {
+            if face.len() < 3 || face.iter().any(|&v| v as uint >= vertices.len()) {
+                continue
+            }
+            for i in range(0, face.len()) {
+                let a = position_ids[face[i] as uint];
+                let b = position_ids[face[(i + 1) % face.len()] as uint];
+                if a == b {
+                    // collapsed by welding
+                    continue
+                }
+                let key = if a < b { (a, b) } else { (b, a) };
+                let id = match edge_ids.get(&key) {
+                    Some(id) => *id,
+                    None => {
+                        let _ = edge_ids.insert(key, edges.len());
+                        edges.push(Edge { positions: key, faces: Vec::new() });
+                        edges.len() - 1
+                    }
+                };
+                edges[id].faces.push((face_index, a < b));
+            }
+        }
+
+        Topology {
+            position_ids: position_ids,
+            representatives: representatives,
+            edges: edges,
+            num_faces: faces.len(),
+        }
+    }
+
+    /// The number of distinct positions after welding
+    pub fn num_positions(&self) -> uint {
+        self.representatives.len()
+    }
+
+    /// The number of distinct edges
+    pub fn num_edges(&self) -> uint {
+        self.edges.len()
+    }
+
+    /// The first mesh vertex with the same position as each mesh vertex
+    pub fn get_welded_vertices(&self) -> Vec<u32> {
+        self.position_ids.iter().map(|&id| self.representatives[id as uint]).collect()
+    }
+
+    /// The loops of edges used by only one face, as lists of vertices.
+    ///
+    /// The loops follow the winding of their faces. Where boundaries meet at
+    /// a vertex they can't be told apart, so loops through such vertices
+    /// may be split or joined differently than expected.
+    pub fn boundary_loops(&self) -> Vec<Vec<u32>> {
+        // the boundary edges leaving each position, as traversed by their
+        // face
+        let mut outgoing: HashMap<u32, Vec<(u32, uint)>> = HashMap::new();
+        let mut num_boundary = 0u;
+        for edge in self.edges.iter() {
+            if edge.faces.len() != 1 {
+                continue
+            }
+            let (a, b) = edge.positions;
+            let (_, forward) = edge.faces[0];
+            let (from, to) = if forward { (a, b) } else { (b, a) };
+            if !outgoing.contains_key(&from) {
+                let _ = outgoing.insert(from, Vec::new());
+            }
+            outgoing.get_mut(&from).unwrap().push((to, num_boundary));
+            num_boundary += 1;
+        }
+
+        let mut used = Vec::from_elem(num_boundary, false);
+        let mut loops = Vec::new();
+        for edge in self.edges.iter() {
+            if edge.faces.len() != 1 {
+                continue
+            }
+            let (a, b) = edge.positions;
+            let (_, forward) = edge.faces[0];
+            let start = if forward { a } else { b };
+
+            let mut boundary = Vec::new();
+            let mut current = start;
+            loop {
+                let next = match outgoing.get(&current) {
+                    Some(edges) => edges.iter().find(|&&(_, id)| !used[id]).map(|&e| e),
+                    None => None,
+                };
+                let (to, id) = match next {
+                    Some(e) => e,
+                    None => break,
+                };
+                used[id] = true;
+                boundary.push(self.representatives[current as uint]);
+                current = to;
+                if current == start {
+                    break
+                }
+            }
+            if !boundary.is_empty() {
+                loops.push(boundary);
+            }
+        }
+        loops
+    }
+
+    /// The edges used by more than two faces, as pairs of vertices
+    pub fn non_manifold_edges(&self) -> Vec<(u32, u32)> {
+        self.edges.iter()
+            .filter(|edge| edge.faces.len() > 2)
+            .map(|edge| self.edge_vertices(edge))
+            .collect()
+    }
+
+    /// The edges whose two faces traverse them in the same direction, as
+    /// pairs of vertices.
+    ///
+    /// One of the two faces has its winding flipped relative to the other.
+    pub fn inconsistent_edges(&self) -> Vec<(u32, u32)> {
+        self.edges.iter()
+            .filter(|edge| {
+                if edge.faces.len() != 2 {
+                    return false
+                }
+                let ((_, first), (_, second)) = (edge.faces[0], edge.faces[1]);
+                first == second
+            })
+            .map(|edge| self.edge_vertices(edge))
+            .collect()
+    }
+
+    /// The groups of faces connected by shared edges, as lists of face
+    /// indices.
+    ///
+    /// Faces without edges, i.e. points, lines and faces ignored by
+    /// `from_faces`, don't belong to any component.
+    pub fn connected_components(&self) -> Vec<Vec<uint>> {
+        let mut parents: Vec<uint> = range(0, self.num_faces).collect();
+        let mut has_edges = Vec::from_elem(self.num_faces, false);
+        for edge in self.edges.iter() {
+            let (first, _) = edge.faces[0];
+            for &(face, _) in edge.faces.iter() {
+                has_edges[face] = true;
+                let a = find_root(&mut parents, first);
+                let b = find_root(&mut parents, face);
+                if a != b {
+                    parents[b] = a;
+                }
+            }
+        }
+
+        let mut component_ids: HashMap<uint, uint> = HashMap::new();
+        let mut components: Vec<Vec<uint>> = Vec::new();
+        for face in range(0, self.num_faces) {
+            if !has_edges[face] {
+                continue
+            }
+            let root = find_root(&mut parents, face);
+            let id = match component_ids.get(&root) {
+                Some(id) => *id,
+                None => {
+                    let _ = component_ids.insert(root, components.len());
+                    components.push(Vec::new());
+                    components.len() - 1
+                }
+            };
+            components[id].push(face);
+        }
+        components
+    }
+
+    /// Whether the faces form closed, consistently wound surfaces: every
+    /// edge is used by exactly two faces traversing it in opposite
+    /// directions.
+    ///
+    /// Meshes without any edges aren't watertight.
+    pub fn is_watertight(&self) -> bool {
+        !self.edges.is_empty() && self.edges.iter().all(|edge| {
+            if edge.faces.len() != 2 {
+                return false
+            }
+            let ((_, first), (_, second)) = (edge.faces[0], edge.faces[1]);
+            first != second
+        })
+    }
+
+    fn edge_vertices(&self, edge: &Edge) -> (u32, u32) {
+        let (a, b) = edge.positions;
+        (self.representatives[a as uint], self.representatives[b as uint])
+    }
+}
+
+/// The root of `node` in the union-find forest `parents`, compressing the
+/// path on the way
+fn find_root(parents: &mut Vec<uint>, node: uint) -> uint {
+    let mut root = node;
+    while parents[root] != root {
+        root = parents[root];
+    }
+    let mut current = node;
+    while parents[current] != root {
+        let next = parents[current];
+        parents[current] = root;
+        current = next;
+    }
+    root
+}
+
+/// Number the distinct positions of `vertices`, treating positions closer
+/// than `tolerance` as the same
+fn weld(vertices: &[Vector3D], tolerance: f32) -> Vec<u32> {
+    if !(tolerance > 0.0) {
+        let (ids, _) = weld_exact(vertices);
+        return ids
+    }
+
+    let mut ids = Vec::with_capacity(vertices.len());
+    let mut num_positions = 0u32;
+
+    // the positions in each cell of a grid with cells the size of the
+    // tolerance, so close positions are in the same or neighbouring cells
+    let cell_of = |v: &Vector3D| {
+        ((v.x / tolerance).floor() as i64,
+         (v.y / tolerance).floor() as i64,
+         (v.z / tolerance).floor() as i64)
+    };
+    let mut cells: HashMap<(i64, i64, i64), Vec<(Vector3D, u32)>> = HashMap::new();
+    let tolerance_sq = tolerance * tolerance;
+    for v in vertices.iter() {
+        let (x, y, z) = cell_of(v);
+        let mut found = None;
+        for dx in range(-1i64, 2) {
+            for dy in range(-1i64, 2) {
+                for dz in range(-1i64, 2) {
+                    match cells.get(&(x + dx, y + dy, z + dz)) {
+                        Some(positions) => {
+                            for &(p, id) in positions.iter() {
+                                let d = p - *v;
+                                if found.is_none() && d.dot(&d) <= tolerance_sq {
+                                    found = Some(id);
+                                }
+                            }
+                        }
+                        None => { }
+                    }
+                }
+            }
+        }
+
+        let id = match found {
+            Some(id) => id,
+            None => {
+                if !cells.contains_key(&(x, y, z)) {
+                    let _ = cells.insert((x, y, z), Vec::new());
+                }
+                cells.get_mut(&(x, y, z)).unwrap().push((*v, num_positions));
+                num_positions += 1;
+                num_positions - 1
+            }
+        };
+        ids.push(id);
+    }
+    ids
+}
+
+#[cfg(test)]
+mod test {
+    use test_util::vec3;
+    use types::Vector3D;
+    use super::Topology;
+
+    /// The corners of a unit cube, with every corner duplicated at a tiny
+    /// offset, as importers do at seams
+    fn cube_vertices() -> Vec<Vector3D> {
+        let mut vertices = Vec::new();
+        for &offset in [0.0f32, 1e-5].iter() {
+            for i in range(0u, 8) {
+                vertices.push(vec3((i & 1) as f32 + offset,
+                                   ((i >> 1) & 1) as f32,
+                                   ((i >> 2) & 1) as f32));
+            }
+        }
+        vertices
+    }
+
+    /// The faces of the cube wound counter-clockwise seen from outside,
+    /// alternating between the two copies of the corners
+    fn cube_faces() -> Vec<Vec<u32>> {
+        let faces = [[0u32, 2, 3, 1], [4, 5, 7, 6], [0, 1, 5, 4],
+                     [2, 6, 7, 3], [0, 4, 6, 2], [1, 3, 7, 5]];
+        let mut result = Vec::new();
+        for (i, face) in faces.iter().enumerate() {
+            let copy = if i % 2 == 0 { 0 } else { 8 };
+            result.push(face.iter().map(|&v| v + copy).collect());
+        }
+        result
+    }
+
+    fn topology(faces: &Vec<Vec<u32>>, tolerance: f32) -> Topology {
+        let vertices = cube_vertices();
+        let slices: Vec<&[u32]> = faces.iter().map(|f| f.as_slice()).collect();
+        Topology::from_faces(vertices.as_slice(), slices.as_slice(), tolerance)
+    }
+
+    #[test]
+    fn test_closed_cube() {
+        let faces = cube_faces();
+        let welded = topology(&faces, 1e-4);
+        assert_eq!(welded.num_positions(), 8);
+        assert_eq!(welded.num_edges(), 12);
+        assert!(welded.is_watertight());
+        assert!(welded.boundary_loops().is_empty());
+        assert_eq!(welded.connected_components().len(), 1);
+
+        // without welding the faces don't share edges across the seams
+        let split = topology(&faces, 0.0);
+        assert!(!split.is_watertight());
+        assert!(split.connected_components().len() > 1);
+    }
+
+    #[test]
+    fn test_open_and_flipped_faces() {
+        let mut faces = cube_faces();
+        let _ = faces.pop();
+        let open = topology(&faces, 1e-4);
+        assert!(!open.is_watertight());
+        let loops = open.boundary_loops();
+        assert_eq!(loops.len(), 1);
+        assert_eq!(loops[0].len(), 4);
+
+        let mut faces = cube_faces();
+        faces[0].reverse();
+        let flipped = topology(&faces, 1e-4);
+        assert_eq!(flipped.inconsistent_edges().len(), 4);
+        assert!(flipped.non_manifold_edges().is_empty());
+    }
+
+    #[test]
+    fn test_non_manifold_edge() {
+        // three triangles hinged on the edge from corner 0 to corner 1
+        let faces = vec![vec![0, 1, 2], vec![1, 0, 4], vec![0, 1, 6]];
+        let fins = topology(&faces, 1e-4);
+        assert_eq!(fins.non_manifold_edges(), vec![(0u32, 1u32)]);
+        assert!(fins.inconsistent_edges().is_empty());
+        assert!(!fins.is_watertight());
+        assert_eq!(fins.connected_components(), vec![vec![0u, 1, 2]]);
+    }
+
+    #[test]
+    fn test_degenerate_input() {
+        // nothing to be closed
+        let empty = topology(&Vec::new(), 1e-4);
+        assert_eq!(empty.num_edges(), 0);
+        assert!(!empty.is_watertight());
+        let lines = topology(&vec![vec![0, 1], vec![1]], 1e-4);
+        assert!(!lines.is_watertight());
+
+        // a face using a vertex which doesn't exist is ignored
+        let mut faces = cube_faces();
+        faces.push(vec![0, 1, 100]);
+        let welded = topology(&faces, 1e-4);
+        assert_eq!(welded.num_edges(), 12);
+        assert!(welded.is_watertight());
+        assert_eq!(welded.connected_components(), vec![vec![0u, 1, 2, 3, 4, 5]]);
+    }
+}
+
+// vim: et tw=78 sw=4:
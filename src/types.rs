@@ -1,5 +1,6 @@
 //! Defines basic data types and primitives used by assimp.
 
+use std::cmp;
 use std::str;
 use std::fmt;
 use libc::{c_float, size_t, c_uchar, c_uint};
@@ -65,6 +66,8 @@ pub struct Plane {
 }
 
 /// Represents a ray.
+///
+/// See `bvh::Bvh::raycast` for casting rays against a scene.
 #[deriving(Clone, PartialEq, Show)]
 #[repr(C, packed)]
 pub struct Ray {
@@ -168,6 +171,19 @@ impl AiString {
         }
     }
 
+    /// Create a new string from a `str`.
+    ///
+    /// Strings longer than `MAXLEN - 1` bytes are truncated.
+    pub fn from_str(s: &str) -> AiString {
+        let mut string = AiString::new();
+        let len = cmp::min(s.len(), MAXLEN - 1);
+        for (dst, src) in string.data.iter_mut().zip(s.as_bytes().slice_to(len).iter()) {
+            *dst = *src;
+        }
+        string.length = len as size_t;
+        string
+    }
+
     /// Get a `str` representation of this `AiString`
     pub fn as_str(&self) -> Option<&str> {
         str::from_utf8(self.data.slice_to((self.length) as uint))
@@ -340,6 +356,16 @@ impl Vector3D {
         self.z * other.z
     }
 
+    /// Cross product
+    #[inline]
+    pub fn cross(&self, other: &Vector3D) -> Vector3D {
+        Vector3D {
+            x: self.y * other.z - self.z * other.y,
+            y: self.z * other.x - self.x * other.z,
+            z: self.x * other.y - self.y * other.x,
+        }
+    }
+
     /// Calculate the norm of the vector
     #[inline]
     pub fn norm(&self) -> f32 {
@@ -357,6 +383,13 @@ impl Vector3D {
     pub fn normalize(&mut self) -> Vector3D {
         (*self) * self.rnorm()
     }
+
+    /// Linear interpolation between this vector (`t = 0`) and `other`
+    /// (`t = 1`)
+    #[inline]
+    pub fn lerp(&self, other: &Vector3D, t: f32) -> Vector3D {
+        *self + (*other - *self) * t
+    }
 }
 
 impl Add<Vector3D, Vector3D> for Vector3D {
@@ -427,6 +460,11 @@ impl Quaternion {
 }
 
 impl Quaternion {
+    /// Create the identity rotation
+    pub fn identity() -> Quaternion {
+        Quaternion { w: 1.0, x: 0.0, y: 0.0, z: 0.0 }
+    }
+
     /// Creates a rotation quaternion from the given matrix
     pub fn from_matrix(mat: &Matrix3x3) -> Quaternion {
         let mut quat: Quaternion = Quaternion::zero();
@@ -487,6 +525,44 @@ impl Quaternion {
     pub fn normalize(&mut self) -> Quaternion {
         (*self) * self.rnorm()
     }
+
+    /// The conjugate of the quaternion, which is the inverse rotation for
+    /// unit quaternions
+    #[inline]
+    pub fn conjugate(&self) -> Quaternion {
+        Quaternion { w: self.w, x: -self.x, y: -self.y, z: -self.z }
+    }
+
+    /// Rotate a vector by this quaternion, which must be normalized
+    pub fn rotate(&self, v: &Vector3D) -> Vector3D {
+        let p = Quaternion { w: 0.0, x: v.x, y: v.y, z: v.z };
+        let r = *self * p * self.conjugate();
+        Vector3D { x: r.x, y: r.y, z: r.z }
+    }
+
+    /// Spherical linear interpolation between this rotation (`t = 0`) and
+    /// `other` (`t = 1`).
+    ///
+    /// Always interpolates along the shortest path between the rotations.
+    pub fn slerp(&self, other: &Quaternion, t: f32) -> Quaternion {
+        let mut cos = self.dot(other);
+        let mut end = other.clone();
+        // q and -q are the same rotation, pick the one closer to self
+        if cos < 0.0 {
+            cos = -cos;
+            end = end * -1.0;
+        }
+
+        let (s0, s1) = if 1.0 - cos > 1e-6 {
+            let omega = cos.acos();
+            let sin = omega.sin();
+            (((1.0 - t) * omega).sin() / sin, (t * omega).sin() / sin)
+        } else {
+            // the rotations are almost the same, fall back to a lerp
+            (1.0 - t, t)
+        };
+        (*self * s0 + end * s1).normalize()
+    }
 }
 
 impl Add<Quaternion, Quaternion> for Quaternion {
@@ -511,6 +587,19 @@ impl Sub<Quaternion, Quaternion> for Quaternion {
     }
 }
 
+/// The Hamilton product. The result applies the rotation of `rhs` first,
+/// followed by the rotation of `self`.
+impl Mul<Quaternion, Quaternion> for Quaternion {
+    fn mul(&self, rhs: &Quaternion) -> Quaternion {
+        Quaternion {
+            w: self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+            x: self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            y: self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            z: self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+        }
+    }
+}
+
 impl Mul<f32, Quaternion> for Quaternion {
     fn mul(&self, rhs: &f32) -> Quaternion {
         Quaternion {
@@ -640,6 +729,72 @@ impl Matrix4x4 {
         copy
     }
 
+    /// Decompose a transformation matrix into its translation, rotation and
+    /// scaling components.
+    ///
+    /// The matrix must not contain shearing or a projection.
+    pub fn decompose(&self) -> (Vector3D, Quaternion, Vector3D) {
+        let position = Vector3D { x: self.a4, y: self.b4, z: self.c4 };
+
+        let columns = [
+            Vector3D { x: self.a1, y: self.b1, z: self.c1 },
+            Vector3D { x: self.a2, y: self.b2, z: self.c2 },
+            Vector3D { x: self.a3, y: self.b3, z: self.c3 },
+        ];
+        let mut scaling = Vector3D {
+            x: columns[0].norm(),
+            y: columns[1].norm(),
+            z: columns[2].norm(),
+        };
+
+        // a negative determinant means the matrix contains a reflection
+        let det = self.a1 * (self.b2 * self.c3 - self.b3 * self.c2) -
+                  self.a2 * (self.b1 * self.c3 - self.b3 * self.c1) +
+                  self.a3 * (self.b1 * self.c2 - self.b2 * self.c1);
+        if det < 0.0 {
+            scaling = scaling * -1.0;
+        }
+
+        let rotation = Matrix3x3 {
+            a1: self.a1 / scaling.x, a2: self.a2 / scaling.y, a3: self.a3 / scaling.z,
+            b1: self.b1 / scaling.x, b2: self.b2 / scaling.y, b3: self.b3 / scaling.z,
+            c1: self.c1 / scaling.x, c2: self.c2 / scaling.y, c3: self.c3 / scaling.z,
+        };
+
+        (position, Quaternion::from_matrix(&rotation), scaling)
+    }
+
+    /// Compose a transformation matrix from translation, rotation and scaling
+    /// components.
+    ///
+    /// The scaling is applied first, followed by the rotation and the
+    /// translation.
+    pub fn compose(position: &Vector3D,
+                   rotation: &Quaternion,
+                   scaling: &Vector3D) -> Matrix4x4 {
+        position.translation_matrix() *
+        rotation.rotation_matrix() *
+        scaling.scaling_matrix()
+    }
+
+    /// Transform a point by this matrix, including the translation
+    pub fn transform_point(&self, p: &Vector3D) -> Vector3D {
+        Vector3D {
+            x: self.a1 * p.x + self.a2 * p.y + self.a3 * p.z + self.a4,
+            y: self.b1 * p.x + self.b2 * p.y + self.b3 * p.z + self.b4,
+            z: self.c1 * p.x + self.c2 * p.y + self.c3 * p.z + self.c4,
+        }
+    }
+
+    /// Transform a direction by this matrix, ignoring the translation
+    pub fn transform_vector(&self, v: &Vector3D) -> Vector3D {
+        Vector3D {
+            x: self.a1 * v.x + self.a2 * v.y + self.a3 * v.z,
+            y: self.b1 * v.x + self.b2 * v.y + self.b3 * v.z,
+            z: self.c1 * v.x + self.c2 * v.y + self.c3 * v.z,
+        }
+    }
+
     /// Compute the inverse of a 4x4 matrix
     pub fn inverse(&self) -> Matrix4x4 {
         let inv = m::mat4_inv(self.to_array());
@@ -662,11 +817,219 @@ impl Mul<Matrix4x4, Matrix4x4> for Matrix4x4 {
     }
 }
 
-// #[cfg(test)]
-// mod test {
-//     use super::Matrix4x4;
-//     #[test]
-//     fn test_inv() {
-//         Matrix4x4::identity() * 
-//     }
-// }
+/// An axis-aligned bounding box.
+///
+/// An empty box has a minimum larger than its maximum.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Aabb {
+    /// The corner with the smallest coordinates
+    pub min: Vector3D,
+    /// The corner with the largest coordinates
+    pub max: Vector3D,
+}
+
+impl Aabb {
+    /// Create a box containing nothing
+    pub fn empty() -> Aabb {
+        let inf: f32 = Float::infinity();
+        Aabb {
+            min: Vector3D { x: inf, y: inf, z: inf },
+            max: Vector3D { x: -inf, y: -inf, z: -inf },
+        }
+    }
+
+    /// Create the smallest box containing all `points`
+    pub fn from_points(points: &[Vector3D]) -> Aabb {
+        let mut aabb = Aabb::empty();
+        for p in points.iter() {
+            aabb.add_point(p);
+        }
+        aabb
+    }
+
+    /// Check if the box contains nothing
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    /// Grow the box to contain `p`
+    pub fn add_point(&mut self, p: &Vector3D) {
+        self.min = Vector3D {
+            x: self.min.x.min(p.x),
+            y: self.min.y.min(p.y),
+            z: self.min.z.min(p.z),
+        };
+        self.max = Vector3D {
+            x: self.max.x.max(p.x),
+            y: self.max.y.max(p.y),
+            z: self.max.z.max(p.z),
+        };
+    }
+
+    /// The smallest box containing this box and `other`
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        let mut aabb = self.clone();
+        if !other.is_empty() {
+            aabb.add_point(&other.min);
+            aabb.add_point(&other.max);
+        }
+        aabb
+    }
+
+    /// The center of the box
+    pub fn center(&self) -> Vector3D {
+        (self.min + self.max) * 0.5
+    }
+
+    /// The size of the box along each axis
+    pub fn size(&self) -> Vector3D {
+        self.max - self.min
+    }
+
+    /// The smallest box containing this box transformed by `transformation`
+    pub fn transform(&self, transformation: &Matrix4x4) -> Aabb {
+        if self.is_empty() {
+            return self.clone()
+        }
+        let mut aabb = Aabb::empty();
+        for i in range(0u, 8) {
+            let corner = Vector3D {
+                x: if i & 1 == 0 { self.min.x } else { self.max.x },
+                y: if i & 2 == 0 { self.min.y } else { self.max.y },
+                z: if i & 4 == 0 { self.min.z } else { self.max.z },
+            };
+            aabb.add_point(&transformation.transform_point(&corner));
+        }
+        aabb
+    }
+}
+
+/// A sphere enclosing a set of points.
+#[deriving(Clone, PartialEq, Show)]
+pub struct BoundingSphere {
+    /// The center of the sphere
+    pub center: Vector3D,
+    /// The radius of the sphere, negative for a sphere containing nothing
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// Create a sphere containing nothing
+    pub fn empty() -> BoundingSphere {
+        BoundingSphere {
+            center: Vector3D { x: 0.0, y: 0.0, z: 0.0 },
+            radius: -1.0,
+        }
+    }
+
+    /// Create a sphere containing all `points`.
+    ///
+    /// Uses Ritter's algorithm, so the sphere is close to but not
+    /// necessarily the smallest one.
+    pub fn from_points(points: &[Vector3D]) -> BoundingSphere {
+        if points.is_empty() {
+            return BoundingSphere::empty()
+        }
+
+        // start with the points farthest from an arbitrary point and from
+        // each other
+        let farthest = |from: &Vector3D| -> Vector3D {
+            let mut best = points[0];
+            let mut best_dist = -1.0;
+            for p in points.iter() {
+                let d = *p - *from;
+                let dist = d.dot(&d);
+                if dist > best_dist {
+                    best = *p;
+                    best_dist = dist;
+                }
+            }
+            best
+        };
+        let a = farthest(&points[0]);
+        let b = farthest(&a);
+
+        let mut sphere = BoundingSphere {
+            center: (a + b) * 0.5,
+            radius: (b - a).norm() * 0.5,
+        };
+        for p in points.iter() {
+            sphere.add_point(p);
+        }
+        sphere
+    }
+
+    /// Check if the sphere contains nothing
+    pub fn is_empty(&self) -> bool {
+        self.radius < 0.0
+    }
+
+    /// Grow the sphere to contain `p`
+    pub fn add_point(&mut self, p: &Vector3D) {
+        if self.is_empty() {
+            self.center = *p;
+            self.radius = 0.0;
+            return
+        }
+        let d = *p - self.center;
+        let dist = d.norm();
+        if dist > self.radius {
+            // move the center towards p, so the far side stays inside
+            let radius = (self.radius + dist) * 0.5;
+            self.center = self.center + d * ((radius - self.radius) / dist);
+            self.radius = radius;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use test_util::vec3;
+    use super::{Matrix4x4, Aabb, BoundingSphere};
+
+    #[test]
+    fn test_aabb() {
+        let empty = Aabb::empty();
+        assert!(empty.is_empty());
+        assert!(Aabb::from_points(&[]).is_empty());
+        assert!(empty.transform(&Matrix4x4::identity()).is_empty());
+
+        let a = Aabb::from_points(&[vec3(0.0, 1.0, 2.0), vec3(1.0, -1.0, 0.0)]);
+        assert_eq!(a, Aabb { min: vec3(0.0, -1.0, 0.0), max: vec3(1.0, 1.0, 2.0) });
+        assert_eq!(a.center(), vec3(0.5, 0.0, 1.0));
+        assert_eq!(a.size(), vec3(1.0, 2.0, 2.0));
+
+        // the empty box is the identity of the union
+        assert_eq!(a.union(&empty), a);
+        assert_eq!(empty.union(&a), a);
+
+        let b = Aabb { min: vec3(2.0, 0.0, -3.0), max: vec3(3.0, 0.5, -1.0) };
+        let both = Aabb { min: vec3(0.0, -1.0, -3.0), max: vec3(3.0, 1.0, 2.0) };
+        assert_eq!(a.union(&b), both);
+        assert_eq!(a.union(&b), b.union(&a));
+    }
+
+    #[test]
+    fn test_bounding_sphere() {
+        assert!(BoundingSphere::from_points(&[]).is_empty());
+        let single = BoundingSphere::from_points(&[vec3(1.0, 2.0, 3.0)]);
+        assert_eq!(single, BoundingSphere { center: vec3(1.0, 2.0, 3.0), radius: 0.0 });
+
+        // a pseudo random cloud, which isn't symmetric around any point
+        let mut seed = 12345u32;
+        let mut points = Vec::new();
+        for _ in range(0u, 200) {
+            let mut coords = [0.0f32, ..3];
+            for c in coords.iter_mut() {
+                seed = seed * 1103515245 + 12345;
+                *c = ((seed >> 16) & 0x7fff) as f32 / 32767.0 * 10.0 - 5.0;
+            }
+            points.push(vec3(coords[0], coords[1] * 0.5, coords[2] * 2.0));
+        }
+
+        let sphere = BoundingSphere::from_points(points.as_slice());
+        for p in points.iter() {
+            assert!((*p - sphere.center).norm() <= sphere.radius * (1.0 + 1e-5));
+        }
+    }
+}
@@ -1,6 +1,9 @@
+use std::collections::HashMap;
 use std::raw;
 use std::mem;
 
+use types::Vector3D;
+
 #[inline(always)]
 pub unsafe fn ptr_ptr_to_slice<'a, T>(ptr: *mut*mut T, len: uint) -> &'a [&'a T] {
     let raw_slice : raw::Slice<&T> = raw::Slice {
@@ -18,3 +21,32 @@ pub unsafe fn ptr_to_slice<'a, T>(ptr: *mut T, len: uint) -> &'a [T] {
     };
     mem::transmute(raw_slice)
 }
+
+/// The bits of a float, with both zeros mapped to the same value
+#[inline]
+pub fn float_bits(f: f32) -> u32 {
+    let f = if f == 0.0 { 0.0 } else { f };
+    unsafe { mem::transmute(f) }
+}
+
+/// Number the distinct positions of `vertices` in the order they first
+/// appear, treating only identical positions as the same.
+///
+/// Returns the position id of each vertex and the number of positions.
+pub fn weld_exact(vertices: &[Vector3D]) -> (Vec<u32>, uint) {
+    let mut first: HashMap<(u32, u32, u32), u32> = HashMap::new();
+    let mut ids = Vec::with_capacity(vertices.len());
+    for v in vertices.iter() {
+        let key = (float_bits(v.x), float_bits(v.y), float_bits(v.z));
+        let next = first.len() as u32;
+        let id = match first.get(&key) {
+            Some(id) => *id,
+            None => next,
+        };
+        if id == next {
+            let _ = first.insert(key, id);
+        }
+        ids.push(id);
+    }
+    (ids, first.len())
+}